@@ -0,0 +1,2166 @@
+use rig::{
+    agent::{Agent, AgentBuilder},
+    completion::{self, Completion, CompletionError, PromptError},
+    message::{self, AssistantContent, Message, ToolCall, ToolFunction, ToolResultContent, UserContent},
+    OneOrMany,
+};
+use rig::tool::Tool;
+use futures_util::future::join_all;
+use serde_json::json;
+use std::error::Error;
+use std::fmt;
+use std::io::{BufRead, IsTerminal};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::config::CodeReviewConfig;
+use crate::status::{RunState, StatusFile};
+use crate::tools::code_review::{display_name, CodeReviewTool};
+use crate::tools::events::EventSink;
+use crate::tools::multi_gateway::MultiGatewayReviewer;
+
+/// Characters of reviewer quotes in rejection feedback, shared by the
+/// dissenting reviewers, by default
+pub const DEFAULT_FEEDBACK_BUDGET: usize = 4500;
+
+// Longest quote of a single reviewer in a summarized tool result
+const MAX_SUMMARY_CONCERN_LEN: usize = 400;
+
+// Rounds in a row whose response had nothing to act on before the turn fails
+const MAX_EMPTY_RESPONSES: usize = 2;
+
+/// Generate-and-review rounds a turn gets before giving up, by default
+pub const DEFAULT_MAX_ITERATIONS: usize = 5;
+
+// Phrases that open a refusal rather than an answer
+const REFUSAL_PHRASES: [&str; 8] = [
+    "i can't help",
+    "i cannot help",
+    "i can't assist",
+    "i cannot assist",
+    "i'm unable to",
+    "i am unable to",
+    "i won't be able to",
+    "i'm sorry, but i",
+];
+
+// A refusal mentions one of the phrases and contains no code block. Code
+// that merely quotes such a phrase in a string or comment is fenced, so it
+// doesn't count.
+fn is_refusal(text: &str, phrases: &[String]) -> bool {
+    let lower = text.to_lowercase().replace('’', "'");
+    !text.contains("```") && phrases.iter().any(|phrase| lower.contains(phrase.as_str()))
+}
+
+#[derive(Debug)]
+pub enum MultiTurnError {
+    PromptError(PromptError),
+    /// The session's estimated token usage reached its budget
+    BudgetExhausted { used: u64, limit: u64 },
+    /// The model declined to write code; holds its response
+    Refused(String),
+    /// The prompt had no text; the model was not called
+    EmptyPrompt,
+    /// The model kept answering without text or a tool call
+    EmptyResponse { rounds: usize },
+    /// Every allowed round was spent without the code passing review; holds
+    /// the last code reviewed and the feedback it got
+    IterationsExhausted { rounds: usize, code: String, feedback: String },
+    /// The turn ended without code to return; holds the reason
+    NoCode(String),
+}
+
+impl fmt::Display for MultiTurnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultiTurnError::PromptError(e) => write!(f, "{}", e),
+            MultiTurnError::BudgetExhausted { used, limit } => write!(
+                f,
+                "Token budget exhausted: used ~{} of {} estimated tokens, refusing new generations",
+                used, limit
+            ),
+            MultiTurnError::Refused(response) => write!(f, "The model refused to generate code: {}", response),
+            MultiTurnError::EmptyPrompt => write!(f, "Empty prompt"),
+            MultiTurnError::EmptyResponse { rounds } => {
+                write!(f, "The model returned no text or tool call {} times in a row", rounds)
+            }
+            MultiTurnError::IterationsExhausted { rounds, .. } => {
+                write!(f, "Review did not pass after {} rounds", rounds)
+            }
+            MultiTurnError::NoCode(reason) => write!(f, "No code to return: {}", reason),
+        }
+    }
+}
+
+impl Error for MultiTurnError {}
+
+impl From<PromptError> for MultiTurnError {
+    fn from(e: PromptError) -> Self {
+        MultiTurnError::PromptError(e)
+    }
+}
+
+impl From<CompletionError> for MultiTurnError {
+    fn from(e: CompletionError) -> Self {
+        MultiTurnError::PromptError(PromptError::CompletionError(e))
+    }
+}
+
+// Rough token count at about four characters per token. This rig version
+// doesn't report usage, so budgets are estimates.
+fn estimate_tokens(chars: usize) -> u64 {
+    (chars as u64).div_ceil(4)
+}
+
+// Estimated tokens of one completion: the history and prompt sent, and the
+// response received
+fn exchange_tokens(history: &[Message], prompt: &Message, choice: &OneOrMany<AssistantContent>) -> u64 {
+    let sent_chars = serde_json::to_string(history).map(|json| json.len()).unwrap_or(0)
+        + serde_json::to_string(prompt).map(|json| json.len()).unwrap_or(0);
+    let received_chars: usize = choice
+        .iter()
+        .map(|content| match content {
+            AssistantContent::Text(text) => text.text.len(),
+            AssistantContent::ToolCall(call) => call.function.name.len() + call.function.arguments.to_string().len(),
+        })
+        .sum();
+    estimate_tokens(sent_chars + received_chars)
+}
+
+// Spread candidate temperatures evenly from 0.2 to 1.0
+fn candidate_temperature(index: usize, count: usize) -> f64 {
+    if count <= 1 {
+        return 0.2;
+    }
+    0.2 + 0.8 * index as f64 / (count - 1) as f64
+}
+
+// Review arguments for a candidate: its review tool call, or its text answer
+// as the code. `None` if it produced neither.
+fn candidate_submission(choice: &OneOrMany<AssistantContent>, user_input: &str, correlation_id: &str) -> Option<serde_json::Value> {
+    choice.iter().find_map(|content| match content {
+        AssistantContent::ToolCall(call) if call.function.name == CodeReviewTool::NAME => {
+            let mut arguments = call.function.arguments.clone();
+            if let Some(code) = arguments.get("code").and_then(|v| v.as_str()) {
+                let code = strip_reasoning(code, correlation_id).to_string();
+                arguments["code"] = json!(code);
+            }
+            Some(arguments)
+        }
+        AssistantContent::Text(text) if !text.text.trim().is_empty() => Some(json!({
+            "user_input": user_input,
+            "code": strip_reasoning(&text.text, correlation_id),
+        })),
+        _ => None,
+    })
+}
+
+// POSITIVE votes in a review result
+fn positive_votes(review: &serde_json::Value) -> usize {
+    review
+        .pointer("/magi_state/agents")
+        .and_then(|v| v.as_array())
+        .map_or(0, |agents| {
+            agents
+                .iter()
+                .filter(|agent| agent.get("decision").and_then(|v| v.as_str()) == Some("POSITIVE"))
+                .count()
+        })
+}
+
+// Attach the turn's correlation id to the tool arguments so it reaches the gateway
+fn with_correlation_id(mut arguments: serde_json::Value, correlation_id: &str) -> serde_json::Value {
+    if let Some(object) = arguments.as_object_mut() {
+        object.insert("correlation_id".to_string(), json!(correlation_id));
+    }
+    arguments
+}
+
+// Whether a review result was rejected only because of transient agent errors
+fn is_retryable(tool_result: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(tool_result)
+        .ok()
+        .and_then(|result| result.get("retryable").and_then(|v| v.as_bool()))
+        .unwrap_or(false)
+}
+
+// Let the operator settle a close-call review. The panel's decision stands
+// when the review isn't a close call, stdin isn't a terminal, or the answer
+// isn't understood; otherwise the operator's decision is recorded in the result.
+fn apply_human_tiebreak(tool_result: String) -> String {
+    if !std::io::stdin().is_terminal() {
+        return tool_result;
+    }
+    human_tiebreak(tool_result, &mut std::io::stdin().lock())
+}
+
+// `apply_human_tiebreak` with the operator's answer read from `input`
+fn human_tiebreak(tool_result: String, input: &mut impl BufRead) -> String {
+    let Ok(mut review) = serde_json::from_str::<serde_json::Value>(&tool_result) else {
+        return tool_result;
+    };
+    if !review.get("close_call").and_then(|v| v.as_bool()).unwrap_or(false) {
+        return tool_result;
+    }
+
+    eprintln!("Close call, reviewer positions:");
+    if let Some(agents) = review.pointer("/magi_state/agents").and_then(|v| v.as_array()) {
+        for agent in agents {
+            eprintln!(
+                "  {}: {}",
+                agent.get("name").and_then(|v| v.as_str()).unwrap_or("?"),
+                agent.get("decision").and_then(|v| v.as_str()).unwrap_or("PENDING")
+            );
+        }
+    }
+    eprint!("Panel result is {}. Approve the code? [y/n] ", review["result"].as_str().unwrap_or("?"));
+
+    let mut answer = String::new();
+    if input.read_line(&mut answer).is_err() {
+        return tool_result;
+    }
+    let approved = match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => return tool_result,
+    };
+
+    let decision = if approved { "POSITIVE" } else { "NEGATIVE" };
+    review["passed"] = json!(approved);
+    review["result"] = json!(decision);
+    review["human_decision"] = json!(decision);
+    review.to_string()
+}
+
+// Let the operator accept or overrule an approval. A rejection carries the
+// operator's feedback for the next round. Results that didn't pass, and runs
+// without a terminal on stdin, are left as they are.
+fn apply_review_gate(tool_result: String) -> String {
+    if !std::io::stdin().is_terminal() {
+        return tool_result;
+    }
+    review_gate(tool_result, &mut std::io::stdin().lock())
+}
+
+// `apply_review_gate` with the operator's answers read from `input`
+fn review_gate(tool_result: String, input: &mut impl BufRead) -> String {
+    let Ok(mut review) = serde_json::from_str::<serde_json::Value>(&tool_result) else {
+        return tool_result;
+    };
+    if !review.get("passed").and_then(|v| v.as_bool()).unwrap_or(false) {
+        return tool_result;
+    }
+
+    if let Some(code) = review.get("code").and_then(|v| v.as_str()) {
+        eprintln!("The panel approved:\n{}", code);
+    }
+    eprint!("Accept the approved code? [y/n] ");
+
+    let mut answer = String::new();
+    if input.read_line(&mut answer).is_err() {
+        return tool_result;
+    }
+    match answer.trim().to_lowercase().as_str() {
+        "n" | "no" => {}
+        _ => {
+            review["operator_decision"] = json!("accepted");
+            return review.to_string();
+        }
+    }
+
+    eprint!("What should change? ");
+    let mut feedback = String::new();
+    input.read_line(&mut feedback).ok();
+
+    review["passed"] = json!(false);
+    review["result"] = json!("NEGATIVE");
+    review["operator_decision"] = json!("rejected");
+    review["operator_feedback"] = json!(feedback.trim());
+    review.to_string()
+}
+
+// Whether a review result says the code passed
+fn review_passed(tool_result: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(tool_result)
+        .ok()
+        .and_then(|result| result.get("passed").and_then(|v| v.as_bool()))
+        .unwrap_or(false)
+}
+
+// Turn a rejected review into a user message quoting each dissenting
+// reviewer, or the compiler output when the compile check failed
+fn rejection_feedback(review: &serde_json::Value, budget: usize) -> Message {
+    let mut text = String::from("The code was rejected in review.\n");
+
+    if let Some(errors) = review.get("compile_errors").and_then(|v| v.as_str()) {
+        text.push_str(&format!("\nIt failed to compile:\n{}\n", errors));
+    } else if let Some(veto) = review.get("veto").and_then(|v| v.as_str()) {
+        // The panel approved, but a reviewer flagged a critical security issue
+        text.push_str(&format!("\nA reviewer flagged a critical security issue:\n{}\n", veto));
+    } else if review.get("operator_decision").and_then(|v| v.as_str()) == Some("rejected") {
+        // The panel approved; the objection is the operator's
+        let feedback = review
+            .get("operator_feedback")
+            .and_then(|v| v.as_str())
+            .filter(|feedback| !feedback.is_empty())
+            .unwrap_or("(no details given)");
+        text.push_str(&format!("\nThe operator objected:\n{}\n", feedback));
+    } else {
+        // Split evenly, so one long review can't crowd out the others
+        let dissent = dissent(review, usize::MAX);
+        let limit = budget / dissent.len().max(1);
+        for (name, concern) in dissent {
+            let concern: String = concern.chars().take(limit).collect();
+            text.push_str(&format!("\n{} objected:\n{}\n", display_name(&name), concern));
+        }
+    }
+
+    // The rejection was settled before every reviewer finished
+    if let Some(pending) = review.get("pending_agents").and_then(|v| v.as_array()).filter(|pending| !pending.is_empty()) {
+        let names: Vec<String> = pending.iter().filter_map(|v| v.as_str()).map(display_name).collect();
+        text.push_str(&format!("\n({} had not finished reviewing; their feedback is not included.)\n", names.join(", ")));
+    }
+
+    // An agent that errored cast no vote, so it has no concern to quote
+    let errored = errored_agents(review);
+    if !errored.is_empty() {
+        let names: Vec<String> = errored.iter().map(|name| display_name(name)).collect();
+        text.push_str(&format!("\n({} errored and did not review.)\n", names.join(", ")));
+    }
+
+    text.push_str("\nPlease improve the code to address these concerns.");
+    Message::User {
+        content: OneOrMany::one(UserContent::Text(message::Text { text })),
+    }
+}
+
+// Each NEGATIVE reviewer's name and content, the content cut to `limit`
+// characters. Agents that errored are counted NEGATIVE but raised no
+// concern, so they're left out.
+fn dissent(review: &serde_json::Value, limit: usize) -> Vec<(String, String)> {
+    review_agents(review)
+        .iter()
+        .filter(|agent| agent.get("decision").and_then(|v| v.as_str()) == Some("NEGATIVE") && !is_errored(agent))
+        .map(|agent| {
+            let name = agent.get("name").and_then(|v| v.as_str()).unwrap_or("reviewer");
+            let concern = agent
+                .get("content")
+                .and_then(|v| v.as_str())
+                .map(str::trim)
+                .filter(|content| !content.is_empty())
+                .unwrap_or("(no details given)");
+            (name.to_string(), concern.chars().take(limit).collect())
+        })
+        .collect()
+}
+
+// The names of the agents that errored instead of reviewing
+fn errored_agents(review: &serde_json::Value) -> Vec<String> {
+    review_agents(review)
+        .iter()
+        .filter(|agent| is_errored(agent))
+        .filter_map(|agent| agent.get("name").and_then(|v| v.as_str()).map(str::to_string))
+        .collect()
+}
+
+fn review_agents(review: &serde_json::Value) -> &[serde_json::Value] {
+    review
+        .pointer("/magi_state/agents")
+        .and_then(|v| v.as_array())
+        .map_or(&[], Vec::as_slice)
+}
+
+fn is_errored(agent: &serde_json::Value) -> bool {
+    agent.get("errored").and_then(|v| v.as_bool()).unwrap_or(false)
+}
+
+/// A compact form of a review for the tool result sent back to the model:
+/// the decision, each dissenting reviewer with the start of its concern, and
+/// the compile errors, veto, unfinished and errored reviewers when there are
+/// any. The code and the per-agent transcripts are left out.
+pub fn summarize_review(review: &serde_json::Value) -> String {
+    let mut summary = json!({
+        "passed": review.get("passed").and_then(|v| v.as_bool()).unwrap_or(false),
+        "result": review.get("result").cloned().unwrap_or(serde_json::Value::Null),
+        "dissent": dissent(review, MAX_SUMMARY_CONCERN_LEN)
+            .into_iter()
+            .map(|(agent, concern)| json!({ "agent": agent, "concern": concern }))
+            .collect::<Vec<_>>(),
+    });
+
+    for key in ["compile_errors", "veto", "pending_agents", "operator_feedback"] {
+        if let Some(value) = review.get(key).filter(|value| !value.is_null()) {
+            summary[key] = value.clone();
+        }
+    }
+    let errored = errored_agents(review);
+    if !errored.is_empty() {
+        summary["errored_agents"] = json!(errored);
+    }
+    summary.to_string()
+}
+
+// Split a reasoning preamble off the code. Recognizes a `THOUGHTS:` section
+// ending at a `CODE:` line, or prose before the first code fence. Text with
+// neither is all code.
+fn split_reasoning(text: &str) -> (Option<&str>, &str) {
+    let trimmed = text.trim_start();
+
+    if trimmed.get(..9).is_some_and(|head| head.eq_ignore_ascii_case("THOUGHTS:")) {
+        let mut offset = 0;
+        for line in trimmed.split_inclusive('\n') {
+            let marker = line.trim_start();
+            if marker.get(..5).is_some_and(|head| head.eq_ignore_ascii_case("CODE:")) {
+                let reasoning = trimmed[9..offset].trim();
+                let code_start = offset + (line.len() - marker.len()) + 5;
+                return (Some(reasoning).filter(|r| !r.is_empty()), trimmed[code_start..].trim());
+            }
+            offset += line.len();
+        }
+    }
+
+    if let Some(fence) = trimmed.find("```") {
+        let reasoning = trimmed[..fence].trim();
+        if !reasoning.is_empty() {
+            return (Some(reasoning), &trimmed[fence..]);
+        }
+    }
+
+    (None, text)
+}
+
+// Log and drop the reasoning preamble of model output headed for review
+fn strip_reasoning<'a>(text: &'a str, correlation_id: &str) -> &'a str {
+    let (reasoning, code) = split_reasoning(text);
+    if let Some(reasoning) = reasoning {
+        tracing::debug!(target: "rig-magi", correlation_id = %correlation_id,
+            "Model reasoning: {}",
+            reasoning
+        );
+    }
+    code
+}
+
+// A user prompt made only of blank text
+fn is_empty_prompt(prompt: &Message) -> bool {
+    match prompt {
+        Message::User { content } => content
+            .iter()
+            .all(|content| matches!(content, UserContent::Text(text) if text.text.trim().is_empty())),
+        _ => false,
+    }
+}
+
+fn is_tool_result(message: &Message) -> bool {
+    match message {
+        Message::User { content } => content
+            .iter()
+            .any(|content| matches!(content, UserContent::ToolResult(_))),
+        _ => false,
+    }
+}
+
+// The text of a user prompt, used as the review's user_input
+fn prompt_text(prompt: &Message) -> String {
+    match prompt {
+        Message::User { content } => content
+            .iter()
+            .filter_map(|content| match content {
+                UserContent::Text(text) => Some(text.text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+// Instructions for the code generation model
+const CODE_AGENT_PREAMBLE: &str = "You are a code generation assistant with access to the code_review tool.\
+        \
+        IMPORTANT: You MUST follow this EXACT workflow:\
+        1. First, generate the requested code.\
+        2. Then, IMMEDIATELY call the code_review tool with these parameters:\
+           - user_input: user's first message\
+           - code: your generated code\
+        3. Wait for the review results.\
+        4. If approved, output the code.\
+        5. If rejected, improve and try again.\
+        \
+        DO NOT output any explanations or comments.\
+        DO NOT skip the code review step.\
+        ALWAYS use the code_review tool after generating ANY code.\
+        \
+        Example tool usage:\
+        {\"name\": \"code_review\",\
+         \"arguments\": {\
+            \"user_input\": \"hello world program in python\",\
+            \"code\": \"def add(a, b): return a + b\"\
+         }\
+        }\
+        \
+        Type 'exit' to quit.";
+
+/// The code generation agent for a provider's agent builder: the workflow
+/// preamble and the review tool, against several gateways when
+/// CODE_REVIEW_SERVER_URLS is set. `events` receives the progress of each
+/// review on a single gateway.
+///
+/// The loop options (`with_*`) keep their defaults; set them on the result.
+pub fn build_code_agent<M: rig::completion::CompletionModel>(
+    builder: AgentBuilder<M>,
+    review_config: &CodeReviewConfig,
+    events: Option<EventSink>,
+) -> MultiTurnAgent<M> {
+    let builder = builder.preamble(CODE_AGENT_PREAMBLE);
+    let agent = match MultiGatewayReviewer::from_env(review_config) {
+        Some(reviewer) => builder.tool(reviewer).build(),
+        None => {
+            let mut tool = CodeReviewTool::with_config(review_config.clone());
+            if let Some(sink) = events {
+                tool = tool.with_events(sink);
+            }
+            builder.tool(tool).build()
+        }
+    };
+    MultiTurnAgent::new(agent)
+}
+
+/// Drives the generate, review and improve loop for one prompt at a time
+pub struct MultiTurnAgent<M: rig::completion::CompletionModel> {
+    agent: Agent<M>,
+    chat_history: Vec<completion::Message>,
+    // Round of its turn each `chat_history` message was added in, or 0 for
+    // messages from the caller
+    history_rounds: Vec<usize>,
+    // Extra attempts for reviews rejected only by transient gateway errors
+    max_review_retries: usize,
+    // Ask the operator to settle close-call reviews
+    human_tiebreak: bool,
+    // Ask the operator to confirm approved reviews
+    review_gate: bool,
+    // Review text the model returns without calling the tool
+    review_direct_text: bool,
+    // Most messages kept in the history sent to the model
+    max_history: Option<usize>,
+    // Estimated tokens allowed across all turns
+    token_budget: Option<u64>,
+    // Estimated tokens sent and received so far
+    tokens_used: u64,
+    // Return after the first review, whatever its verdict
+    single_pass: bool,
+    // Parsed result of the latest review in the current turn
+    last_review: Option<serde_json::Value>,
+    // Lowercase phrases that mark a text answer as a refusal
+    refusal_phrases: Vec<String>,
+    // Instruction for one more pass over approved code, e.g. adding docstrings
+    post_approval_prompt: Option<String>,
+    // Review the post-approval pass's output before accepting it
+    post_approval_review: bool,
+    // Keep and log each raw completion response
+    debug_completions: bool,
+    // Serialized `choice` of the latest completion in the current turn
+    last_completion: Option<serde_json::Value>,
+    // Generate-and-review rounds run in the current turn
+    turn_rounds: usize,
+    // Most rounds per turn; 0 is unlimited
+    max_iterations: usize,
+    // Characters of reviewer quotes in the feedback for the next round
+    feedback_budget: usize,
+    // Told when reviews start and end
+    status: Option<Arc<StatusFile>>,
+    // Send the model `summarize_review` of each review instead of the full output
+    summarize_tool_results: bool,
+}
+
+impl<M: rig::completion::CompletionModel> MultiTurnAgent<M> {
+    pub fn new(agent: Agent<M>) -> Self {
+        Self {
+            agent,
+            chat_history: Vec::new(),
+            max_review_retries: 2,
+            human_tiebreak: false,
+            review_gate: false,
+            review_direct_text: false,
+            max_history: None,
+            token_budget: None,
+            tokens_used: 0,
+            single_pass: false,
+            last_review: None,
+            refusal_phrases: REFUSAL_PHRASES.iter().map(|phrase| phrase.to_string()).collect(),
+            post_approval_prompt: None,
+            post_approval_review: false,
+            debug_completions: false,
+            last_completion: None,
+            turn_rounds: 0,
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            feedback_budget: DEFAULT_FEEDBACK_BUDGET,
+            history_rounds: Vec::new(),
+            status: None,
+            summarize_tool_results: false,
+        }
+    }
+
+    /// Ask the operator to settle close-call reviews
+    pub fn with_human_tiebreak(mut self, enabled: bool) -> Self {
+        self.human_tiebreak = enabled;
+        self
+    }
+
+    /// Ask the operator to accept or reject each approved review
+    pub fn with_review_gate(mut self, enabled: bool) -> Self {
+        self.review_gate = enabled;
+        self
+    }
+
+    /// Messages sent as context with the next prompt
+    pub fn history(&self) -> &[Message] {
+        &self.chat_history
+    }
+
+    /// Append a message to the history, e.g. to seed prior context
+    pub fn push_message(&mut self, message: Message) {
+        self.chat_history.push(message);
+        self.history_rounds.push(0);
+    }
+
+    /// Replace the whole history
+    pub fn set_history(&mut self, history: Vec<Message>) {
+        self.history_rounds = vec![0; history.len()];
+        self.chat_history = history;
+    }
+
+    /// Keep only the first `len` messages, e.g. to roll back a failed turn
+    pub fn truncate_history(&mut self, len: usize) {
+        self.chat_history.truncate(len);
+        self.history_rounds.truncate(len);
+    }
+
+    pub fn clear_history(&mut self) {
+        self.chat_history.clear();
+        self.history_rounds.clear();
+    }
+
+    /// The round of its turn each `history()` message was added in, index
+    /// for index: the prompt and everything up to the first review are round
+    /// 1, the feedback and what follows it round 2, and so on. Messages from
+    /// `push_message`, `set_history` or a loaded session are round 0.
+    pub fn history_rounds(&self) -> &[usize] {
+        &self.history_rounds
+    }
+
+    // Add a message of the current turn, tagged with its round
+    fn push_turn_message(&mut self, message: Message) {
+        self.chat_history.push(message);
+        self.history_rounds.push(self.turn_rounds.max(1));
+    }
+
+    /// Send direct text answers through the review instead of accepting them
+    pub fn with_review_direct_text(mut self, enabled: bool) -> Self {
+        self.review_direct_text = enabled;
+        self
+    }
+
+    /// Answer the model's review calls with `summarize_review` of the output
+    /// instead of the full JSON, which repeats the code and every transcript.
+    /// `last_review()` still holds the full output.
+    pub fn with_summarized_tool_results(mut self, enabled: bool) -> Self {
+        self.summarize_tool_results = enabled;
+        self
+    }
+
+    /// Keep only the last `max` messages of history (the preamble is always sent)
+    pub fn with_max_history(mut self, max: Option<usize>) -> Self {
+        self.max_history = max;
+        self
+    }
+
+    /// Give up on a turn after this many generate-and-review rounds, 0 for
+    /// no limit
+    pub fn with_max_iterations(mut self, max: usize) -> Self {
+        self.max_iterations = max;
+        self
+    }
+
+    /// Quote at most this many characters of the dissenting reviewers in
+    /// the feedback after a rejection, split evenly between them
+    pub fn with_feedback_budget(mut self, budget: usize) -> Self {
+        self.feedback_budget = budget;
+        self
+    }
+
+    /// Refuse new generations once about this many tokens have been used
+    pub fn with_token_budget(mut self, budget: Option<u64>) -> Self {
+        self.token_budget = budget;
+        self
+    }
+
+    /// Generate and review once, returning the code even if the review rejects it
+    pub fn with_single_pass(mut self, enabled: bool) -> Self {
+        self.single_pass = enabled;
+        self
+    }
+
+    /// Replace the phrases that mark a text answer as a refusal (matched
+    /// case-insensitively); an empty list turns detection off
+    pub fn with_refusal_phrases(mut self, phrases: Vec<String>) -> Self {
+        self.refusal_phrases = phrases.into_iter().map(|phrase| phrase.to_lowercase()).collect();
+        self
+    }
+
+    /// After approval, send the code back to the model with this instruction
+    /// (e.g. "add docstrings") and return its answer instead
+    pub fn with_post_approval_prompt(mut self, prompt: Option<String>) -> Self {
+        self.post_approval_prompt = prompt;
+        self
+    }
+
+    /// Review the post-approval pass's output, keeping the approved code if
+    /// the panel rejects it
+    pub fn with_post_approval_review(mut self, enabled: bool) -> Self {
+        self.post_approval_review = enabled;
+        self
+    }
+
+    /// Keep each raw completion response for `last_completion()` and log it
+    /// at debug level
+    pub fn with_debug_completions(mut self, enabled: bool) -> Self {
+        self.debug_completions = enabled;
+        self
+    }
+
+    /// The full `choice` of the last turn's latest completion, as JSON, when
+    /// `with_debug_completions` is on
+    pub fn last_completion(&self) -> Option<&serde_json::Value> {
+        self.last_completion.as_ref()
+    }
+
+    /// Mark the status file as reviewing while the panel reviews, and as
+    /// generating again afterwards
+    pub fn with_status_file(mut self, status: Option<Arc<StatusFile>>) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Generate-and-review rounds the last `multi_turn_prompt` ran (always 1
+    /// for `candidates_prompt`), e.g. for `ReviewReport::with_iterations`
+    pub fn turn_rounds(&self) -> usize {
+        self.turn_rounds
+    }
+
+    /// The latest review of the last turn, if its code was reviewed
+    pub fn last_review(&self) -> Option<&serde_json::Value> {
+        self.last_review.as_ref()
+    }
+
+    /// Estimated tokens used so far
+    pub fn tokens_used(&self) -> u64 {
+        self.tokens_used
+    }
+
+    /// Estimated tokens left before the budget is exhausted
+    pub fn remaining_budget(&self) -> Option<u64> {
+        self.token_budget.map(|limit| limit.saturating_sub(self.tokens_used))
+    }
+
+    // Keep the response as the model sent it, before any text or tool call
+    // is extracted from it
+    fn record_completion(&mut self, choice: &OneOrMany<AssistantContent>, correlation_id: &str) {
+        if !self.debug_completions {
+            return;
+        }
+
+        let completion = serde_json::to_value(choice).unwrap_or(serde_json::Value::Null);
+        tracing::debug!(target: "rig-magi", correlation_id = %correlation_id,
+            "Completion response: {}",
+            completion
+        );
+        self.last_completion = Some(completion);
+    }
+
+    fn set_state(&self, state: RunState) {
+        if let Some(status) = &self.status {
+            status.set_state(state);
+        }
+    }
+
+    // What the model is told a review found
+    fn tool_result_text(&self, tool_result: &str, review: &serde_json::Value) -> String {
+        if self.summarize_tool_results {
+            summarize_review(review)
+        } else {
+            tool_result.to_string()
+        }
+    }
+
+    fn check_budget(&self) -> Result<(), MultiTurnError> {
+        match self.token_budget {
+            Some(limit) if self.tokens_used >= limit => Err(MultiTurnError::BudgetExhausted {
+                used: self.tokens_used,
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // Drop the oldest messages beyond `max_history`. A tool result whose tool
+    // call was dropped goes too, so call/result pairs are never split.
+    fn trim_history(&mut self) {
+        let Some(max) = self.max_history else {
+            return;
+        };
+        if self.chat_history.len() <= max {
+            return;
+        }
+
+        let mut start = self.chat_history.len() - max;
+        while start < self.chat_history.len() && is_tool_result(&self.chat_history[start]) {
+            start += 1;
+        }
+
+        tracing::debug!(target: "rig-magi", dropped = start, "Trimming chat history");
+        self.chat_history.drain(..start);
+        self.history_rounds.drain(..start);
+    }
+
+    // Call the review tool, retrying transient failures and letting the
+    // operator settle close calls when enabled
+    async fn run_review(
+        &self,
+        name: &str,
+        arguments: serde_json::Value,
+        correlation_id: &str,
+    ) -> Result<String, PromptError> {
+        self.set_state(RunState::Reviewing);
+        let tool_result = self.review_with_retries(name, arguments, correlation_id).await;
+        self.set_state(RunState::Generating);
+        let mut tool_result = tool_result?;
+        
+        if self.human_tiebreak {
+            tool_result = apply_human_tiebreak(tool_result);
+        }
+        
+        // The operator has the final say over approvals
+        if self.review_gate {
+            tool_result = apply_review_gate(tool_result);
+        }
+
+        Ok(tool_result)
+    }
+
+    // Review several submissions concurrently, in the order given
+    async fn review_many(
+        &self,
+        submissions: Vec<serde_json::Value>,
+        correlation_id: &str,
+    ) -> Vec<Result<String, PromptError>> {
+        self.set_state(RunState::Reviewing);
+        let reviews = join_all(
+            submissions
+                .into_iter()
+                .map(|arguments| self.review_with_retries(CodeReviewTool::NAME, arguments, correlation_id)),
+        )
+        .await;
+        self.set_state(RunState::Generating);
+        reviews
+    }
+
+    // Call the review tool, retrying reviews that failed only because of
+    // transient agent errors
+    async fn review_with_retries(
+        &self,
+        name: &str,
+        arguments: serde_json::Value,
+        correlation_id: &str,
+    ) -> Result<String, PromptError> {
+        let arguments = with_correlation_id(arguments, correlation_id);
+        let mut tool_result = self.agent.tools.call(name, arguments.to_string()).await?;
+        
+        // Retry reviews that failed only because of transient agent errors
+        let mut retries = 0;
+        while retries < self.max_review_retries && is_retryable(&tool_result) {
+            retries += 1;
+            tracing::info!(target: "rig-magi", correlation_id = %correlation_id,
+                "Retrying code review after transient errors ({}/{})",
+                retries,
+                self.max_review_retries
+            );
+            tool_result = self.agent.tools.call(name, arguments.to_string()).await?;
+        }
+
+        Ok(tool_result)
+    }
+
+    /// Generate `count` candidates for the prompt at spread-out temperatures,
+    /// review them all concurrently and return the first (in generation
+    /// order) that passes. If none pass, the candidate with the most POSITIVE
+    /// votes is returned; `last_review()` holds the verdict either way.
+    ///
+    /// There is no improvement loop, and the operator prompts of
+    /// `with_human_tiebreak`/`with_review_gate` are not shown.
+    pub async fn candidates_prompt(
+        &mut self,
+        prompt: impl Into<Message> + Send,
+        count: usize,
+    ) -> Result<String, MultiTurnError> {
+        self.check_budget()?;
+        
+        let prompt = prompt.into();
+        if is_empty_prompt(&prompt) {
+            return Err(MultiTurnError::EmptyPrompt);
+        }
+        
+        let correlation_id = Uuid::new_v4().to_string();
+        self.last_review = None;
+        self.last_completion = None;
+        self.turn_rounds = 1;
+        self.trim_history();
+        
+        // Fan out the generations
+        let history = self.chat_history.clone();
+        let agent = &self.agent;
+        let responses = join_all((0..count).map(|index| {
+            let temperature = candidate_temperature(index, count);
+            let prompt = prompt.clone();
+            let history = history.clone();
+            async move {
+                agent
+                    .completion(prompt, history)
+                    .await?
+                    .temperature(temperature)
+                    .send()
+                    .await
+            }
+        }))
+        .await;
+        
+        // Keep the candidates that produced code
+        let user_input = prompt_text(&prompt);
+        let mut submissions = Vec::new();
+        for response in responses {
+            let response = response?;
+            self.tokens_used += exchange_tokens(&history, &prompt, &response.choice);
+            self.record_completion(&response.choice, &correlation_id);
+            if let Some(submission) = candidate_submission(&response.choice, &user_input, &correlation_id) {
+                submissions.push(submission);
+            }
+        }
+        tracing::info!(target: "rig-magi", correlation_id = %correlation_id,
+            "Reviewing {} of {} candidates",
+            submissions.len(),
+            count
+        );
+        
+        // Fan in the reviews: first pass wins, otherwise the most POSITIVE votes
+        let reviews = self.review_many(submissions, &correlation_id).await;
+        let mut best: Option<(usize, serde_json::Value)> = None;
+        for review in reviews {
+            let Ok(review) = serde_json::from_str::<serde_json::Value>(&review?) else {
+                continue;
+            };
+            let passed = review.get("passed").and_then(|v| v.as_bool()).unwrap_or(false);
+            let score = if passed { usize::MAX } else { positive_votes(&review) };
+            if best.as_ref().map_or(true, |(best_score, _)| score > *best_score) {
+                best = Some((score, review));
+            }
+            if passed {
+                break;
+            }
+        }
+        
+        let Some((_, review)) = best else {
+            return Err(MultiTurnError::NoCode("no candidate could be reviewed".to_string()));
+        };
+        let code = review.get("code").and_then(|v| v.as_str()).unwrap_or_default().to_string();
+        
+        self.push_turn_message(prompt);
+        self.push_turn_message(Message::Assistant {
+            content: OneOrMany::one(AssistantContent::Text(message::Text { text: code.clone() })),
+        });
+        self.last_review = Some(review);
+        Ok(code)
+    }
+
+    // One more model pass over approved code with `post_approval_prompt`.
+    // The approved code stands if the pass gives no code or, with
+    // `post_approval_review`, fails review.
+    async fn post_approval(
+        &mut self,
+        code: String,
+        user_input: String,
+        correlation_id: &str,
+    ) -> Result<String, MultiTurnError> {
+        let Some(instruction) = self.post_approval_prompt.clone() else {
+            return Ok(code);
+        };
+        self.check_budget()?;
+        self.trim_history();
+        
+        tracing::info!(target: "rig-magi", correlation_id = %correlation_id,
+            "Running post-approval pass"
+        );
+        let prompt = Message::User {
+            content: OneOrMany::one(UserContent::Text(message::Text {
+                text: format!(
+                    "{}\n\nApply this to the approved code above and reply with the complete code only.",
+                    instruction.trim()
+                ),
+            })),
+        };
+        let resp = self
+            .agent
+            .completion(prompt.clone(), self.chat_history.clone())
+            .await?
+            .send()
+            .await?;
+        self.tokens_used += exchange_tokens(&self.chat_history, &prompt, &resp.choice);
+        self.record_completion(&resp.choice, correlation_id);
+        
+        // Take the code from a text answer, or from a review call it made anyway
+        let revised = resp.choice.into_iter().find_map(|content| match content {
+            AssistantContent::Text(text) => Some(strip_reasoning(&text.text, correlation_id).to_string()),
+            AssistantContent::ToolCall(call) => call
+                .function
+                .arguments
+                .get("code")
+                .and_then(|v| v.as_str())
+                .map(|code| strip_reasoning(code, correlation_id).to_string()),
+        });
+        let Some(revised) = revised.filter(|revised| !revised.trim().is_empty()) else {
+            tracing::warn!(target: "rig-magi", correlation_id = %correlation_id,
+                "Post-approval pass returned no code, keeping the approved code"
+            );
+            return Ok(code);
+        };
+        
+        if self.post_approval_review {
+            let arguments = json!({ "user_input": user_input, "code": &revised });
+            let tool_result = self.run_review(CodeReviewTool::NAME, arguments, correlation_id).await?;
+            if !review_passed(&tool_result) {
+                tracing::warn!(target: "rig-magi", correlation_id = %correlation_id,
+                    "Post-approval pass was rejected in review, keeping the approved code"
+                );
+                return Ok(code);
+            }
+            self.last_review = serde_json::from_str(&tool_result).ok();
+        }
+        
+        self.push_turn_message(prompt);
+        self.push_turn_message(Message::Assistant {
+            content: OneOrMany::one(AssistantContent::Text(message::Text { text: revised.clone() })),
+        });
+        Ok(revised)
+    }
+
+    pub async fn multi_turn_prompt(
+        &mut self,
+        prompt: impl Into<Message> + Send,
+    ) -> Result<String, MultiTurnError> {
+        self.check_budget()?;
+        
+        // Correlates this turn with the gateway requests it triggers
+        let correlation_id = Uuid::new_v4().to_string();
+        self.last_review = None;
+        self.last_completion = None;
+        self.turn_rounds = 0;
+        
+        // Initial prompt
+        let initial_prompt = prompt.into();
+        if is_empty_prompt(&initial_prompt) {
+            return Err(MultiTurnError::EmptyPrompt);
+        }
+        let mut current_prompt = initial_prompt.clone();
+        
+        // Save initial prompt to history
+        self.push_turn_message(current_prompt.clone());
+        
+        // Rejection feedback sent as the prompt, added to history once answered
+        let mut feedback_turn: Option<Message> = None;
+        
+        // Tests the model sent with its first review, reused for every revision
+        let mut turn_tests: Option<serde_json::Value> = None;
+        
+        // Consecutive rounds that came back with nothing to act on
+        let mut empty_responses = 0;
+        
+        // Code generation and review loop
+        loop {
+            // Code the panel keeps rejecting would otherwise be regenerated forever
+            if self.max_iterations > 0 && self.turn_rounds >= self.max_iterations {
+                let code = self
+                    .last_review
+                    .as_ref()
+                    .and_then(|review| review.get("code"))
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                tracing::warn!(target: "rig-magi", correlation_id = %correlation_id,
+                    "Giving up after {} rounds",
+                    self.turn_rounds
+                );
+                return Err(MultiTurnError::IterationsExhausted {
+                    rounds: self.turn_rounds,
+                    code,
+                    feedback: prompt_text(&current_prompt),
+                });
+            }
+            self.turn_rounds += 1;
+            
+            match self.max_iterations {
+                0 => tracing::info!(target: "rig-magi", correlation_id = %correlation_id,
+                    round = self.turn_rounds,
+                    "Generating code (round {})",
+                    self.turn_rounds
+                ),
+                max => tracing::info!(target: "rig-magi", correlation_id = %correlation_id,
+                    round = self.turn_rounds,
+                    "Generating code (round {}/{})",
+                    self.turn_rounds,
+                    max
+                ),
+            }
+            
+            self.trim_history();
+            
+            // Rounds within a turn count against the budget too
+            self.check_budget()?;
+            
+            // Send prompt to AI
+            let resp = self
+                .agent
+                .completion(current_prompt.clone(), self.chat_history.clone())
+                .await?
+                .send()
+                .await?;
+            
+            // Account for what was sent and what came back
+            self.tokens_used += exchange_tokens(&self.chat_history, &current_prompt, &resp.choice);
+            self.record_completion(&resp.choice, &correlation_id);
+            
+            // Record the feedback the model just answered, after the tool result it follows
+            if let Some(feedback) = feedback_turn.take() {
+                self.push_turn_message(feedback);
+            }
+
+            let mut final_text = None;
+            let mut code_approved = false;
+            let mut actionable = false;
+
+            for content in resp.choice.into_iter() {
+                match content {
+                    // Blank text has nothing to review or return
+                    AssistantContent::Text(text) if text.text.trim().is_empty() => {
+                        tracing::warn!(target: "rig-magi", correlation_id = %correlation_id,
+                            "Skipping blank text response"
+                        );
+                    }
+                    AssistantContent::Text(text) => {
+                        actionable = true;
+                        
+                        // AI directly returns text (usually code that has passed review)
+                        tracing::debug!(target: "rig-magi", correlation_id = %correlation_id,
+                            "Model response: {}",
+                            text.text
+                        );
+                        
+                        // Save to history
+                        let response_message = Message::Assistant {
+                            content: OneOrMany::one(AssistantContent::Text(message::Text {
+                                text: text.text.clone(),
+                            })),
+                        };
+                        self.push_turn_message(response_message);
+                        
+                        // A refusal isn't code, approved or otherwise
+                        if is_refusal(&text.text, &self.refusal_phrases) {
+                            tracing::info!(target: "rig-magi", correlation_id = %correlation_id,
+                                "Model refused to generate code"
+                            );
+                            return Err(MultiTurnError::Refused(text.text.clone()));
+                        }
+                        
+                        // The answer proper, without any reasoning in front of it
+                        let code = strip_reasoning(&text.text, &correlation_id).to_string();
+                        
+                        // Models that skip the tool call still get their answer reviewed
+                        if self.review_direct_text {
+                            tracing::info!(target: "rig-magi", correlation_id = %correlation_id,
+                                "Reviewing direct text response"
+                            );
+                            let arguments = json!({
+                                "user_input": prompt_text(&initial_prompt),
+                                "code": &code,
+                            });
+                            let tool_result = self.run_review(CodeReviewTool::NAME, arguments, &correlation_id).await?;
+                            self.last_review = serde_json::from_str(&tool_result).ok();
+                            
+                            if !review_passed(&tool_result) && !self.single_pass {
+                                tracing::info!(target: "rig-magi", correlation_id = %correlation_id,
+                                    "Code review failed, continuing improvements"
+                                );
+                                
+                                // Next round prompt quotes the dissenting reviewers
+                                let review_result = serde_json::from_str(&tool_result).unwrap_or(serde_json::Value::Null);
+                                current_prompt = rejection_feedback(&review_result, self.feedback_budget);
+                                feedback_turn = Some(current_prompt.clone());
+                                break;
+                            }
+                        }
+                        
+                        final_text = Some(code);
+                        code_approved = true;
+                    }
+                    AssistantContent::ToolCall(content) => {
+                        actionable = true;
+                        
+                        tracing::info!(target: "rig-magi", correlation_id = %correlation_id,
+                            "AI call tool: {}",
+                            content.function.name
+                        );
+                        
+                        // Save AI's tool call to history
+                        let tool_call_msg = AssistantContent::ToolCall(content.clone());
+                        self.push_turn_message(Message::Assistant {
+                            content: OneOrMany::one(tool_call_msg),
+                        });
+
+                        // Extract tool call information
+                        let ToolCall {
+                            id,
+                            function: ToolFunction { name, mut arguments },
+                        } = content;
+                        
+                        // Judge every revision against the same tests
+                        match arguments.get("tests").filter(|tests| tests.is_string()) {
+                            Some(tests) => turn_tests = Some(tests.clone()),
+                            None => {
+                                if let (Some(tests), Some(object)) = (&turn_tests, arguments.as_object_mut()) {
+                                    object.insert("tests".to_string(), tests.clone());
+                                }
+                            }
+                        }
+                        
+                        // Only the code goes to review, not any reasoning in front of it
+                        if let Some(code) = arguments.get("code").and_then(|v| v.as_str()) {
+                            let code = strip_reasoning(code, &correlation_id).to_string();
+                            arguments["code"] = json!(code);
+                        }
+
+                        // Call tool (code review)
+                        tracing::info!(target: "rig-magi", correlation_id = %correlation_id,
+                            "Executing code review"
+                        );
+                        let tool_result = self.run_review(&name, arguments, &correlation_id).await?;
+
+                        // Parse review result
+                        if let Ok(review_result) = serde_json::from_str::<serde_json::Value>(&tool_result) {
+                            self.last_review = Some(review_result.clone());
+                            
+                            // Check if code passed review
+                            if let Some(passed) = review_result.get("passed").and_then(|v| v.as_bool()) {
+                                if passed {
+                                    tracing::info!(target: "rig-magi", correlation_id = %correlation_id,
+                                        "Code review passed"
+                                    );
+                                    
+                                    // Extract code
+                                    if let Some(code) = review_result.get("code").and_then(|v| v.as_str()) {
+                                        final_text = Some(code.to_string());
+                                        code_approved = true;
+                                        
+                                        // Create tool result message and add to history
+                                        let tool_result_message =  Message::User {
+                                            content: OneOrMany::one(UserContent::ToolResult(message::ToolResult {
+                                                id: id.clone(),
+                                                content: OneOrMany::one(ToolResultContent::Text(message::Text {
+                                                    text: self.tool_result_text(&tool_result, &review_result),
+                                                })),
+                                            })),
+                                        };
+
+                                        self.push_turn_message(tool_result_message);
+                                        
+                                        // Add final result message
+                                        let final_message = Message::Assistant {
+                                            content: OneOrMany::one(AssistantContent::Text(message::Text {
+                                                text: code.to_string(),
+                                            })),
+                                        };
+                                        self.push_turn_message(final_message);
+                                        
+                                        // Return result directly after code passes review
+                                        return self
+                                            .post_approval(code.to_string(), prompt_text(&initial_prompt), &correlation_id)
+                                            .await;
+                                    }
+                                } else {
+                                    tracing::info!(target: "rig-magi", correlation_id = %correlation_id,
+                                        "Code review failed, continuing improvements"
+                                    );
+
+                                    tracing::debug!(target: "rig-magi", correlation_id = %correlation_id,
+                                        "Review result: {}",
+                                        tool_result
+                                    );
+                                    
+                                    // Create tool result message
+                                    let tool_result_message =  Message::User {
+                                        content: OneOrMany::one(UserContent::ToolResult(message::ToolResult {
+                                            id: id.clone(),
+                                            content: OneOrMany::one(ToolResultContent::Text(message::Text {
+                                                text: self.tool_result_text(&tool_result, &review_result),
+                                            })),
+                                        })),
+                                    };
+
+                                    self.push_turn_message(tool_result_message.clone());
+                                    
+                                    // Single pass hands back the rejected code with its verdict
+                                    if self.single_pass {
+                                        let code = review_result.get("code").and_then(|v| v.as_str()).unwrap_or_default();
+                                        return Ok(code.to_string());
+                                    }
+                                    
+                                    // Next round prompt quotes the dissenting reviewers
+                                    current_prompt = rejection_feedback(&review_result, self.feedback_budget);
+                                    feedback_turn = Some(current_prompt.clone());
+
+                                    break;
+                                }
+                            }
+                        }
+                        
+                        // If unable to parse review result, use original tool result
+                        let tool_result_message = Message::User {
+                            content: OneOrMany::one(UserContent::ToolResult(message::ToolResult {
+                                id: id.clone(),
+                                content: OneOrMany::one(ToolResultContent::Text(message::Text {
+                                    text: tool_result.clone(),
+                                })),
+                            })),
+                        };
+                        self.push_turn_message(tool_result_message.clone());
+                        
+                        // Without a verdict there is no reviewed code to hand back
+                        if self.single_pass {
+                            return Err(MultiTurnError::NoCode(format!("the review gave no verdict: {}", tool_result)));
+                        }
+                        current_prompt = tool_result_message;
+                        
+                        break;
+                    }
+                }
+            }
+
+            if code_approved || final_text.is_some() {
+                return match final_text {
+                    Some(code) => self.post_approval(code, prompt_text(&initial_prompt), &correlation_id).await,
+                    None => Err(MultiTurnError::NoCode("the approved review had no code".to_string())),
+                };
+            }
+            
+            // Nothing to act on would re-send the same prompt forever; ask
+            // once more for an answer, then give up
+            if !actionable {
+                empty_responses += 1;
+                tracing::warn!(target: "rig-magi", correlation_id = %correlation_id,
+                    "Model response had no text or tool call ({}/{})",
+                    empty_responses,
+                    MAX_EMPTY_RESPONSES
+                );
+                if empty_responses >= MAX_EMPTY_RESPONSES {
+                    return Err(MultiTurnError::EmptyResponse { rounds: empty_responses });
+                }
+                current_prompt = Message::User {
+                    content: OneOrMany::one(UserContent::Text(message::Text {
+                        text: format!(
+                            "Your last response contained no text or tool call. Write the code and submit it with the {} tool.",
+                            CodeReviewTool::NAME
+                        ),
+                    })),
+                };
+                feedback_turn = Some(current_prompt.clone());
+            } else {
+                empty_responses = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MockGateway;
+    use crate::testing::{ScriptedModel, ScriptedReview};
+    use crate::tools::code_review::{tool_definition, CodeReviewArgs, CodeReviewError};
+    use rig::completion::ToolDefinition;
+
+    // The loop over a scripted model and review tool; the clones share their
+    // script and record with the caller's
+    fn scripted(model: &ScriptedModel, review: &ScriptedReview) -> MultiTurnAgent<ScriptedModel> {
+        MultiTurnAgent::new(AgentBuilder::new(model.clone()).tool(review.clone()).build())
+    }
+
+    fn user(text: &str) -> Message {
+        Message::User {
+            content: OneOrMany::one(UserContent::Text(message::Text { text: text.to_string() })),
+        }
+    }
+
+    fn json(messages: &[Message]) -> serde_json::Value {
+        serde_json::to_value(messages).unwrap()
+    }
+
+    // The text of a single-text user message
+    fn user_text(message: &Message) -> String {
+        match message {
+            Message::User { content } => match content.first() {
+                UserContent::Text(text) => text.text,
+                other => panic!("not text: {:?}", other),
+            },
+            other => panic!("not a user message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn every_review_of_a_turn_carries_its_correlation_id() {
+        let model = ScriptedModel::new()
+            .then_submit("add", "fn add() {}")
+            .then_submit("add", "fn add(a: i32, b: i32) -> i32 { a + b }");
+        let review = ScriptedReview::new().then_reject("missing arguments").then_approve();
+        let mut agent = scripted(&model, &review);
+
+        agent.multi_turn_prompt("add").await.unwrap();
+
+        let ids: Vec<Option<String>> = review.reviewed().into_iter().map(|args| args.correlation_id).collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids[0].is_some());
+        assert_eq!(ids[0], ids[1]);
+    }
+
+    // A close-call review as the tool returns it
+    fn close_call_review() -> String {
+        json!({
+            "passed": true,
+            "result": "POSITIVE",
+            "close_call": true,
+            "magi_state": { "agents": [{ "name": "melchior", "decision": "POSITIVE" }] },
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn the_operator_settles_a_close_call() {
+        let overruled: serde_json::Value =
+            serde_json::from_str(&human_tiebreak(close_call_review(), &mut "no\n".as_bytes())).unwrap();
+        assert_eq!(overruled["passed"], false);
+        assert_eq!(overruled["result"], "NEGATIVE");
+        assert_eq!(overruled["human_decision"], "NEGATIVE");
+
+        let upheld: serde_json::Value =
+            serde_json::from_str(&human_tiebreak(close_call_review(), &mut "y\n".as_bytes())).unwrap();
+        assert_eq!(upheld["passed"], true);
+        assert_eq!(upheld["human_decision"], "POSITIVE");
+    }
+
+    #[test]
+    fn unclear_answers_and_clear_reviews_keep_the_panel_decision() {
+        assert_eq!(human_tiebreak(close_call_review(), &mut "maybe\n".as_bytes()), close_call_review());
+
+        let clear = json!({ "passed": true, "close_call": false }).to_string();
+        assert_eq!(human_tiebreak(clear.clone(), &mut "n\n".as_bytes()), clear);
+    }
+
+    #[tokio::test]
+    async fn seeded_history_is_sent_to_the_model() {
+        let model = ScriptedModel::new().then_submit("add", "fn add() {}");
+        let review = ScriptedReview::new().then_approve();
+        let mut agent = scripted(&model, &review);
+
+        agent.set_history(vec![user("earlier"), user("dropped")]);
+        agent.truncate_history(1);
+        agent.push_message(user("context"));
+        assert_eq!(agent.history_rounds(), &[0, 0]);
+
+        agent.multi_turn_prompt("add").await.unwrap();
+
+        let sent = &model.calls()[0].chat_history;
+        assert_eq!(json(&sent[..2]), json(&[user("earlier"), user("context")]));
+        assert_eq!(json(&agent.history()[..2]), json(&[user("earlier"), user("context")]));
+    }
+
+    #[tokio::test]
+    async fn kept_history_reaches_the_next_prompt_until_cleared() {
+        let model = ScriptedModel::new()
+            .then_submit("add", "fn add() {}")
+            .then_submit("sub", "fn sub() {}")
+            .then_submit("mul", "fn mul() {}");
+        let review = ScriptedReview::new().then_approve().then_approve().then_approve();
+        let mut agent = scripted(&model, &review);
+
+        agent.multi_turn_prompt("add").await.unwrap();
+        let first_turn = agent.history().to_vec();
+        agent.multi_turn_prompt("sub").await.unwrap();
+        agent.clear_history();
+        agent.multi_turn_prompt("mul").await.unwrap();
+
+        let calls = model.calls();
+        assert_eq!(json(&calls[1].chat_history[..first_turn.len()]), json(&first_turn));
+        assert!(calls[2].chat_history.is_empty());
+    }
+
+    #[test]
+    fn clearing_the_history_drops_its_rounds() {
+        let model = ScriptedModel::new();
+        let mut agent = scripted(&model, &ScriptedReview::new());
+        agent.push_message(user("context"));
+
+        agent.clear_history();
+
+        assert!(agent.history().is_empty());
+        assert!(agent.history_rounds().is_empty());
+    }
+
+    #[tokio::test]
+    async fn direct_text_is_accepted_unreviewed_by_default() {
+        let model = ScriptedModel::new().then_text("fn add() {}");
+        let review = ScriptedReview::new();
+        let mut agent = scripted(&model, &review);
+
+        assert_eq!(agent.multi_turn_prompt("add").await.unwrap(), "fn add() {}");
+        assert!(review.reviewed().is_empty());
+    }
+
+    #[tokio::test]
+    async fn direct_text_is_reviewed_when_enabled() {
+        let model = ScriptedModel::new()
+            .then_text("fn add() {}")
+            .then_text("fn add(a: i32, b: i32) -> i32 { a + b }");
+        let review = ScriptedReview::new().then_reject("missing arguments").then_approve();
+        let mut agent = scripted(&model, &review).with_review_direct_text(true);
+
+        let code = agent.multi_turn_prompt("add").await.unwrap();
+
+        assert_eq!(code, "fn add(a: i32, b: i32) -> i32 { a + b }");
+        let reviewed: Vec<String> = review.reviewed().into_iter().map(|args| args.code).collect();
+        assert_eq!(reviewed, ["fn add() {}", "fn add(a: i32, b: i32) -> i32 { a + b }"]);
+    }
+
+    #[test]
+    fn rejection_feedback_quotes_each_dissenting_reviewer() {
+        let review = json!({
+            "passed": false,
+            "magi_state": { "agents": [
+                { "name": "melchior", "decision": "POSITIVE", "content": "Fine" },
+                { "name": "balthasar", "decision": "NEGATIVE", "content": "No input checks" },
+                { "name": "casper", "decision": "NEGATIVE", "content": "  " },
+            ]},
+            "pending_agents": [],
+        });
+
+        let text = user_text(&rejection_feedback(&review, 1000));
+
+        assert!(text.starts_with("The code was rejected in review.\n"));
+        assert!(text.contains("\nBalthasar objected:\nNo input checks\n"));
+        assert!(text.contains("\nCasper objected:\n(no details given)\n"));
+        assert!(!text.contains("Melchior"));
+        assert!(text.ends_with("Please improve the code to address these concerns."));
+
+        let short = user_text(&rejection_feedback(&review, 10));
+        assert!(short.contains("\nBalthasar objected:\nNo in\n"));
+    }
+
+    #[tokio::test]
+    async fn the_next_round_responds_to_the_feedback() {
+        let model = ScriptedModel::new().then_submit("add", "fn add() {}").then_submit("add", "fn add(a: i32) {}");
+        let review = ScriptedReview::new().then_reject("missing arguments").then_approve();
+        let mut agent = scripted(&model, &review);
+
+        agent.multi_turn_prompt("add").await.unwrap();
+
+        let feedback = user_text(&model.calls()[1].prompt);
+        assert!(feedback.contains("Melchior objected:\nmissing arguments"));
+    }
+
+    #[tokio::test]
+    async fn the_feedback_budget_cuts_long_objections() {
+        let model = ScriptedModel::new().then_submit("add", "fn add() {}").then_submit("add", "fn add(a: i32) {}");
+        let review = ScriptedReview::new().then_reject(&"missing arguments ".repeat(50)).then_approve();
+        let mut agent = scripted(&model, &review).with_feedback_budget(17);
+
+        agent.multi_turn_prompt("add").await.unwrap();
+
+        let feedback = user_text(&model.calls()[1].prompt);
+        assert!(feedback.contains("Melchior objected:\nmissing arguments\n"));
+        assert!(!feedback.contains("missing arguments missing"));
+    }
+
+    fn tool_call(id: &str) -> Message {
+        Message::Assistant {
+            content: OneOrMany::one(AssistantContent::ToolCall(ToolCall {
+                id: id.to_string(),
+                function: ToolFunction {
+                    name: CodeReviewTool::NAME.to_string(),
+                    arguments: json!({ "user_input": "add", "code": "fn add() {}" }),
+                },
+            })),
+        }
+    }
+
+    fn tool_result(id: &str) -> Message {
+        Message::User {
+            content: OneOrMany::one(UserContent::ToolResult(message::ToolResult {
+                id: id.to_string(),
+                content: OneOrMany::one(ToolResultContent::Text(message::Text { text: "{}".to_string() })),
+            })),
+        }
+    }
+
+    #[test]
+    fn trimming_keeps_the_last_messages_without_splitting_a_tool_call() {
+        let model = ScriptedModel::new();
+        let history = vec![user("first"), tool_call("call_0"), tool_result("call_0"), user("last")];
+
+        let mut agent = scripted(&model, &ScriptedReview::new()).with_max_history(Some(3));
+        agent.set_history(history.clone());
+        agent.trim_history();
+        assert_eq!(json(agent.history()), json(&history[1..]));
+        assert_eq!(agent.history_rounds().len(), 3);
+
+        // Cutting between the call and its result drops the orphaned result too
+        let mut agent = scripted(&model, &ScriptedReview::new()).with_max_history(Some(2));
+        agent.set_history(history.clone());
+        agent.trim_history();
+        assert_eq!(json(agent.history()), json(&history[3..]));
+        assert_eq!(agent.history_rounds(), &[0]);
+    }
+
+    #[tokio::test]
+    async fn no_completion_is_sent_a_history_starting_with_a_tool_result() {
+        let model = ScriptedModel::new()
+            .then_submit("add", "fn add() {}")
+            .then_submit("add", "fn add(a: i32) {}")
+            .then_submit("add", "fn add(a: i32, b: i32) {}");
+        let review = ScriptedReview::new().then_reject("one argument").then_reject("two arguments").then_approve();
+        let mut agent = scripted(&model, &review).with_max_history(Some(2));
+
+        agent.multi_turn_prompt("add").await.unwrap();
+
+        for call in model.calls() {
+            assert!(call.chat_history.len() <= 2);
+            assert!(!call.chat_history.first().is_some_and(is_tool_result));
+        }
+    }
+
+    #[tokio::test]
+    async fn an_exhausted_budget_halts_generation() {
+        let model = ScriptedModel::new().then_submit("add", "fn add() {}").then_submit("sub", "fn sub() {}");
+        let review = ScriptedReview::new().then_approve().then_approve();
+        let mut agent = scripted(&model, &review).with_token_budget(Some(1));
+
+        agent.multi_turn_prompt("add").await.unwrap();
+        assert!(agent.tokens_used() >= 1);
+        assert_eq!(agent.remaining_budget(), Some(0));
+
+        let result = agent.multi_turn_prompt("sub").await;
+
+        assert!(matches!(result, Err(MultiTurnError::BudgetExhausted { limit: 1, .. })));
+        assert_eq!(model.calls().len(), 1);
+        assert_eq!(model.remaining(), 1);
+    }
+
+    #[tokio::test]
+    async fn single_pass_returns_rejected_code_with_its_verdict() {
+        let model = ScriptedModel::new().then_submit("add", "fn add() {}").then_submit("add", "fn add(a: i32) {}");
+        let review = ScriptedReview::new().then_reject("missing arguments").then_approve();
+        let mut agent = scripted(&model, &review).with_single_pass(true);
+
+        let code = agent.multi_turn_prompt("add").await.unwrap();
+
+        assert_eq!(code, "fn add() {}");
+        assert_eq!(agent.last_review().unwrap()["passed"], false);
+        assert_eq!(model.calls().len(), 1);
+        assert_eq!(review.reviewed().len(), 1);
+    }
+
+    fn approved_review() -> String {
+        json!({ "passed": true, "result": "POSITIVE", "code": "fn add() {}" }).to_string()
+    }
+
+    #[test]
+    fn the_operator_can_overrule_an_approval_with_feedback() {
+        let gated = review_gate(approved_review(), &mut "n\nCheck for overflow\n".as_bytes());
+        let review: serde_json::Value = serde_json::from_str(&gated).unwrap();
+
+        assert_eq!(review["passed"], false);
+        assert_eq!(review["result"], "NEGATIVE");
+        assert_eq!(review["operator_decision"], "rejected");
+        assert_eq!(review["operator_feedback"], "Check for overflow");
+        assert!(user_text(&rejection_feedback(&review, 1000)).contains("The operator objected:\nCheck for overflow\n"));
+    }
+
+    #[test]
+    fn accepted_and_rejected_reviews_pass_the_gate() {
+        let accepted: serde_json::Value =
+            serde_json::from_str(&review_gate(approved_review(), &mut "y\n".as_bytes())).unwrap();
+        assert_eq!(accepted["passed"], true);
+        assert_eq!(accepted["operator_decision"], "accepted");
+
+        let rejected = json!({ "passed": false, "result": "NEGATIVE" }).to_string();
+        assert_eq!(review_gate(rejected.clone(), &mut "n\n".as_bytes()), rejected);
+    }
+
+    #[test]
+    fn feedback_names_the_reviewers_not_waited_for() {
+        let review = json!({
+            "passed": false,
+            "magi_state": { "agents": [
+                { "name": "melchior", "decision": "NEGATIVE", "content": "Too slow" },
+                { "name": "balthasar", "decision": "NEGATIVE", "content": "No tests" },
+                { "name": "casper", "decision": null, "content": "" },
+            ]},
+            "pending_agents": ["casper"],
+        });
+
+        let text = user_text(&rejection_feedback(&review, 1000));
+
+        assert!(text.contains("\n(Casper had not finished reviewing; their feedback is not included.)\n"));
+    }
+
+    #[test]
+    fn an_errored_reviewer_is_named_but_not_quoted_as_objecting() {
+        let review = json!({
+            "passed": false,
+            "result": "NEGATIVE",
+            "magi_state": { "agents": [
+                { "name": "melchior", "decision": "NEGATIVE", "content": "Too slow", "errored": false },
+                { "name": "balthasar", "decision": "NEGATIVE", "content": "", "errored": true },
+                { "name": "casper", "decision": "POSITIVE", "content": "Fine", "errored": false },
+            ]},
+        });
+
+        let text = user_text(&rejection_feedback(&review, 1000));
+        assert!(text.contains("\nMelchior objected:\nToo slow\n"));
+        assert!(!text.contains("Balthasar objected") && !text.contains("(no details given)"));
+        assert!(text.contains("\n(Balthasar errored and did not review.)\n"));
+
+        let summary: serde_json::Value = serde_json::from_str(&summarize_review(&review)).unwrap();
+        assert_eq!(summary["dissent"], json!([{ "agent": "melchior", "concern": "Too slow" }]));
+        assert_eq!(summary["errored_agents"], json!(["balthasar"]));
+    }
+
+    #[test]
+    fn reasoning_is_split_off_at_a_code_marker_or_fence() {
+        assert_eq!(
+            split_reasoning("THOUGHTS: keep it short\nCODE:\nfn add() {}\n"),
+            (Some("keep it short"), "fn add() {}")
+        );
+        assert_eq!(
+            split_reasoning("thoughts:\n  code: fn add() {}"),
+            (None, "fn add() {}")
+        );
+        assert_eq!(
+            split_reasoning("Here is the function.\n```rust\nfn add() {}\n```"),
+            (Some("Here is the function."), "```rust\nfn add() {}\n```")
+        );
+        assert_eq!(split_reasoning("fn add() {}\n"), (None, "fn add() {}\n"));
+        assert_eq!(split_reasoning("```rust\nfn add() {}\n```"), (None, "```rust\nfn add() {}\n```"));
+    }
+
+    #[tokio::test]
+    async fn only_the_code_goes_to_review() {
+        let model = ScriptedModel::new().then_submit("add", "Adding two numbers.\n```rust\nfn add() {}\n```");
+        let review = ScriptedReview::new().then_approve();
+        let mut agent = scripted(&model, &review);
+
+        agent.multi_turn_prompt("add").await.unwrap();
+
+        assert_eq!(review.reviewed()[0].code, "```rust\nfn add() {}\n```");
+    }
+
+    #[tokio::test]
+    async fn every_round_is_reviewed_against_the_first_rounds_tests() {
+        let model = ScriptedModel::new()
+            .then(AssistantContent::ToolCall(ToolCall {
+                id: "call_0".to_string(),
+                function: ToolFunction {
+                    name: CodeReviewTool::NAME.to_string(),
+                    arguments: json!({ "user_input": "add", "code": "fn add() {}", "tests": "assert_eq!(add(1, 2), 3);" }),
+                },
+            }))
+            .then_submit("add", "fn add(a: i32, b: i32) -> i32 { a + b }");
+        let review = ScriptedReview::new().then_reject("no arguments").then_approve();
+        let mut agent = scripted(&model, &review);
+
+        agent.multi_turn_prompt("add").await.unwrap();
+
+        let tests: Vec<Option<String>> = review.reviewed().into_iter().map(|args| args.tests).collect();
+        assert_eq!(tests, [Some("assert_eq!(add(1, 2), 3);".to_string()), Some("assert_eq!(add(1, 2), 3);".to_string())]);
+    }
+
+    #[test]
+    fn refusals_are_phrases_without_code() {
+        let phrases: Vec<String> = REFUSAL_PHRASES.iter().map(|phrase| phrase.to_string()).collect();
+
+        assert!(is_refusal("I’m sorry, but I can’t help with that.", &phrases));
+        assert!(is_refusal("I CANNOT ASSIST with this request", &phrases));
+        assert!(!is_refusal("```rust\n// I can't help noticing\nfn add() {}\n```", &phrases));
+        assert!(!is_refusal("fn add() {}", &phrases));
+    }
+
+    #[tokio::test]
+    async fn a_refusal_is_reported_instead_of_approved() {
+        let model = ScriptedModel::new().then_text("I'm sorry, but I can't help with that.");
+        let review = ScriptedReview::new();
+        let mut agent = scripted(&model, &review).with_review_direct_text(true);
+
+        let result = agent.multi_turn_prompt("add").await;
+
+        assert!(matches!(result, Err(MultiTurnError::Refused(response)) if response.starts_with("I'm sorry")));
+        assert!(review.reviewed().is_empty());
+    }
+
+    #[tokio::test]
+    async fn refusal_phrases_are_configurable() {
+        let model = ScriptedModel::new().then_text("Not today.");
+        let mut agent = scripted(&model, &ScriptedReview::new()).with_refusal_phrases(vec!["NOT TODAY".to_string()]);
+
+        assert!(matches!(agent.multi_turn_prompt("add").await, Err(MultiTurnError::Refused(_))));
+    }
+
+    fn three_candidates() -> ScriptedModel {
+        ScriptedModel::new()
+            .then_submit("add", "fn add() -> i32 { 0 }")
+            .then_submit("add", "fn add() -> i32 { 1 }")
+            .then_submit("add", "fn add() -> i32 { 2 }")
+    }
+
+    #[tokio::test]
+    async fn the_candidate_that_passes_is_returned() {
+        let model = three_candidates();
+        let review = ScriptedReview::new().then_reject("wrong").then_approve().then_reject("wrong");
+        let mut agent = scripted(&model, &review);
+
+        let code = agent.candidates_prompt("add", 3).await.unwrap();
+
+        assert_eq!(model.calls().len(), 3);
+        assert_eq!(code, review.reviewed()[1].code);
+        assert_eq!(agent.last_review().unwrap()["passed"], true);
+        assert_eq!(agent.history().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn without_a_pass_a_rejected_candidate_is_returned_with_its_verdict() {
+        let model = three_candidates();
+        let review = ScriptedReview::new().then_reject("wrong").then_reject("wrong").then_reject("wrong");
+        let mut agent = scripted(&model, &review);
+
+        let code = agent.candidates_prompt("add", 3).await.unwrap();
+
+        assert!(review.reviewed().iter().any(|args| args.code == code));
+        assert_eq!(agent.last_review().unwrap()["passed"], false);
+    }
+
+    #[tokio::test]
+    async fn an_empty_prompt_never_reaches_the_model() {
+        let model = ScriptedModel::new().then_submit("add", "fn add() {}");
+        let mut agent = scripted(&model, &ScriptedReview::new().then_approve());
+
+        assert!(matches!(agent.multi_turn_prompt("  \n").await, Err(MultiTurnError::EmptyPrompt)));
+        assert!(matches!(agent.candidates_prompt("", 2).await, Err(MultiTurnError::EmptyPrompt)));
+        assert!(model.calls().is_empty());
+        assert!(agent.history().is_empty());
+    }
+
+    // What each message is, for checking the shape of the history
+    fn kinds(messages: &[Message]) -> Vec<&'static str> {
+        messages
+            .iter()
+            .map(|message| match message {
+                Message::User { .. } if is_tool_result(message) => "tool_result",
+                Message::User { .. } => "user",
+                Message::Assistant { content } if matches!(content.first(), AssistantContent::ToolCall(_)) => "tool_call",
+                Message::Assistant { .. } => "text",
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn the_built_code_agent_reviews_through_the_gateway() {
+        let gateway = MockGateway::voting(&["DECISION: POSITIVE"; 3]).await;
+        let model = ScriptedModel::new().then_submit("add", "fn add() {}");
+        let mut agent = build_code_agent(AgentBuilder::new(model.clone()), &gateway.config(), None);
+
+        let code = agent.multi_turn_prompt("add").await.unwrap();
+
+        assert_eq!(code, "fn add() {}");
+        assert_eq!(gateway.judgements().len(), 1);
+        assert_eq!(agent.last_review().unwrap()["passed"], true);
+    }
+
+    #[tokio::test]
+    async fn approved_code_is_returned_after_one_round() {
+        let model = ScriptedModel::new().then_submit("add", "fn add(a: i32, b: i32) -> i32 { a + b }");
+        let review = ScriptedReview::new().then_approve();
+        let mut agent = scripted(&model, &review);
+
+        let code = agent.multi_turn_prompt("add").await.unwrap();
+
+        assert_eq!(code, "fn add(a: i32, b: i32) -> i32 { a + b }");
+        assert_eq!(kinds(agent.history()), ["user", "tool_call", "tool_result", "text"]);
+        assert_eq!(agent.history_rounds(), &[1, 1, 1, 1]);
+        assert_eq!(agent.last_review().unwrap()["passed"], true);
+    }
+
+    #[tokio::test]
+    async fn a_rejection_is_revised_until_approved() {
+        let model = ScriptedModel::new()
+            .then_submit("add", "fn add() {}")
+            .then_submit("add", "fn add(a: i32, b: i32) -> i32 { a + b }");
+        let review = ScriptedReview::new().then_reject("missing arguments").then_approve();
+        let mut agent = scripted(&model, &review);
+
+        let code = agent.multi_turn_prompt("add").await.unwrap();
+
+        assert_eq!(code, "fn add(a: i32, b: i32) -> i32 { a + b }");
+        assert_eq!(
+            kinds(agent.history()),
+            ["user", "tool_call", "tool_result", "user", "tool_call", "tool_result", "text"]
+        );
+        assert_eq!(agent.history_rounds(), &[1, 1, 1, 2, 2, 2, 2]);
+        assert!(user_text(&agent.history()[3]).contains("missing arguments"));
+        assert_eq!(model.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn rejections_stop_after_the_last_allowed_round() {
+        let model = ScriptedModel::new()
+            .then_submit("add", "fn add() {}")
+            .then_submit("add", "fn add(a: i32) {}")
+            .then_submit("add", "fn add(a: i32, b: i32) -> i32 { a + b }");
+        let review = ScriptedReview::new().then_reject("missing arguments").then_reject("missing b");
+        let mut agent = scripted(&model, &review).with_max_iterations(2);
+
+        let Err(MultiTurnError::IterationsExhausted { rounds, code, feedback }) = agent.multi_turn_prompt("add").await else {
+            panic!("kept revising past the limit");
+        };
+
+        assert_eq!(rounds, 2);
+        assert_eq!(code, "fn add(a: i32) {}");
+        assert!(feedback.contains("missing b"));
+        assert_eq!(model.remaining(), 1);
+    }
+
+    // A review tool whose result is a remark rather than a review
+    #[derive(Clone)]
+    struct RawReview;
+
+    impl Tool for RawReview {
+        const NAME: &'static str = CodeReviewTool::NAME;
+        type Error = CodeReviewError;
+        type Args = CodeReviewArgs;
+        type Output = String;
+
+        async fn definition(&self, _prompt: String) -> ToolDefinition {
+            tool_definition()
+        }
+
+        async fn call(&self, _args: Self::Args) -> Result<Self::Output, Self::Error> {
+            Ok("Reviewers unavailable".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn a_result_without_a_verdict_is_sent_back_to_the_model() {
+        let model = ScriptedModel::new().then_submit("add", "fn add() {}").then_text("fn add(a: i32) {}");
+        let mut agent = MultiTurnAgent::new(AgentBuilder::new(model.clone()).tool(RawReview).build());
+
+        let code = agent.multi_turn_prompt("add").await.unwrap();
+
+        assert_eq!(code, "fn add(a: i32) {}");
+        assert!(is_tool_result(&model.calls()[1].prompt));
+        assert_eq!(kinds(agent.history()), ["user", "tool_call", "tool_result", "text"]);
+        assert_eq!(agent.history_rounds(), &[1, 1, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn a_single_pass_without_a_verdict_has_no_code_to_return() {
+        let model = ScriptedModel::new().then_submit("add", "fn add() {}");
+        let mut agent = MultiTurnAgent::new(AgentBuilder::new(model.clone()).tool(RawReview).build()).with_single_pass(true);
+
+        let result = agent.multi_turn_prompt("add").await;
+
+        assert!(matches!(result, Err(MultiTurnError::NoCode(reason)) if reason.contains("Reviewers unavailable")));
+    }
+
+    #[tokio::test]
+    async fn candidates_without_code_are_an_error() {
+        let model = ScriptedModel::new().then_text(" ").then_text("");
+        let mut agent = scripted(&model, &ScriptedReview::new());
+
+        let result = agent.candidates_prompt("add", 2).await;
+
+        assert!(matches!(result, Err(MultiTurnError::NoCode(_))));
+        assert!(agent.last_review().is_none());
+    }
+
+    #[tokio::test]
+    async fn the_post_approval_pass_replaces_the_approved_code() {
+        let model = ScriptedModel::new()
+            .then_submit("add", "fn add() {}")
+            .then_text("/// Adds nothing\nfn add() {}");
+        let review = ScriptedReview::new().then_approve();
+        let mut agent = scripted(&model, &review).with_post_approval_prompt(Some("Add docstrings".to_string()));
+
+        let code = agent.multi_turn_prompt("add").await.unwrap();
+
+        assert_eq!(code, "/// Adds nothing\nfn add() {}");
+        assert!(user_text(&model.calls()[1].prompt).starts_with("Add docstrings"));
+        assert_eq!(review.reviewed().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_rejected_post_approval_pass_keeps_the_approved_code() {
+        let model = ScriptedModel::new()
+            .then_submit("add", "fn add() {}")
+            .then_text("fn add() { unsafe {} }");
+        let review = ScriptedReview::new().then_approve().then_reject("needless unsafe");
+        let mut agent = scripted(&model, &review)
+            .with_post_approval_prompt(Some("Add docstrings".to_string()))
+            .with_post_approval_review(true);
+
+        let code = agent.multi_turn_prompt("add").await.unwrap();
+
+        assert_eq!(code, "fn add() {}");
+        assert_eq!(review.reviewed()[1].code, "fn add() { unsafe {} }");
+    }
+
+    #[tokio::test]
+    async fn the_raw_completion_is_kept_only_when_debugging() {
+        let answer = OneOrMany::one(AssistantContent::Text(message::Text { text: "fn add() {}".to_string() }));
+        let model = ScriptedModel::new().then_text("fn add() {}").then_text("fn add() {}");
+        let review = ScriptedReview::new();
+        let mut quiet = scripted(&model, &review);
+        let mut debugging = scripted(&model, &review).with_debug_completions(true);
+
+        quiet.multi_turn_prompt("add").await.unwrap();
+        debugging.multi_turn_prompt("add").await.unwrap();
+
+        assert_eq!(quiet.last_completion(), None);
+        assert_eq!(debugging.last_completion(), Some(&serde_json::to_value(answer).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn a_blank_response_is_prompted_again_then_fails_the_turn() {
+        let model = ScriptedModel::new().then_text("").then_text(" \n");
+        let review = ScriptedReview::new();
+        let mut agent = scripted(&model, &review);
+
+        let result = agent.multi_turn_prompt("add").await;
+
+        assert!(matches!(result, Err(MultiTurnError::EmptyResponse { rounds: 2 })));
+        assert!(user_text(&model.calls()[1].prompt).starts_with("Your last response contained no text or tool call."));
+        assert!(review.reviewed().is_empty());
+    }
+
+    #[tokio::test]
+    async fn an_answer_after_a_blank_response_is_used() {
+        let model = ScriptedModel::new().then_text("").then_submit("add", "fn add() {}");
+        let review = ScriptedReview::new().then_approve();
+        let mut agent = scripted(&model, &review);
+
+        let code = agent.multi_turn_prompt("add").await.unwrap();
+
+        assert_eq!(code, "fn add() {}");
+        assert_eq!(
+            MultiTurnError::EmptyResponse { rounds: 2 }.to_string(),
+            "The model returned no text or tool call 2 times in a row"
+        );
+    }
+
+    // The text of a tool result message
+    fn result_text(message: &Message) -> String {
+        match message {
+            Message::User { content } => match content.first() {
+                UserContent::ToolResult(result) => match result.content.first() {
+                    ToolResultContent::Text(text) => text.text,
+                    other => panic!("not text: {:?}", other),
+                },
+                other => panic!("not a tool result: {:?}", other),
+            },
+            other => panic!("not a user message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_summary_keeps_the_decision_and_dissent_but_not_the_code() {
+        let review = json!({
+            "passed": false,
+            "result": "NEGATIVE",
+            "code": "fn add() {}",
+            "veto": null,
+            "pending_agents": ["casper"],
+            "magi_state": { "agents": [
+                { "name": "melchior", "decision": "POSITIVE", "content": "POSITIVE, fine" },
+                { "name": "balthasar", "decision": "NEGATIVE", "content": format!("NEGATIVE: {}", "x".repeat(500)) },
+            ] },
+        });
+
+        let summary: serde_json::Value = serde_json::from_str(&summarize_review(&review)).unwrap();
+
+        assert_eq!(summary["passed"], false);
+        assert_eq!(summary["result"], "NEGATIVE");
+        assert_eq!(summary["dissent"][0]["agent"], "balthasar");
+        assert_eq!(summary["dissent"][0]["concern"].as_str().unwrap().chars().count(), MAX_SUMMARY_CONCERN_LEN);
+        assert_eq!(summary["pending_agents"], json!(["casper"]));
+        assert!(summary.get("code").is_none() && summary.get("veto").is_none() && summary.get("magi_state").is_none());
+    }
+
+    #[tokio::test]
+    async fn only_the_model_sees_the_summarized_tool_result() {
+        let run = |summarize| async move {
+            let model = ScriptedModel::new()
+                .then_submit("add", "fn add() {}")
+                .then_submit("add", "fn add(a: i32, b: i32) -> i32 { a + b }");
+            let review = ScriptedReview::new().then_reject("missing arguments").then_approve();
+            let mut agent = scripted(&model, &review).with_summarized_tool_results(summarize);
+            agent.multi_turn_prompt("add").await.unwrap();
+            (result_text(&agent.history()[2]), user_text(&agent.history()[3]))
+        };
+
+        let (full, full_feedback) = run(false).await;
+        let (summary, summary_feedback) = run(true).await;
+
+        let full: serde_json::Value = serde_json::from_str(&full).unwrap();
+        let summary: serde_json::Value = serde_json::from_str(&summary).unwrap();
+        assert_eq!(full["code"], "fn add() {}");
+        assert!(full.get("magi_state").is_some());
+        assert_eq!(summary, json!({
+            "passed": false,
+            "result": "NEGATIVE",
+            "dissent": [{ "agent": "melchior", "concern": "missing arguments" }],
+        }));
+        assert_eq!(full_feedback, summary_feedback);
+    }
+
+    #[tokio::test]
+    async fn round_tags_follow_the_messages_across_turns() {
+        let model = ScriptedModel::new()
+            .then_submit("add", "fn add() {}")
+            .then_submit("add", "fn add(a: i32, b: i32) -> i32 { a + b }")
+            .then_submit("sub", "fn sub(a: i32, b: i32) -> i32 { a - b }");
+        let review = ScriptedReview::new().then_reject("missing arguments").then_approve().then_approve();
+        let mut agent = scripted(&model, &review);
+        agent.set_history(vec![user("earlier")]);
+
+        agent.multi_turn_prompt("add").await.unwrap();
+        let after_first = agent.history().len();
+        agent.multi_turn_prompt("sub").await.unwrap();
+
+        assert_eq!(agent.history_rounds().len(), agent.history().len());
+        assert_eq!(&agent.history_rounds()[..after_first], &[0, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(&agent.history_rounds()[after_first..], &[1, 1, 1, 1]);
+        agent.truncate_history(after_first);
+        assert_eq!(agent.history_rounds().len(), after_first);
+    }
+}
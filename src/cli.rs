@@ -0,0 +1,153 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+/// MAGI System: code generation with panel review
+#[derive(Debug, Parser)]
+#[command(name = "rig-magi", version)]
+pub struct Cli {
+    /// Path to a magi.toml config file (default: $MAGI_CONFIG, then ./magi.toml)
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Keep a JSON status file (state, last decision, counts, uptime) at this path for supervisors to poll
+    #[arg(long)]
+    pub status_file: Option<PathBuf>,
+
+    /// Name shown in front of the banner, the prompt and log lines, to tell instances apart (also MAGI_INSTANCE)
+    #[arg(long, global = true)]
+    pub instance_name: Option<String>,
+
+    /// Run this one prompt instead of the interactive loop, printing the code and exiting 0 only if it was approved; `-` reads it from stdin
+    #[arg(long)]
+    pub prompt: Option<String>,
+
+    /// Keep the chat history across turns and persist it to this file, resuming from it if it exists
+    #[arg(long)]
+    pub session: Option<PathBuf>,
+
+    /// Fail to start if any message in the session file can't be parsed, instead of skipping it
+    #[arg(long, requires = "session")]
+    pub strict_session: bool,
+
+    /// File with a review rubric (checklist of criteria) sent to every reviewer
+    #[arg(long)]
+    pub rubric_file: Option<PathBuf>,
+
+    /// File with test cases the code should pass, sent to every reviewer
+    #[arg(long)]
+    pub tests_file: Option<PathBuf>,
+
+    /// Write each reviewer's transcript and decision to <dir>/<request_id>/<agent>.md (also MAGI_REVIEW_DUMP_DIR)
+    #[arg(long)]
+    pub review_dump_dir: Option<PathBuf>,
+
+    /// Keep the chat history across prompts instead of starting each one fresh (also MAGI_KEEP_HISTORY)
+    #[arg(long)]
+    pub keep_history: bool,
+
+    /// Keep at most this many messages of chat history, dropping the oldest (also MAGI_MAX_HISTORY)
+    #[arg(long)]
+    pub max_history: Option<usize>,
+
+    /// Give up on a prompt after this many generate-and-review rounds, 0 for no limit (default 5, also MAGI_MAX_ITERATIONS)
+    #[arg(long)]
+    pub max_iterations: Option<usize>,
+
+    /// Quote at most this many characters of the rejecting reviewers in the next round's prompt (default 4500, also MAGI_FEEDBACK_BUDGET)
+    #[arg(long)]
+    pub feedback_budget: Option<usize>,
+
+    /// Refuse new generations once about this many tokens (estimated) have been used (also MAGI_TOKEN_BUDGET)
+    #[arg(long)]
+    pub token_budget: Option<u64>,
+
+    /// Require an explicit verdict from each reviewer instead of guessing from its content
+    #[arg(long)]
+    pub strict_verdict: bool,
+
+    /// Ask the operator to approve or reject close-call reviews
+    #[arg(long)]
+    pub human_tiebreak: bool,
+
+    /// Ask the operator to accept or reject code the panel approved, with feedback for another round
+    #[arg(long)]
+    pub review_gate: bool,
+
+    /// Compile-check code locally and return compiler errors without contacting the gateway
+    #[arg(long)]
+    pub compile_check: bool,
+
+    /// Print reviewers' output as it streams in, prefixed with each agent's name
+    #[arg(long)]
+    pub stream_output: bool,
+
+    /// Generate and review once, printing the code and the verdict without retrying rejections
+    #[arg(long)]
+    pub single_pass: bool,
+
+    /// After approval, have the model revise the code with this instruction, e.g. "add docstrings"
+    #[arg(long)]
+    pub post_approval_prompt: Option<String>,
+
+    /// Review the post-approval revision, keeping the approved code if it is rejected
+    #[arg(long, requires = "post_approval_prompt")]
+    pub post_approval_review: bool,
+
+    /// Generate this many candidates per prompt, review them all and keep the first that passes
+    #[arg(long)]
+    pub candidates: Option<usize>,
+
+    /// Print the model's raw response for the last completion of each turn to stderr as JSON
+    #[arg(long)]
+    pub debug_completions: bool,
+
+    /// Print review progress events to stderr as JSON lines
+    #[arg(long)]
+    pub events: bool,
+
+    /// Forward review events as JSON to this WebSocket endpoint, reconnecting if it drops
+    #[arg(long)]
+    pub events_ws: Option<String>,
+
+    /// Answer review calls with a compact summary (decision, dissent, key concerns) instead of the full review (also MAGI_SUMMARIZE_TOOL_RESULTS)
+    #[arg(long)]
+    pub summarize_tool_results: bool,
+
+    /// Review answers the model returns as text without calling the review tool (also MAGI_REVIEW_DIRECT_TEXT)
+    #[arg(long)]
+    pub review_direct_text: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Review existing files as one change and exit with the verdict:
+    /// 0 approved, 1 rejected, 2 no clear verdict or an error
+    Review {
+        /// File to review; repeat for a multi-file change
+        #[arg(long = "file", required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Review two versions of the same code and report which the panel preferred
+    Compare {
+        /// The old version, then the new one
+        #[arg(long = "file", required = true)]
+        files: Vec<PathBuf>,
+        /// What the code is meant to do, sent as the request for both reviews
+        #[arg(long)]
+        prompt: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Parse and validate the config without running, exiting nonzero on errors
+    Check,
+}
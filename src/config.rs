@@ -0,0 +1,1499 @@
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::tools::code_review::{normalize_ws_url, CodeReviewError, MAGIDecision};
+use crate::tools::compile_check::CompileCheck;
+use crate::tools::events::Backpressure;
+use crate::tools::redact;
+use crate::tools::verdict::VerdictMarkers;
+
+// Constants for MAGI Gateway
+const APP_ID: &str = "b75fce6f-e8af-4207-9c32-f8166afb4520";
+const APP_SECRET: &str = "magi-gateway-development-secret";
+// Gateway protocol version this client's frames follow
+const PROTOCOL_VERSION: u32 = 1;
+// Base review timeout, so an agent that never completes can't hang the review
+const REVIEW_TIMEOUT: Duration = Duration::from_secs(120);
+// Default reviewer panel, used when MAGI_AGENT_IDS is unset
+const AGENT_IDS: [(&str, &str); 3] = [
+    ("melchior", "d37c1cc8-bcc4-4b73-9f49-a93a30971f2c"),
+    ("balthasar", "6634d0ec-d700-4a92-9066-4960a0f11927"),
+    ("casper", "89cbe912-25d0-47b0-97da-b25622bfac0d"),
+];
+
+/// A single reviewer on the MAGI panel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewerAgent {
+    /// Name used in reviews, logs and the output state
+    pub name: String,
+    /// Agent id known to the MAGI Gateway
+    pub id: String,
+}
+
+#[derive(Clone)]
+pub struct CodeReviewConfig {
+    /// MAGI Gateway WebSocket URL
+    pub server_url: String,
+    /// Gateway application id used for authentication
+    pub app_id: String,
+    /// Gateway application secret used to derive the auth token
+    pub app_secret: String,
+    /// Token sent instead of one derived from `app_secret`, for callers that
+    /// compute it themselves. Not read from the environment.
+    pub auth_token: Option<String>,
+    /// Reviewer panel. Its order is kept everywhere agents are listed (the
+    /// request, `magi_state`, evidence, transcripts), so reports are
+    /// reproducible for the same config.
+    pub agents: Vec<ReviewerAgent>,
+    /// Endpoint listing the panel, fetched at startup to replace `agents`
+    /// (needs the `discovery` feature)
+    pub agents_url: Option<String>,
+    /// Where the last fetched panel is saved, and read back when a fetch fails
+    pub agents_cache: Option<PathBuf>,
+    /// Maximum request size in bytes before switching to chunked submission.
+    /// `None` always sends a single message; chunking needs gateway support.
+    pub chunk_size: Option<usize>,
+    /// Authenticated connections kept open and idle so a review doesn't wait
+    /// for the handshake. 0 connects per review.
+    pub warm_connections: usize,
+    /// Minimum length of an approving agent's content for the approval to
+    /// count as substantive. `None` disables the check.
+    pub min_approval_content: Option<usize>,
+    /// What happens to approvals shorter than `min_approval_content`
+    pub weak_approval_mode: WeakApprovalMode,
+    /// Number of POSITIVE votes needed to pass. `None` is a simple majority
+    /// of the panel.
+    pub quorum: Option<usize>,
+    /// Quorums for risky code, checked in order; the first tier matching a
+    /// submission replaces `quorum` for its review
+    pub risk_tiers: Vec<RiskTier>,
+    /// Require an explicit verdict from every agent instead of guessing from
+    /// the content; agents without one are reported as inconclusive.
+    pub strict_verdict: bool,
+    /// Compile-check code locally before sending it to the panel
+    pub compile_check: bool,
+    /// Per-language compile check commands
+    pub compile_checks: Vec<CompileCheck>,
+    /// Languages code may be reviewed in, compared ignoring case; empty
+    /// allows every language
+    pub allowed_languages: Vec<String>,
+    /// Sentences quoted per approving agent in `approval_evidence`; 0 turns
+    /// the summary off.
+    pub approval_evidence_limit: usize,
+    /// A vote this close to `required_positive()` (either side) is reported
+    /// as a close call. 0 means only an exact match.
+    pub close_call_margin: usize,
+    /// Agents whose dissent makes a passing vote a close call
+    pub required_approvers: Vec<String>,
+    /// Votes per agent, by name, for the default majority; unlisted agents
+    /// count 1. Empty counts every vote once.
+    pub weights: HashMap<String, u32>,
+    /// What happens to a pass by exactly `required_positive()` votes
+    pub minimum_margin_mode: MinimumMarginMode,
+    /// How often to log the agents still pending while waiting for the
+    /// panel. `None` disables the heartbeat.
+    pub heartbeat_interval: Option<Duration>,
+    /// Reject an approved review if any agent tagged an issue
+    /// `[SECURITY][CRITICAL]`, whatever the vote
+    pub security_veto: bool,
+    /// Skip a streamed chunk that exactly repeats the agent's previous one,
+    /// for gateways that re-send chunks. Off by default, since a response
+    /// can legitimately repeat itself.
+    pub dedupe_chunks: bool,
+    /// Resubmissions to an agent that failed with a transient error, sent to
+    /// that agent alone while the other verdicts are kept. 0 counts the
+    /// error as a NEGATIVE vote straight away.
+    pub agent_retries: u8,
+    /// How long to wait for the panel before finishing with the verdicts so
+    /// far, before scaling by size (120s by default). `None` waits
+    /// indefinitely.
+    pub review_timeout: Option<Duration>,
+    /// Added to `review_timeout` per KiB of submitted code
+    pub review_timeout_per_kb: Duration,
+    /// Upper bound on the scaled timeout
+    pub review_timeout_max: Option<Duration>,
+    /// Tokens that mean approve and reject, for both the heuristic and
+    /// explicit verdicts
+    pub verdict_markers: VerdictMarkers,
+    /// Review events buffered for a slow consumer before `event_backpressure`
+    /// applies
+    pub event_buffer: usize,
+    /// Whether a full event buffer drops chunks or pauses the review
+    pub event_backpressure: Backpressure,
+    /// Review criteria sent to every agent with each request
+    pub rubric: Option<String>,
+    /// Test cases the code should satisfy, sent with each request
+    pub tests: Option<String>,
+    /// Write each agent's transcript to `<dir>/<request_id>/<agent>.md` after
+    /// every review
+    pub review_dump_dir: Option<PathBuf>,
+    /// Keep clear verdicts in this directory, keyed by a hash of the
+    /// submission, panel and policy, and reuse them across runs
+    pub review_cache_dir: Option<PathBuf>,
+    /// Age after which a cached verdict is ignored. `None` keeps them until
+    /// evicted.
+    pub review_cache_ttl: Option<Duration>,
+    /// Cached verdicts kept before the oldest are evicted; 0 is unlimited
+    pub review_cache_max_entries: usize,
+    /// Wait for every agent's verdict even once the outcome can't change,
+    /// instead of finishing the review early
+    pub wait_for_all: bool,
+    /// With `wait_for_all`, keep taking chunks for this long after the last
+    /// verdict, for gateways that send trailing content after `completed`
+    pub settle_window: Option<Duration>,
+    /// Frame `type` strings of the gateway protocol
+    pub message_types: MessageTypes,
+    /// Gateway protocol version sent with each request. A gateway that
+    /// reports it doesn't speak it fails the review.
+    pub protocol_version: u32,
+    /// Reconnects when the connection drops mid-review, each re-asking the
+    /// agents still reviewing. 0 finishes with the verdicts so far.
+    pub reconnect_attempts: u8,
+    /// Wait before the first reconnect, doubled for each one after
+    pub reconnect_delay: Duration,
+    /// How the gateway encodes agents' `content`; decoded before anything
+    /// else reads it
+    pub content_encoding: ContentEncoding,
+    /// Re-run a review rejected by a single vote up to this many times and
+    /// take the majority of all runs. 0 disables it; each re-run is a full
+    /// review by the panel.
+    pub rereview_on_split: u8,
+    /// Fixed verdicts for known code, keyed by the lowercase hex SHA-256 of
+    /// the submission. A match skips the gateway.
+    pub verdict_overrides: HashMap<String, MAGIDecision>,
+    /// Agents whose individual frames and state changes are logged at debug
+    /// level
+    pub debug_agents: Vec<String>,
+    /// Print reviewers' output to stderr as it streams in, each line
+    /// prefixed with the agent's name
+    pub stream_output: bool,
+}
+
+// Written out so the secret and token never reach a log line
+impl fmt::Debug for CodeReviewConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CodeReviewConfig")
+            .field("server_url", &redact::url(&self.server_url))
+            .field("app_id", &self.app_id)
+            .field("app_secret", &redact::MASK)
+            .field("auth_token", &self.auth_token.as_ref().map(|_| redact::MASK))
+            .field("agents", &self.agents)
+            .field("agents_url", &self.agents_url.as_deref().map(redact::url))
+            .field("agents_cache", &self.agents_cache)
+            .field("chunk_size", &self.chunk_size)
+            .field("warm_connections", &self.warm_connections)
+            .field("min_approval_content", &self.min_approval_content)
+            .field("weak_approval_mode", &self.weak_approval_mode)
+            .field("quorum", &self.quorum)
+            .field("risk_tiers", &self.risk_tiers)
+            .field("strict_verdict", &self.strict_verdict)
+            .field("compile_check", &self.compile_check)
+            .field("compile_checks", &self.compile_checks)
+            .field("allowed_languages", &self.allowed_languages)
+            .field("approval_evidence_limit", &self.approval_evidence_limit)
+            .field("close_call_margin", &self.close_call_margin)
+            .field("required_approvers", &self.required_approvers)
+            .field("weights", &self.weights)
+            .field("minimum_margin_mode", &self.minimum_margin_mode)
+            .field("heartbeat_interval", &self.heartbeat_interval)
+            .field("security_veto", &self.security_veto)
+            .field("dedupe_chunks", &self.dedupe_chunks)
+            .field("agent_retries", &self.agent_retries)
+            .field("review_timeout", &self.review_timeout)
+            .field("review_timeout_per_kb", &self.review_timeout_per_kb)
+            .field("review_timeout_max", &self.review_timeout_max)
+            .field("verdict_markers", &self.verdict_markers)
+            .field("event_buffer", &self.event_buffer)
+            .field("event_backpressure", &self.event_backpressure)
+            .field("rubric", &self.rubric)
+            .field("tests", &self.tests)
+            .field("review_dump_dir", &self.review_dump_dir)
+            .field("review_cache_dir", &self.review_cache_dir)
+            .field("review_cache_ttl", &self.review_cache_ttl)
+            .field("review_cache_max_entries", &self.review_cache_max_entries)
+            .field("wait_for_all", &self.wait_for_all)
+            .field("settle_window", &self.settle_window)
+            .field("message_types", &self.message_types)
+            .field("protocol_version", &self.protocol_version)
+            .field("reconnect_attempts", &self.reconnect_attempts)
+            .field("reconnect_delay", &self.reconnect_delay)
+            .field("content_encoding", &self.content_encoding)
+            .field("rereview_on_split", &self.rereview_on_split)
+            .field("verdict_overrides", &self.verdict_overrides)
+            .field("debug_agents", &self.debug_agents)
+            .field("stream_output", &self.stream_output)
+            .finish()
+    }
+}
+
+/// The `type` strings the gateway protocol uses, for gateway versions that
+/// name their frames differently. Unset fields keep the default.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MessageTypes {
+    /// Outgoing judgement request
+    pub judgement: String,
+    /// Outgoing piece of a chunked judgement request
+    pub judgement_chunk: String,
+    /// Incoming streamed or completed agent response
+    pub agent_response: String,
+    /// Incoming consolidated verdict
+    pub final_decision: String,
+}
+
+impl Default for MessageTypes {
+    fn default() -> Self {
+        Self {
+            judgement: "agent_judgement".to_string(),
+            judgement_chunk: "agent_judgement_chunk".to_string(),
+            agent_response: "agent_response".to_string(),
+            final_decision: "final_decision".to_string(),
+        }
+    }
+}
+
+/// Encoding of the `content` of agent frames
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentEncoding {
+    /// Plain text
+    #[default]
+    Identity,
+    /// Standard base64 of the UTF-8 text
+    Base64,
+}
+
+impl std::str::FromStr for ContentEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "identity" | "none" => Ok(ContentEncoding::Identity),
+            "base64" => Ok(ContentEncoding::Base64),
+            other => Err(format!("Unknown content encoding: {}", other)),
+        }
+    }
+}
+
+impl ContentEncoding {
+    /// The text the content stands for
+    pub fn decode(&self, content: &str) -> Result<String, String> {
+        match self {
+            ContentEncoding::Identity => Ok(content.to_string()),
+            ContentEncoding::Base64 => {
+                let bytes = BASE64_STANDARD.decode(content.trim()).map_err(|e| format!("Invalid base64 content: {}", e))?;
+                String::from_utf8(bytes).map_err(|e| format!("Decoded content is not UTF-8: {}", e))
+            }
+        }
+    }
+}
+
+/// A risk tier for submissions matching any of its patterns, e.g. code
+/// touching auth or crypto, with the quorum its reviews need
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RiskTier {
+    /// Reported as the review's `risk_tier`
+    pub name: String,
+    /// Substrings of the code, matched ignoring case
+    pub patterns: Vec<String>,
+    /// POSITIVE votes needed in this tier. `None` is a simple majority.
+    #[serde(default)]
+    pub quorum: Option<usize>,
+    /// Require every agent's approval, whatever `quorum` says
+    #[serde(default)]
+    pub unanimous: bool,
+}
+
+impl RiskTier {
+    pub fn matches(&self, code: &str) -> bool {
+        let code = code.to_lowercase();
+        self.patterns
+            .iter()
+            .any(|pattern| !pattern.is_empty() && code.contains(&pattern.to_lowercase()))
+    }
+
+    /// The quorum this tier sets for a panel of `panel_size`
+    pub fn quorum_for(&self, panel_size: usize) -> Option<usize> {
+        if self.unanimous {
+            Some(panel_size)
+        } else {
+            self.quorum
+        }
+    }
+}
+
+/// Handling of approvals that give little or no reasoning
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WeakApprovalMode {
+    /// Count the vote but report the review as weakly approved
+    #[default]
+    Flag,
+    /// Don't count the vote toward the majority
+    Discount,
+}
+
+/// Handling of a pass with exactly the required number of POSITIVE votes,
+/// e.g. 2 of 3
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MinimumMarginMode {
+    /// Pass as usual
+    #[default]
+    Pass,
+    /// Pass, but set `minimum_margin` and report it as a close call
+    Flag,
+    /// Re-review and take the majority of all runs, like a split rejection
+    Rerun,
+}
+
+impl std::str::FromStr for MinimumMarginMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "pass" => Ok(MinimumMarginMode::Pass),
+            "flag" => Ok(MinimumMarginMode::Flag),
+            "rerun" => Ok(MinimumMarginMode::Rerun),
+            other => Err(format!("Unknown minimum margin mode: {}", other)),
+        }
+    }
+}
+
+impl std::str::FromStr for WeakApprovalMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "flag" => Ok(WeakApprovalMode::Flag),
+            "discount" => Ok(WeakApprovalMode::Discount),
+            other => Err(format!("Unknown weak approval mode: {}", other)),
+        }
+    }
+}
+
+impl Default for CodeReviewConfig {
+    fn default() -> Self {
+        Self {
+            server_url: "ws://localhost:8080/review".to_string(),
+            app_id: APP_ID.to_string(),
+            app_secret: APP_SECRET.to_string(),
+            auth_token: None,
+            agents: AGENT_IDS.iter().map(|(name, id)| ReviewerAgent {
+                name: name.to_string(),
+                id: id.to_string(),
+            }).collect(),
+            agents_url: None,
+            agents_cache: None,
+            chunk_size: None,
+            warm_connections: 0,
+            min_approval_content: None,
+            weak_approval_mode: WeakApprovalMode::default(),
+            quorum: None,
+            risk_tiers: Vec::new(),
+            strict_verdict: false,
+            compile_check: false,
+            compile_checks: CompileCheck::defaults(),
+            allowed_languages: Vec::new(),
+            approval_evidence_limit: 3,
+            close_call_margin: 0,
+            required_approvers: Vec::new(),
+            weights: HashMap::new(),
+            minimum_margin_mode: MinimumMarginMode::default(),
+            heartbeat_interval: Some(Duration::from_secs(10)),
+            security_veto: false,
+            dedupe_chunks: false,
+            agent_retries: 0,
+            review_timeout: Some(REVIEW_TIMEOUT),
+            review_timeout_per_kb: Duration::ZERO,
+            review_timeout_max: None,
+            verdict_markers: VerdictMarkers::default(),
+            event_buffer: 64,
+            event_backpressure: Backpressure::default(),
+            rubric: None,
+            tests: None,
+            review_dump_dir: None,
+            review_cache_dir: None,
+            review_cache_ttl: None,
+            review_cache_max_entries: 1000,
+            wait_for_all: false,
+            settle_window: None,
+            message_types: MessageTypes::default(),
+            protocol_version: PROTOCOL_VERSION,
+            reconnect_attempts: 3,
+            reconnect_delay: Duration::from_millis(500),
+            content_encoding: ContentEncoding::default(),
+            rereview_on_split: 0,
+            verdict_overrides: HashMap::new(),
+            debug_agents: Vec::new(),
+            stream_output: false,
+        }
+    }
+}
+
+impl CodeReviewConfig {
+    /// Load the config from environment variables, falling back to the defaults.
+    ///
+    /// `MAGI_AGENT_IDS` is a comma-separated list of `name:id` pairs, e.g.
+    /// `security:d37c...,style:6634...`. An entry without a name keeps the
+    /// default persona name for its position. `MAGI_AGENTS_URL` names an
+    /// endpoint to fetch the panel from at startup and `MAGI_AGENTS_CACHE` a
+    /// file to keep the last fetched panel in. `MAGI_CHUNK_SIZE` enables
+    /// chunked submission above the given size in bytes, and
+    /// `MAGI_WARM_CONNECTIONS` keeps that many connections open ahead of use.
+    /// `MAGI_MIN_APPROVAL_CONTENT` and `MAGI_WEAK_APPROVAL_MODE` (`flag` or
+    /// `discount`) configure the check for terse approvals.
+    /// `MAGI_STRICT_VERDICT=true` requires explicit verdicts and
+    /// `MAGI_COMPILE_CHECK=true` enables the local compile check and
+    /// `MAGI_ALLOWED_LANGUAGES` (comma-separated) limits the languages
+    /// reviewed.
+    /// `MAGI_APPROVAL_EVIDENCE` sets how many sentences are quoted per
+    /// approving agent. `MAGI_CLOSE_CALL_MARGIN` and `MAGI_REQUIRED_APPROVERS`
+    /// (comma-separated agent names) define close calls, and
+    /// `MAGI_MINIMUM_MARGIN` (`pass`, `flag` or `rerun`) handles passes by
+    /// exactly the required votes.
+    /// `MAGI_HEARTBEAT_SECS` sets the progress log interval (0 disables it).
+    /// `MAGI_REVIEW_TIMEOUT_SECS` (or `CODE_REVIEW_TIMEOUT_SECS`; 0 disables
+    /// it), `MAGI_REVIEW_TIMEOUT_PER_KB_MS`
+    /// and `MAGI_REVIEW_TIMEOUT_MAX_SECS` set the size-scaled review timeout.
+    /// `MAGI_AGENT_RETRIES` resubmits to agents that error transiently, and
+    /// `MAGI_DEDUPE_CHUNKS=true` skips chunks the gateway re-sent.
+    /// `MAGI_SECURITY_VETO=true` lets a critical security issue veto approval.
+    /// `MAGI_AGENT_WEIGHTS` (comma-separated `name=weight` pairs) weights
+    /// the agents' votes.
+    /// `MAGI_POSITIVE_MARKERS`/`MAGI_NEGATIVE_MARKERS` (comma-separated)
+    /// replace the `POSITIVE`/`NEGATIVE` verdict tokens. `MAGI_EVENT_BUFFER`
+    /// and `MAGI_EVENT_BACKPRESSURE` (`drop_chunks` or `block`) configure the
+    /// review event channel. `MAGI_CONTENT_ENCODING` (`identity` or `base64`)
+    /// decodes agents' content and `MAGI_PROTOCOL_VERSION` pins the gateway
+    /// protocol version. `MAGI_RECONNECT_ATTEMPTS` and
+    /// `MAGI_RECONNECT_DELAY_MS` handle connections dropped mid-review.
+    /// `MAGI_RUBRIC_FILE` names a file whose contents
+    /// are sent as the review rubric. `MAGI_WAIT_FOR_ALL=true` waits for
+    /// every verdict, `MAGI_SETTLE_MS` then waits that long for trailing
+    /// chunks, and `MAGI_REVIEW_DUMP_DIR` saves the transcripts.
+    /// `MAGI_REVIEW_CACHE_DIR` caches verdicts on disk, for
+    /// `MAGI_REVIEW_CACHE_TTL_SECS` (0 keeps them) and up to
+    /// `MAGI_REVIEW_CACHE_MAX_ENTRIES` (0 is unlimited).
+    /// `MAGI_REREVIEW_ON_SPLIT` re-runs reviews lost by a single vote, and
+    /// `MAGI_DEBUG_AGENTS` (comma-separated names) logs those agents' frames,
+    /// and `MAGI_STREAM_OUTPUT=true` prints every reviewer's output live.
+    ///
+    /// Credentials come from `MAGI_APP_ID`/`MAGI_APP_SECRET`, overridden by
+    /// the file named in `MAGI_SECRETS_FILE` when it is set.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        config.apply_env();
+        config
+    }
+
+    /// Load `magi.toml` (when given), then apply environment overrides on top.
+    pub fn load(config_file: Option<&Path>) -> Result<Self, CodeReviewError> {
+        let mut config = Self::default();
+
+        if let Some(path) = config_file {
+            MagiConfig::load(path)?.apply(&mut config);
+        }
+
+        config.apply_env();
+        Ok(config)
+    }
+
+    /// Apply the environment variables described on `from_env`
+    pub fn apply_env(&mut self) {
+        if let Ok(server_url) = std::env::var("CODE_REVIEW_SERVER_URL") {
+            self.server_url = server_url;
+        }
+
+        if let Ok(app_id) = std::env::var("MAGI_APP_ID") {
+            self.app_id = app_id;
+        }
+
+        if let Ok(app_secret) = std::env::var("MAGI_APP_SECRET") {
+            self.app_secret = app_secret;
+        }
+
+        if let Ok(agent_ids) = std::env::var("MAGI_AGENT_IDS") {
+            let agents = parse_agent_ids(&agent_ids);
+            if !agents.is_empty() {
+                self.agents = agents;
+            }
+        }
+
+        if let Ok(url) = std::env::var("MAGI_AGENTS_URL") {
+            self.agents_url = Some(url);
+        }
+
+        if let Ok(cache) = std::env::var("MAGI_AGENTS_CACHE") {
+            self.agents_cache = Some(PathBuf::from(cache));
+        }
+
+        if let Some(chunk_size) = std::env::var("MAGI_CHUNK_SIZE").ok().and_then(|v| v.parse().ok()) {
+            self.chunk_size = Some(chunk_size);
+        }
+
+        if let Some(warm) = std::env::var("MAGI_WARM_CONNECTIONS").ok().and_then(|v| v.parse().ok()) {
+            self.warm_connections = warm;
+        }
+
+        if let Some(min_len) = std::env::var("MAGI_MIN_APPROVAL_CONTENT").ok().and_then(|v| v.parse().ok()) {
+            self.min_approval_content = Some(min_len);
+        }
+
+        if let Ok(mode) = std::env::var("MAGI_WEAK_APPROVAL_MODE") {
+            match mode.parse() {
+                Ok(mode) => self.weak_approval_mode = mode,
+                Err(e) => tracing::warn!(target: "rig-magi", "{}", e),
+            }
+        }
+
+        if let Some(strict) = env_bool("MAGI_STRICT_VERDICT") {
+            self.strict_verdict = strict;
+        }
+
+        if let Some(compile_check) = env_bool("MAGI_COMPILE_CHECK") {
+            self.compile_check = compile_check;
+        }
+
+        if let Ok(languages) = std::env::var("MAGI_ALLOWED_LANGUAGES") {
+            self.allowed_languages = split_list(&languages);
+        }
+
+        if let Some(limit) = std::env::var("MAGI_APPROVAL_EVIDENCE").ok().and_then(|v| v.parse().ok()) {
+            self.approval_evidence_limit = limit;
+        }
+
+        if let Some(margin) = std::env::var("MAGI_CLOSE_CALL_MARGIN").ok().and_then(|v| v.parse().ok()) {
+            self.close_call_margin = margin;
+        }
+
+        if let Ok(mode) = std::env::var("MAGI_MINIMUM_MARGIN") {
+            match mode.parse() {
+                Ok(mode) => self.minimum_margin_mode = mode,
+                Err(e) => tracing::warn!(target: "rig-magi", "{}", e),
+            }
+        }
+
+        if let Ok(approvers) = std::env::var("MAGI_REQUIRED_APPROVERS") {
+            self.required_approvers = split_list(&approvers);
+        }
+
+        if let Ok(weights) = std::env::var("MAGI_AGENT_WEIGHTS") {
+            match parse_weights(&weights) {
+                Ok(weights) => self.weights = weights,
+                Err(e) => tracing::warn!(target: "rig-magi", "{}", e),
+            }
+        }
+
+        if let Some(secs) = std::env::var("MAGI_HEARTBEAT_SECS").ok().and_then(|v| v.parse().ok()) {
+            self.heartbeat_interval = heartbeat_interval(secs);
+        }
+
+        if let Some(veto) = env_bool("MAGI_SECURITY_VETO") {
+            self.security_veto = veto;
+        }
+
+        if let Some(dedupe) = env_bool("MAGI_DEDUPE_CHUNKS") {
+            self.dedupe_chunks = dedupe;
+        }
+
+        if let Some(retries) = std::env::var("MAGI_AGENT_RETRIES").ok().and_then(|v| v.parse().ok()) {
+            self.agent_retries = retries;
+        }
+
+        // The MAGI_ name wins when both are set
+        let timeout_secs = ["CODE_REVIEW_TIMEOUT_SECS", "MAGI_REVIEW_TIMEOUT_SECS"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok().and_then(|v| v.parse::<u64>().ok()))
+            .last();
+        if let Some(secs) = timeout_secs {
+            self.review_timeout = (secs > 0).then(|| Duration::from_secs(secs));
+        }
+
+        if let Some(millis) = std::env::var("MAGI_REVIEW_TIMEOUT_PER_KB_MS").ok().and_then(|v| v.parse().ok()) {
+            self.review_timeout_per_kb = Duration::from_millis(millis);
+        }
+
+        if let Some(secs) = std::env::var("MAGI_REVIEW_TIMEOUT_MAX_SECS").ok().and_then(|v| v.parse().ok()) {
+            self.review_timeout_max = Some(Duration::from_secs(secs));
+        }
+
+        if let Ok(markers) = std::env::var("MAGI_POSITIVE_MARKERS") {
+            self.verdict_markers.positive = split_list(&markers);
+        }
+
+        if let Ok(markers) = std::env::var("MAGI_NEGATIVE_MARKERS") {
+            self.verdict_markers.negative = split_list(&markers);
+        }
+
+        if let Some(buffer) = std::env::var("MAGI_EVENT_BUFFER").ok().and_then(|v| v.parse().ok()) {
+            self.event_buffer = buffer;
+        }
+
+        if let Ok(mode) = std::env::var("MAGI_EVENT_BACKPRESSURE") {
+            match mode.parse() {
+                Ok(mode) => self.event_backpressure = mode,
+                Err(e) => tracing::warn!(target: "rig-magi", "{}", e),
+            }
+        }
+
+        if let Some(version) = std::env::var("MAGI_PROTOCOL_VERSION").ok().and_then(|v| v.parse().ok()) {
+            self.protocol_version = version;
+        }
+
+        if let Some(attempts) = std::env::var("MAGI_RECONNECT_ATTEMPTS").ok().and_then(|v| v.parse().ok()) {
+            self.reconnect_attempts = attempts;
+        }
+
+        if let Some(millis) = std::env::var("MAGI_RECONNECT_DELAY_MS").ok().and_then(|v| v.parse().ok()) {
+            self.reconnect_delay = Duration::from_millis(millis);
+        }
+
+        if let Ok(encoding) = std::env::var("MAGI_CONTENT_ENCODING") {
+            match encoding.parse() {
+                Ok(encoding) => self.content_encoding = encoding,
+                Err(e) => tracing::warn!(target: "rig-magi", "{}", e),
+            }
+        }
+
+        if let Ok(rubric_file) = std::env::var("MAGI_RUBRIC_FILE") {
+            if let Err(e) = self.load_rubric(Path::new(&rubric_file)) {
+                tracing::warn!(target: "rig-magi", "Ignoring rubric: {}", e);
+            }
+        }
+
+        if let Some(wait) = env_bool("MAGI_WAIT_FOR_ALL") {
+            self.wait_for_all = wait;
+        }
+
+        if let Some(millis) = std::env::var("MAGI_SETTLE_MS").ok().and_then(|v| v.parse().ok()) {
+            self.settle_window = settle_window(millis);
+        }
+
+        if let Some(reruns) = std::env::var("MAGI_REREVIEW_ON_SPLIT").ok().and_then(|v| v.parse().ok()) {
+            self.rereview_on_split = reruns;
+        }
+
+        if let Ok(agents) = std::env::var("MAGI_DEBUG_AGENTS") {
+            self.debug_agents = split_list(&agents);
+        }
+
+        if let Some(stream_output) = env_bool("MAGI_STREAM_OUTPUT") {
+            self.stream_output = stream_output;
+        }
+
+        if let Ok(dir) = std::env::var("MAGI_REVIEW_DUMP_DIR") {
+            self.review_dump_dir = Some(PathBuf::from(dir));
+        }
+
+        if let Ok(dir) = std::env::var("MAGI_REVIEW_CACHE_DIR") {
+            self.review_cache_dir = Some(PathBuf::from(dir));
+        }
+
+        if let Some(secs) = std::env::var("MAGI_REVIEW_CACHE_TTL_SECS").ok().and_then(|v| v.parse().ok()) {
+            self.review_cache_ttl = cache_ttl(secs);
+        }
+
+        if let Some(max) = std::env::var("MAGI_REVIEW_CACHE_MAX_ENTRIES").ok().and_then(|v| v.parse().ok()) {
+            self.review_cache_max_entries = max;
+        }
+
+        if let Ok(secrets_file) = std::env::var("MAGI_SECRETS_FILE") {
+            if let Err(e) = self.apply_secrets_file(&secrets_file) {
+                tracing::warn!(target: "rig-magi", "Ignoring secrets file: {}", e);
+            }
+        }
+    }
+
+    /// Use the contents of a file as the tests the code is judged against
+    pub fn load_tests(&mut self, path: &Path) -> Result<(), CodeReviewError> {
+        let tests = std::fs::read_to_string(path).map_err(|e| {
+            CodeReviewError::ConfigError(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+
+        self.tests = Some(tests).filter(|tests| !tests.trim().is_empty());
+        Ok(())
+    }
+
+    /// Use the contents of a file as the review rubric
+    pub fn load_rubric(&mut self, path: &Path) -> Result<(), CodeReviewError> {
+        let rubric = std::fs::read_to_string(path).map_err(|e| {
+            CodeReviewError::ConfigError(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+
+        self.rubric = Some(rubric).filter(|rubric| !rubric.trim().is_empty());
+        Ok(())
+    }
+
+    /// The first risk tier the code matches, if any
+    pub fn risk_tier(&self, code: &str) -> Option<&RiskTier> {
+        self.risk_tiers.iter().find(|tier| tier.matches(code))
+    }
+
+    /// POSITIVE votes needed for the panel to pass the code
+    pub fn required_positive(&self) -> usize {
+        self.required_positive_of(self.agents.len())
+    }
+
+    /// POSITIVE votes needed when only `responding` agents answered without
+    /// an error: the configured quorum, or else a majority of them
+    pub fn required_positive_of(&self, responding: usize) -> usize {
+        self.quorum.unwrap_or(responding / 2 + 1)
+    }
+
+    /// The review timeout for a submission of `code_len` bytes:
+    /// `review_timeout + review_timeout_per_kb * code_len / 1024`, capped at
+    /// `review_timeout_max`. `None` when there is no base timeout.
+    pub fn review_timeout_for(&self, code_len: usize) -> Option<Duration> {
+        let base = self.review_timeout?;
+        let scaled = base.saturating_add(self.review_timeout_per_kb.mul_f64(code_len as f64 / 1024.0));
+        Some(match self.review_timeout_max {
+            Some(max) => scaled.min(max),
+            None => scaled,
+        })
+    }
+
+    /// Whether code in `language` may be reviewed
+    pub fn language_allowed(&self, language: &str) -> bool {
+        self.allowed_languages.is_empty()
+            || self.allowed_languages.iter().any(|allowed| allowed.eq_ignore_ascii_case(language.trim()))
+    }
+
+    /// Check the panel is non-empty with unique names and agent ids. Agents
+    /// are looked up by id and reported by name, so a duplicate would leave
+    /// a slot that never completes.
+    pub fn check_panel(&self) -> Result<(), CodeReviewError> {
+        if self.agents.is_empty() {
+            return Err(CodeReviewError::ConfigError("Reviewer panel is empty".to_string()));
+        }
+
+        for (index, agent) in self.agents.iter().enumerate() {
+            for other in &self.agents[..index] {
+                if other.id == agent.id {
+                    return Err(CodeReviewError::ConfigError(format!(
+                        "Reviewers {} and {} have the same agent id {}",
+                        other.name, agent.name, agent.id
+                    )));
+                }
+                if other.name == agent.name {
+                    return Err(CodeReviewError::ConfigError(format!(
+                        "Two reviewers are named {} (agent ids {} and {})",
+                        agent.name, other.id, agent.id
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the config is usable before contacting the gateway
+    pub fn validate(&self) -> Result<(), CodeReviewError> {
+        if self.app_id.trim().is_empty() || self.app_secret.trim().is_empty() {
+            return Err(CodeReviewError::ConfigError("Gateway app id and secret must be set".to_string()));
+        }
+
+        self.check_panel()?;
+
+        if let Some(quorum) = self.quorum {
+            if quorum == 0 || quorum > self.agents.len() {
+                return Err(CodeReviewError::ConfigError(format!(
+                    "Quorum must be between 1 and the panel size ({}), got {}",
+                    self.agents.len(),
+                    quorum
+                )));
+            }
+        }
+
+        for tier in &self.risk_tiers {
+            if tier.patterns.iter().all(|pattern| pattern.is_empty()) {
+                return Err(CodeReviewError::ConfigError(format!("Risk tier {} has no patterns", tier.name)));
+            }
+            if let Some(quorum) = tier.quorum.filter(|quorum| *quorum == 0 || *quorum > self.agents.len()) {
+                return Err(CodeReviewError::ConfigError(format!(
+                    "Risk tier {} quorum must be between 1 and the panel size ({}), got {}",
+                    tier.name,
+                    self.agents.len(),
+                    quorum
+                )));
+            }
+        }
+
+        if let Some(unknown) = self
+            .required_approvers
+            .iter()
+            .find(|name| !self.agents.iter().any(|agent| &agent.name == *name))
+        {
+            return Err(CodeReviewError::ConfigError(format!(
+                "Required approver {} is not on the panel",
+                unknown
+            )));
+        }
+
+        if let Some(unknown) = self
+            .weights
+            .keys()
+            .find(|name| !self.agents.iter().any(|agent| &agent.name == *name))
+        {
+            return Err(CodeReviewError::ConfigError(format!("Weighted agent {} is not on the panel", unknown)));
+        }
+        if !self.weights.is_empty() && self.agents.iter().all(|agent| self.weights.get(&agent.name) == Some(&0)) {
+            return Err(CodeReviewError::ConfigError("Agent weights must not all be 0".to_string()));
+        }
+
+        self.verdict_markers.validate().map_err(CodeReviewError::ConfigError)?;
+
+        if self.event_buffer == 0 {
+            return Err(CodeReviewError::ConfigError("Event buffer must hold at least one event".to_string()));
+        }
+
+        // An http(s) URL is upgraded, with a warning
+        normalize_ws_url(&self.server_url).map_err(CodeReviewError::ConfigError)?;
+
+        Ok(())
+    }
+
+    /// Override the gateway URL and credentials from a secrets file.
+    ///
+    /// The file uses `.env` syntax and may set `CODE_REVIEW_SERVER_URL`,
+    /// `MAGI_APP_ID` and `MAGI_APP_SECRET`; other keys are ignored.
+    pub fn apply_secrets_file(&mut self, path: impl AsRef<Path>) -> Result<(), CodeReviewError> {
+        let path = path.as_ref();
+        warn_if_world_readable(path);
+
+        let entries = dotenv::from_path_iter(path).map_err(|e| {
+            CodeReviewError::ConfigError(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+
+        for entry in entries {
+            let (key, value) = entry.map_err(|e| {
+                CodeReviewError::ConfigError(format!("Failed to parse {}: {}", path.display(), e))
+            })?;
+
+            match key.as_str() {
+                "CODE_REVIEW_SERVER_URL" => self.server_url = value,
+                "MAGI_APP_ID" => self.app_id = value,
+                "MAGI_APP_SECRET" => self.app_secret = value,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Comma-separated list, ignoring blanks
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Comma-separated `name=weight` pairs, e.g. `casper=2,melchior=1`
+fn parse_weights(value: &str) -> Result<HashMap<String, u32>, String> {
+    split_list(value)
+        .iter()
+        .map(|pair| {
+            let (name, weight) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Agent weight {} is not name=weight", pair))?;
+            let weight = weight
+                .trim()
+                .parse()
+                .map_err(|_| format!("Agent weight for {} is not a whole number: {}", name.trim(), weight.trim()))?;
+            Ok((name.trim().to_string(), weight))
+        })
+        .collect()
+}
+
+/// A boolean environment variable: `true`, `1`, `yes` or `on` and `false`,
+/// `0`, `no` or `off`, in any case. `None` when unset or unrecognized, with
+/// a warning for the latter.
+pub fn env_bool(name: &str) -> Option<bool> {
+    let value = std::env::var(name).ok()?;
+    let parsed = parse_bool(&value);
+    if parsed.is_none() {
+        tracing::warn!(target: "rig-magi", "Ignoring {}={}, expected true or false", name, value);
+    }
+    parsed
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+// 0 milliseconds turns the settle window off
+fn settle_window(millis: u64) -> Option<Duration> {
+    (millis > 0).then(|| Duration::from_millis(millis))
+}
+
+// 0 seconds keeps cached verdicts until they are evicted
+fn cache_ttl(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+// 0 seconds turns the heartbeat off
+fn heartbeat_interval(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+#[cfg(unix)]
+fn warn_if_world_readable(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Ok(metadata) = std::fs::metadata(path) {
+        if metadata.permissions().mode() & 0o004 != 0 {
+            tracing::warn!(target: "rig-magi",
+                "Secrets file {} is world-readable, consider `chmod 600`",
+                path.display()
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_world_readable(_path: &Path) {}
+
+fn parse_agent_ids(value: &str) -> Vec<ReviewerAgent> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .enumerate()
+        .map(|(index, entry)| match entry.split_once(':') {
+            Some((name, id)) => ReviewerAgent {
+                name: name.trim().to_string(),
+                id: id.trim().to_string(),
+            },
+            None => ReviewerAgent {
+                name: AGENT_IDS
+                    .get(index)
+                    .map(|(name, _)| name.to_string())
+                    .unwrap_or_else(|| format!("agent{}", index + 1)),
+                id: entry.to_string(),
+            },
+        })
+        .collect()
+}
+
+/// Contents of a `magi.toml` config file. Every field is optional and
+/// overrides the built-in default; environment variables override the file.
+///
+/// ```toml
+/// [gateway]
+/// url = "wss://magi.example.com/review"
+/// app_id = "..."
+///
+/// [[agents]]
+/// name = "melchior"
+/// id = "d37c1cc8-bcc4-4b73-9f49-a93a30971f2c"
+///
+/// [review]
+/// quorum = 2
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MagiConfig {
+    #[serde(default)]
+    pub gateway: GatewaySection,
+    #[serde(default)]
+    pub agents: Vec<ReviewerAgent>,
+    #[serde(default)]
+    pub review: ReviewSection,
+    /// Replaces the built-in compile checks when non-empty
+    #[serde(default)]
+    pub compile_checks: Vec<CompileCheck>,
+    /// Code SHA-256 to forced verdict
+    #[serde(default)]
+    pub verdict_overrides: HashMap<String, MAGIDecision>,
+    /// Replaces any risk tiers when non-empty
+    #[serde(default)]
+    pub risk_tiers: Vec<RiskTier>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GatewaySection {
+    pub url: Option<String>,
+    pub app_id: Option<String>,
+    pub app_secret: Option<String>,
+    pub chunk_size: Option<usize>,
+    pub warm_connections: Option<usize>,
+    pub message_types: Option<MessageTypes>,
+    pub content_encoding: Option<ContentEncoding>,
+    pub protocol_version: Option<u32>,
+    pub reconnect_attempts: Option<u8>,
+    pub reconnect_delay_ms: Option<u64>,
+    pub agents_url: Option<String>,
+    pub agents_cache: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReviewSection {
+    pub quorum: Option<usize>,
+    pub min_approval_content: Option<usize>,
+    pub weak_approval_mode: Option<WeakApprovalMode>,
+    pub minimum_margin: Option<MinimumMarginMode>,
+    pub strict_verdict: Option<bool>,
+    pub compile_check: Option<bool>,
+    pub allowed_languages: Option<Vec<String>>,
+    pub approval_evidence: Option<usize>,
+    pub close_call_margin: Option<usize>,
+    pub required_approvers: Option<Vec<String>>,
+    pub weights: Option<HashMap<String, u32>>,
+    pub heartbeat_secs: Option<u64>,
+    pub security_veto: Option<bool>,
+    pub dedupe_chunks: Option<bool>,
+    pub agent_retries: Option<u8>,
+    pub review_timeout_secs: Option<u64>,
+    pub review_timeout_per_kb_ms: Option<u64>,
+    pub review_timeout_max_secs: Option<u64>,
+    pub positive_markers: Option<Vec<String>>,
+    pub negative_markers: Option<Vec<String>>,
+    pub event_buffer: Option<usize>,
+    pub event_backpressure: Option<Backpressure>,
+    pub rubric: Option<String>,
+    pub review_dump_dir: Option<PathBuf>,
+    pub cache_dir: Option<PathBuf>,
+    pub cache_ttl_secs: Option<u64>,
+    pub cache_max_entries: Option<usize>,
+    pub wait_for_all: Option<bool>,
+    pub settle_ms: Option<u64>,
+    pub rereview_on_split: Option<u8>,
+    pub debug_agents: Option<Vec<String>>,
+    pub stream_output: Option<bool>,
+}
+
+impl MagiConfig {
+    pub fn load(path: &Path) -> Result<Self, CodeReviewError> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            CodeReviewError::ConfigError(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+
+        toml::from_str(&text).map_err(|e| {
+            CodeReviewError::ConfigError(format!("Failed to parse {}: {}", path.display(), e))
+        })
+    }
+
+    pub fn apply(self, config: &mut CodeReviewConfig) {
+        if let Some(url) = self.gateway.url {
+            config.server_url = url;
+        }
+        if let Some(app_id) = self.gateway.app_id {
+            config.app_id = app_id;
+        }
+        if let Some(app_secret) = self.gateway.app_secret {
+            config.app_secret = app_secret;
+        }
+        if self.gateway.chunk_size.is_some() {
+            config.chunk_size = self.gateway.chunk_size;
+        }
+        if let Some(warm) = self.gateway.warm_connections {
+            config.warm_connections = warm;
+        }
+        if let Some(message_types) = self.gateway.message_types {
+            config.message_types = message_types;
+        }
+        if let Some(encoding) = self.gateway.content_encoding {
+            config.content_encoding = encoding;
+        }
+        if let Some(version) = self.gateway.protocol_version {
+            config.protocol_version = version;
+        }
+        if let Some(attempts) = self.gateway.reconnect_attempts {
+            config.reconnect_attempts = attempts;
+        }
+        if let Some(millis) = self.gateway.reconnect_delay_ms {
+            config.reconnect_delay = Duration::from_millis(millis);
+        }
+        if self.gateway.agents_url.is_some() {
+            config.agents_url = self.gateway.agents_url;
+        }
+        if self.gateway.agents_cache.is_some() {
+            config.agents_cache = self.gateway.agents_cache;
+        }
+        if !self.agents.is_empty() {
+            config.agents = self.agents;
+        }
+        if self.review.quorum.is_some() {
+            config.quorum = self.review.quorum;
+        }
+        if self.review.min_approval_content.is_some() {
+            config.min_approval_content = self.review.min_approval_content;
+        }
+        if let Some(mode) = self.review.minimum_margin {
+            config.minimum_margin_mode = mode;
+        }
+        if let Some(mode) = self.review.weak_approval_mode {
+            config.weak_approval_mode = mode;
+        }
+        if let Some(strict) = self.review.strict_verdict {
+            config.strict_verdict = strict;
+        }
+        if let Some(compile_check) = self.review.compile_check {
+            config.compile_check = compile_check;
+        }
+        if let Some(languages) = self.review.allowed_languages {
+            config.allowed_languages = languages;
+        }
+        if let Some(limit) = self.review.approval_evidence {
+            config.approval_evidence_limit = limit;
+        }
+        if let Some(margin) = self.review.close_call_margin {
+            config.close_call_margin = margin;
+        }
+        if let Some(approvers) = self.review.required_approvers {
+            config.required_approvers = approvers;
+        }
+        if let Some(weights) = self.review.weights {
+            config.weights = weights;
+        }
+        if let Some(secs) = self.review.heartbeat_secs {
+            config.heartbeat_interval = heartbeat_interval(secs);
+        }
+        if let Some(veto) = self.review.security_veto {
+            config.security_veto = veto;
+        }
+        if let Some(dedupe) = self.review.dedupe_chunks {
+            config.dedupe_chunks = dedupe;
+        }
+        if let Some(retries) = self.review.agent_retries {
+            config.agent_retries = retries;
+        }
+        if let Some(secs) = self.review.review_timeout_secs {
+            config.review_timeout = (secs > 0).then(|| Duration::from_secs(secs));
+        }
+        if let Some(millis) = self.review.review_timeout_per_kb_ms {
+            config.review_timeout_per_kb = Duration::from_millis(millis);
+        }
+        if let Some(secs) = self.review.review_timeout_max_secs {
+            config.review_timeout_max = Some(Duration::from_secs(secs));
+        }
+        if let Some(markers) = self.review.positive_markers {
+            config.verdict_markers.positive = markers;
+        }
+        if let Some(markers) = self.review.negative_markers {
+            config.verdict_markers.negative = markers;
+        }
+        if let Some(buffer) = self.review.event_buffer {
+            config.event_buffer = buffer;
+        }
+        if let Some(mode) = self.review.event_backpressure {
+            config.event_backpressure = mode;
+        }
+        if self.review.rubric.is_some() {
+            config.rubric = self.review.rubric;
+        }
+        if let Some(agents) = self.review.debug_agents {
+            config.debug_agents = agents;
+        }
+        if let Some(stream_output) = self.review.stream_output {
+            config.stream_output = stream_output;
+        }
+        if let Some(reruns) = self.review.rereview_on_split {
+            config.rereview_on_split = reruns;
+        }
+        if let Some(wait) = self.review.wait_for_all {
+            config.wait_for_all = wait;
+        }
+        if let Some(millis) = self.review.settle_ms {
+            config.settle_window = settle_window(millis);
+        }
+        if self.review.review_dump_dir.is_some() {
+            config.review_dump_dir = self.review.review_dump_dir;
+        }
+        if self.review.cache_dir.is_some() {
+            config.review_cache_dir = self.review.cache_dir;
+        }
+        if let Some(secs) = self.review.cache_ttl_secs {
+            config.review_cache_ttl = cache_ttl(secs);
+        }
+        if let Some(max) = self.review.cache_max_entries {
+            config.review_cache_max_entries = max;
+        }
+        if !self.compile_checks.is_empty() {
+            config.compile_checks = self.compile_checks;
+        }
+        if !self.risk_tiers.is_empty() {
+            config.risk_tiers = self.risk_tiers;
+        }
+        // Keys are compared against lowercase hex digests
+        config.verdict_overrides.extend(
+            self.verdict_overrides
+                .into_iter()
+                .map(|(hash, decision)| (hash.trim().to_lowercase(), decision)),
+        );
+    }
+}
+
+/// Resolve the config file: an explicit path, then `MAGI_CONFIG`, then
+/// `./magi.toml` if it exists.
+pub fn resolve_config_path(explicit: Option<PathBuf>) -> Option<PathBuf> {
+    explicit
+        .or_else(|| std::env::var("MAGI_CONFIG").ok().map(PathBuf::from))
+        .or_else(|| {
+            let default = PathBuf::from("magi.toml");
+            default.exists().then_some(default)
+        })
+}
+
+/// Load and validate a config, returning a short human-readable summary
+pub fn check(config_file: Option<&Path>) -> Result<String, CodeReviewError> {
+    let config = CodeReviewConfig::load(config_file)?;
+    config.validate()?;
+
+    let agents: Vec<&str> = config.agents.iter().map(|agent| agent.name.as_str()).collect();
+    Ok(format!(
+        "Config OK ({})\n  gateway: {}\n  agents: {}\n  quorum: {} of {}",
+        config_file.map(|path| path.display().to_string()).unwrap_or_else(|| "defaults and environment".to_string()),
+        redact::url(&config.server_url),
+        agents.join(", "),
+        config.required_positive(),
+        config.agents.len()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn agent_ids_take_names_or_keep_the_default_persona() {
+        let agents = parse_agent_ids("security:sec-id, style : style-id,,bare-id");
+
+        let agents: Vec<(&str, &str)> = agents.iter().map(|agent| (agent.name.as_str(), agent.id.as_str())).collect();
+        assert_eq!(agents, [("security", "sec-id"), ("style", "style-id"), ("casper", "bare-id")]);
+    }
+
+    // A secrets file in a fresh directory, readable by its owner only
+    fn secrets_file(contents: &str) -> PathBuf {
+        let path = test_util::temp_dir().join("magi.env");
+        std::fs::write(&path, contents).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn secrets_file_wins_over_configured_credentials() {
+        let path = secrets_file("MAGI_APP_ID=file-app\nMAGI_APP_SECRET=file-secret\nMAGI_QUORUM=1\n");
+        let mut config = CodeReviewConfig {
+            app_id: "env-app".to_string(),
+            app_secret: "env-secret".to_string(),
+            ..CodeReviewConfig::default()
+        };
+
+        config.apply_secrets_file(&path).unwrap();
+
+        assert_eq!(config.app_id, "file-app");
+        assert_eq!(config.app_secret, "file-secret");
+        assert_eq!(config.server_url, CodeReviewConfig::default().server_url);
+        assert_eq!(config.quorum, None);
+    }
+
+    #[test]
+    fn missing_secrets_file_is_an_error() {
+        let path = test_util::temp_dir().join("missing.env");
+
+        let result = CodeReviewConfig::default().apply_secrets_file(&path);
+
+        assert!(matches!(result, Err(CodeReviewError::ConfigError(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn world_readable_secrets_file_is_warned_about() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = secrets_file("MAGI_APP_ID=file-app\n");
+        let private = test_util::capture_logs(|| CodeReviewConfig::default().apply_secrets_file(&path).unwrap());
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let shared = test_util::capture_logs(|| CodeReviewConfig::default().apply_secrets_file(&path).unwrap());
+
+        assert!(!private.contains("world-readable"));
+        assert!(shared.contains("world-readable"));
+    }
+
+    // A config from `magi.toml` contents over the defaults, validated, without
+    // the environment
+    fn from_toml(text: &str) -> Result<CodeReviewConfig, CodeReviewError> {
+        let file: MagiConfig = toml::from_str(text).map_err(|e| CodeReviewError::ConfigError(e.to_string()))?;
+        let mut config = CodeReviewConfig::default();
+        file.apply(&mut config);
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn valid_config_file_is_applied() {
+        let config = from_toml(
+            r#"
+[gateway]
+url = "wss://magi.example.com/review"
+
+[[agents]]
+name = "security"
+id = "sec-id"
+
+[[agents]]
+name = "style"
+id = "style-id"
+
+[review]
+quorum = 2
+required_approvers = ["security"]
+weights = { style = 2 }
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.server_url, "wss://magi.example.com/review");
+        let names: Vec<&str> = config.agents.iter().map(|agent| agent.name.as_str()).collect();
+        assert_eq!(names, ["security", "style"]);
+        assert_eq!(config.required_positive(), 2);
+        assert_eq!(config.weights.get("style"), Some(&2));
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        let invalid = [
+            "[review]\nquorum = 4\n",
+            "[review]\nquorum = 0\n",
+            "[review]\nrequired_approvers = [\"nobody\"]\n",
+            "[review]\nweights = { nobody = 2 }\n",
+            "[review]\nweights = { melchior = 0, balthasar = 0, casper = 0 }\n",
+            "[review]\nunknown_key = 1\n",
+            "[review]\npositive_markers = [\"OK\"]\nnegative_markers = [\"ok\"]\n",
+            "[review]\nevent_buffer = 0\n",
+            "[gateway]\nurl = \"ftp://magi.example.com\"\n",
+            "[gateway]\napp_secret = \" \"\n",
+            "[[agents]]\nname = \"a\"\nid = \"same\"\n\n[[agents]]\nname = \"b\"\nid = \"same\"\n",
+            "[[risk_tiers]]\nname = \"auth\"\npatterns = [\"\"]\n",
+        ];
+
+        for text in invalid {
+            assert!(from_toml(text).is_err(), "accepted {:?}", text);
+        }
+    }
+
+    #[test]
+    fn check_reports_an_unreadable_file() {
+        let path = test_util::temp_dir().join("missing.toml");
+
+        assert!(matches!(check(Some(&path)), Err(CodeReviewError::ConfigError(_))));
+    }
+
+    #[test]
+    fn verdict_override_hashes_are_lowercased() {
+        let config = from_toml("[verdict_overrides]\nABCDEF = \"POSITIVE\"\n").unwrap();
+
+        assert_eq!(config.verdict_overrides.get("abcdef"), Some(&MAGIDecision::POSITIVE));
+    }
+
+    fn agent(name: &str, id: &str) -> ReviewerAgent {
+        ReviewerAgent {
+            name: name.to_string(),
+            id: id.to_string(),
+        }
+    }
+
+    #[test]
+    fn duplicated_agents_are_named_in_the_error() {
+        let same_id = CodeReviewConfig {
+            agents: vec![agent("melchior", "one"), agent("balthasar", "two"), agent("casper", "one")],
+            ..CodeReviewConfig::default()
+        };
+        let same_name = CodeReviewConfig {
+            agents: vec![agent("melchior", "one"), agent("melchior", "two")],
+            ..CodeReviewConfig::default()
+        };
+
+        let Err(CodeReviewError::ConfigError(message)) = same_id.check_panel() else {
+            panic!("accepted a repeated agent id");
+        };
+        assert_eq!(message, "Reviewers melchior and casper have the same agent id one");
+        let Err(CodeReviewError::ConfigError(message)) = same_name.check_panel() else {
+            panic!("accepted a repeated name");
+        };
+        assert_eq!(message, "Two reviewers are named melchior (agent ids one and two)");
+        assert!(CodeReviewConfig::default().check_panel().is_ok());
+    }
+
+    #[test]
+    fn review_timeout_scales_with_code_size_up_to_the_cap() {
+        let config = CodeReviewConfig {
+            review_timeout: Some(Duration::from_secs(10)),
+            review_timeout_per_kb: Duration::from_millis(500),
+            review_timeout_max: Some(Duration::from_secs(120)),
+            ..CodeReviewConfig::default()
+        };
+
+        assert_eq!(config.review_timeout_for(0), Some(Duration::from_secs(10)));
+        assert_eq!(config.review_timeout_for(10 * 1024), Some(Duration::from_secs(15)));
+        assert_eq!(config.review_timeout_for(1024 * 1024), Some(Duration::from_secs(120)));
+        assert_eq!(CodeReviewConfig::default().review_timeout_for(1024 * 1024), Some(REVIEW_TIMEOUT));
+    }
+
+    #[test]
+    fn boolean_env_values_are_read_in_any_case() {
+        for value in ["true", "TRUE", "1", "yes", " On "] {
+            assert_eq!(parse_bool(value), Some(true), "{}", value);
+        }
+        for value in ["false", "False", "0", "no", "OFF"] {
+            assert_eq!(parse_bool(value), Some(false), "{}", value);
+        }
+        assert_eq!(parse_bool("enabled"), None);
+        assert_eq!(parse_bool(""), None);
+    }
+
+    #[test]
+    fn content_is_decoded_by_its_encoding() {
+        assert_eq!(ContentEncoding::Identity.decode("UE9TSVRJVkU="), Ok("UE9TSVRJVkU=".to_string()));
+        assert_eq!(ContentEncoding::Base64.decode(" UE9TSVRJVkU=\n"), Ok("POSITIVE".to_string()));
+        assert!(ContentEncoding::Base64.decode("not base64!").is_err());
+        assert!(ContentEncoding::Base64.decode("/w==").unwrap_err().contains("not UTF-8"));
+    }
+
+    #[test]
+    fn the_first_matching_risk_tier_is_chosen() {
+        let config = from_toml(
+            r#"
+[[risk_tiers]]
+name = "high"
+patterns = ["password", "crypto"]
+unanimous = true
+
+[[risk_tiers]]
+name = "low"
+patterns = ["fn get_"]
+quorum = 1
+"#,
+        )
+        .unwrap();
+
+        let tier = |code| config.risk_tier(code).map(|tier| tier.name.as_str());
+        assert_eq!(tier("use Crypto::hash;"), Some("high"));
+        assert_eq!(tier("fn get_password() {}"), Some("high"));
+        assert_eq!(tier("fn get_name() {}"), Some("low"));
+        assert_eq!(tier("fn add() {}"), None);
+        assert_eq!(config.risk_tiers[0].quorum_for(3), Some(3));
+        assert_eq!(config.risk_tiers[1].quorum_for(3), Some(1));
+    }
+}
@@ -0,0 +1,213 @@
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::{CodeReviewConfig, ReviewerAgent};
+use crate::session::write_atomically;
+use crate::tools::code_review::CodeReviewError;
+use crate::tools::redact;
+
+// Startup shouldn't hang on an unresponsive endpoint
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The endpoint may return the list bare or wrapped in an object; fields
+// other than `name` and `id` (e.g. `role`) are ignored
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PanelResponse {
+    Wrapped { agents: Vec<ReviewerAgent> },
+    Bare(Vec<ReviewerAgent>),
+}
+
+impl PanelResponse {
+    fn into_agents(self) -> Vec<ReviewerAgent> {
+        match self {
+            PanelResponse::Wrapped { agents } | PanelResponse::Bare(agents) => agents,
+        }
+    }
+}
+
+/// Replace the panel with the one listed at `agents_url`, when set.
+///
+/// A fetched panel is saved to `agents_cache`. If the fetch fails, the cached
+/// panel is used instead, and failing that the configured panel is kept.
+pub async fn discover_panel(config: &mut CodeReviewConfig) {
+    let Some(url) = config.agents_url.clone() else {
+        return;
+    };
+
+    match fetch_panel(&url).await {
+        Ok(agents) => {
+            tracing::info!(target: "rig-magi", "Discovered {} reviewers at {}", agents.len(), redact::url(&url));
+            if let Some(cache) = &config.agents_cache {
+                if let Err(e) = save_cache(cache, &agents) {
+                    tracing::warn!(target: "rig-magi", "Failed to cache the panel: {}", e);
+                }
+            }
+            config.agents = agents;
+        }
+        Err(e) => {
+            tracing::warn!(target: "rig-magi", "Panel discovery failed: {}", e);
+            match config.agents_cache.as_deref().map(load_cache) {
+                Some(Ok(agents)) => {
+                    tracing::info!(target: "rig-magi", "Using the cached panel of {} reviewers", agents.len());
+                    config.agents = agents;
+                }
+                Some(Err(e)) => {
+                    tracing::warn!(target: "rig-magi", "No usable cached panel, keeping the configured one: {}", e);
+                }
+                None => tracing::info!(target: "rig-magi", "Keeping the configured panel"),
+            }
+        }
+    }
+}
+
+/// Fetch the panel from the endpoint. An empty list is an error, since a
+/// review needs at least one agent. Errors show the URL redacted.
+pub async fn fetch_panel(url: &str) -> Result<Vec<ReviewerAgent>, CodeReviewError> {
+    let shown_url = redact::url(url);
+    let client = reqwest::Client::builder().timeout(FETCH_TIMEOUT).build().map_err(|e| {
+        CodeReviewError::ConnectionError(format!("Failed to build HTTP client: {}", e))
+    })?;
+
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| CodeReviewError::ConnectionError(format!("Failed to fetch {}: {}", shown_url, redact::params(&e.to_string()))))?;
+
+    let agents = response
+        .json::<PanelResponse>()
+        .await
+        .map_err(|e| CodeReviewError::SerializationError(format!("Invalid panel from {}: {}", shown_url, redact::params(&e.to_string()))))?
+        .into_agents();
+
+    if agents.is_empty() {
+        return Err(CodeReviewError::ConfigError(format!("{} listed no reviewers", shown_url)));
+    }
+    Ok(agents)
+}
+
+fn save_cache(path: &Path, agents: &[ReviewerAgent]) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(agents).map_err(|e| e.to_string())?;
+    write_atomically(path, &json).map_err(|e| e.to_string())
+}
+
+fn load_cache(path: &Path) -> Result<Vec<ReviewerAgent>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let agents: Vec<ReviewerAgent> =
+        serde_json::from_str(&text).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+
+    if agents.is_empty() {
+        return Err(format!("{} lists no reviewers", path.display()));
+    }
+    Ok(agents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // An HTTP endpoint answering every request with `status` and `body`
+    async fn serve(status: &'static str, body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/agents", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request).await;
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    fn names(config: &CodeReviewConfig) -> Vec<&str> {
+        config.agents.iter().map(|agent| agent.name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn the_fetched_panel_replaces_the_configured_one_and_is_cached() {
+        let url = serve(
+            "200 OK",
+            r#"{"agents": [{"name": "security", "id": "sec-id", "role": "audit"}, {"name": "style", "id": "style-id"}]}"#,
+        )
+        .await;
+        let cache = test_util::temp_dir().join("agents.json");
+        let mut config = CodeReviewConfig {
+            agents_url: Some(url),
+            agents_cache: Some(cache.clone()),
+            ..CodeReviewConfig::default()
+        };
+
+        discover_panel(&mut config).await;
+
+        assert_eq!(names(&config), ["security", "style"]);
+        assert_eq!(load_cache(&cache).unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_failed_fetch_falls_back_to_the_cache_then_the_config() {
+        let url = serve("500 Internal Server Error", "{}").await;
+        let cache = test_util::temp_dir().join("agents.json");
+        save_cache(
+            &cache,
+            &[ReviewerAgent {
+                name: "cached".to_string(),
+                id: "cached-id".to_string(),
+            }],
+        )
+        .unwrap();
+        let mut cached = CodeReviewConfig {
+            agents_url: Some(url.clone()),
+            agents_cache: Some(cache),
+            ..CodeReviewConfig::default()
+        };
+        let mut configured = CodeReviewConfig {
+            agents_url: Some(url),
+            ..CodeReviewConfig::default()
+        };
+
+        discover_panel(&mut cached).await;
+        discover_panel(&mut configured).await;
+
+        assert_eq!(names(&cached), ["cached"]);
+        assert_eq!(names(&configured), ["melchior", "balthasar", "casper"]);
+    }
+
+    #[test]
+    fn the_agents_url_is_logged_and_reported_redacted() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let url = runtime.block_on(serve("500 Internal Server Error", "{}")) + "?token=s3cr3t";
+
+        let mut result = None;
+        let logs = test_util::capture_logs(|| result = Some(runtime.block_on(fetch_panel(&url))));
+
+        let Some(Err(CodeReviewError::ConnectionError(message))) = result else {
+            panic!("fetched a panel from a failing endpoint");
+        };
+        assert!(message.contains("token=***"));
+        assert!(!message.contains("s3cr3t") && !logs.contains("s3cr3t"));
+
+        let url = runtime.block_on(serve("200 OK", r#"[{"name": "style", "id": "style-id"}]"#)) + "?token=s3cr3t";
+        let mut config = CodeReviewConfig {
+            agents_url: Some(url),
+            ..CodeReviewConfig::default()
+        };
+        let logs = test_util::capture_logs(|| runtime.block_on(discover_panel(&mut config)));
+
+        assert!(logs.contains("Discovered 1 reviewers at http://127.0.0.1:"));
+        assert!(logs.contains("token=***") && !logs.contains("s3cr3t"));
+    }
+}
@@ -0,0 +1,27 @@
+//! MAGI code review for rig agents: a code generation agent whose every
+//! answer is reviewed by a panel of agents over the MAGI gateway, and
+//! improved until the panel approves it.
+//!
+//! [`build_code_agent`] wires a provider's agent builder to the
+//! [`CodeReviewTool`]; [`MultiTurnAgent`] runs the generate, review and
+//! improve loop. The `rig-demo` binary is an interactive wrapper around both.
+
+pub mod agent;
+pub mod config;
+#[cfg(feature = "discovery")]
+pub mod discovery;
+pub mod session;
+pub mod status;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+#[cfg(test)]
+mod test_util;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod tools;
+
+pub use agent::{build_code_agent, MultiTurnAgent, MultiTurnError};
+pub use config::CodeReviewConfig;
+pub use tools::code_review::{
+    CodeReviewArgs, CodeReviewOutput, CodeReviewTool, MAGIAgentState, MAGIDecision, MAGIMessage, MAGISystemState,
+};
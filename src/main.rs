@@ -1,283 +1,820 @@
 use dotenv::dotenv;
-use rig::{
-    cli_chatbot::cli_chatbot,
-    agent::Agent,
-    completion::{self, Prompt, Completion, PromptError, ToolDefinition},
-    message::{self, AssistantContent, Message, ToolCall, ToolFunction, ToolResultContent, UserContent},
-    providers::{openai, anthropic},
-    OneOrMany,
+use rig::agent::AgentBuilder;
+use rig::completion::{CompletionModel, Message};
+use rig::providers::{anthropic, openai};
+use tracing_subscriber::fmt::{format::Writer, time::{FormatTime, SystemTime}};
+use std::{
+    env,
+    error::Error,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
-use std::{env, error::Error, thread::current, io::Write};
-use serde_json::json;
-mod tools;
-use tools::code_review::CodeReviewTool;
-
-struct MultiTurnAgent<M: rig::completion::CompletionModel> {
-    agent: Agent<M>,
-    chat_history: Vec<completion::Message>,
+mod cli;
+use clap::Parser;
+use cli::{Cli, Command, ConfigCommand};
+use rig_magi::agent::{build_code_agent, MultiTurnAgent, MultiTurnError, DEFAULT_FEEDBACK_BUDGET, DEFAULT_MAX_ITERATIONS};
+use rig_magi::config::{self, CodeReviewConfig};
+#[cfg(feature = "discovery")]
+use rig_magi::discovery;
+use rig_magi::session;
+use rig_magi::status::{RunState, StatusFile};
+#[cfg(feature = "otel")]
+use rig_magi::telemetry;
+use rig_magi::tools::code_review::{CodeReviewArgs, CodeReviewTool, ReviewFile, EXIT_APPROVED, EXIT_REJECTED, EXIT_UNDECIDED};
+use rig_magi::tools::compare::{Comparison, VersionScore};
+use rig_magi::tools::report::ReviewReport;
+use rig_magi::tools::event_relay::EventRelay;
+use rig_magi::tools::events;
+
+// Longest response asked of Anthropic models, which need a limit on every request
+const ANTHROPIC_MAX_TOKENS: u64 = 8192;
+
+// The LLM provider that generates the code, from MAGI_PROVIDER
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum Provider {
+    #[default]
+    OpenAi,
+    Anthropic,
 }
 
-impl<M: rig::completion::CompletionModel> MultiTurnAgent<M> {
-    async fn multi_turn_prompt(
-        &mut self,
-        prompt: impl Into<Message> + Send,
-    ) -> Result<String, PromptError> {
-        // Initial prompt
-        let initial_prompt = prompt.into();
-        let mut current_prompt = initial_prompt.clone();
-        
-        // Save initial prompt to history
-        self.chat_history.push(current_prompt.clone());
-        
-        // Code generation and review loop
-        loop {
-            tracing::info!(target: "rig-magi",
-                            "Generating codes"
-                        );
-            
-            // Send prompt to AI
-            let resp = self
-                .agent
-                .completion(current_prompt.clone(), self.chat_history.clone())
-                .await?
-                .send()
-                .await?;
-
-            let mut final_text = None;
-            let mut code_approved = false;
-
-            for content in resp.choice.into_iter() {
-                match content {
-                    AssistantContent::Text(text) => {
-                        // AI directly returns text (usually code that has passed review)
-                        println!("AI响应: {}", text.text);
-                        final_text = Some(text.text.clone());
-                        
-                        // Save to history
-                        let response_message = Message::Assistant {
-                            content: OneOrMany::one(AssistantContent::Text(message::Text {
-                                text: text.text.clone(),
-                            })),
-                        };
-                        self.chat_history.push(response_message);
-                        code_approved = true;
-                    }
-                    AssistantContent::ToolCall(content) => {
-                        
-                        tracing::info!(target: "rig-magi",
-                            "AI call tool: {}",
-                            content.function.name
-                        );
-                        
-                        // Save AI's tool call to history
-                        let tool_call_msg = AssistantContent::ToolCall(content.clone());
-                        self.chat_history.push(Message::Assistant {
-                            content: OneOrMany::one(tool_call_msg),
-                        });
-
-                        // Extract tool call information
-                        let ToolCall {
-                            id,
-                            function: ToolFunction { name, arguments },
-                        } = content;
-
-                        // Call tool (code review)
-                        tracing::info!(target: "rig-magi",
-                            "Executing code review"
-                        );
-                        let tool_result = self.agent.tools.call(&name, arguments.to_string()).await?;
-
-                        // Parse review result
-                        if let Ok(review_result) = serde_json::from_str::<serde_json::Value>(&tool_result) {
-                            // Check if code passed review
-                            if let Some(passed) = review_result.get("passed").and_then(|v| v.as_bool()) {
-                                if passed {
-                                    tracing::info!(target: "rig-magi",
-                                        "Code review passed"
-                                    );
-                                    
-                                    // Extract code
-                                    if let Some(code) = review_result.get("code").and_then(|v| v.as_str()) {
-                                        final_text = Some(code.to_string());
-                                        code_approved = true;
-                                        
-                                        // Create tool result message and add to history
-                                        let tool_result_message =  Message::User {
-                                            content: OneOrMany::one(UserContent::ToolResult(message::ToolResult {
-                                                id: id.clone(),
-                                                content: OneOrMany::one(ToolResultContent::Text(message::Text {
-                                                    text: tool_result.clone(),
-                                                })),
-                                            })),
-                                        };
-
-                                        self.chat_history.push(tool_result_message);
-                                        
-                                        // Add final result message
-                                        let final_message = Message::Assistant {
-                                            content: OneOrMany::one(AssistantContent::Text(message::Text {
-                                                text: code.to_string(),
-                                            })),
-                                        };
-                                        self.chat_history.push(final_message);
-                                        
-                                        // Return result directly after code passes review
-                                        return Ok(code.to_string());
-                                    }
-                                } else {
-                                    println!("Code review failed, continuing improvements...");
-                                    tracing::info!(target: "rig-magi",
-                                        "Code review failed"
-                                    );
-
-                                    tracing::debug!(target: "rig-magi",
-                                        "Review result: {}",
-                                        tool_result
-                                    );
-                                    
-                                    // Create tool result message
-                                    let tool_result_message =  Message::User {
-                                        content: OneOrMany::one(UserContent::ToolResult(message::ToolResult {
-                                            id: id.clone(),
-                                            content: OneOrMany::one(ToolResultContent::Text(message::Text {
-                                                text: tool_result.clone(),
-                                            })),
-                                        })),
-                                    };
-
-                                    self.chat_history.push(tool_result_message.clone());
-                                    
-                                    // Next round prompt uses original request plus review feedback
-                                    current_prompt = Message::User {
-                                        content: OneOrMany::one(UserContent::Text(message::Text {
-                                            text: format!("Please improve the code based on the last review feedback",),
-                                        })),
-                                    };
-
-                                    break;
-                                }
-                            }
-                        }
-                        
-                        // If unable to parse review result, use original tool result
-                        let tool_result_message = Message::User {
-                            content: OneOrMany::one(UserContent::ToolResult(message::ToolResult {
-                                id: id.clone(),
-                                content: OneOrMany::one(ToolResultContent::Text(message::Text {
-                                    text: tool_result.clone(),
-                                })),
-                            })),
-                        };
-                        self.chat_history.push(tool_result_message.clone());
-                        current_prompt = tool_result_message;
-                        
-                        break;
-                    }
-                }
-            }
+impl std::str::FromStr for Provider {
+    type Err = String;
 
-            if code_approved || final_text.is_some() {
-                return Ok(final_text.unwrap_or_else(|| "Unable to get final code".to_string()));
-            }
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "openai" => Ok(Provider::OpenAi),
+            "anthropic" => Ok(Provider::Anthropic),
+            other => Err(format!("Unknown provider: {} (expected openai or anthropic)", other)),
         }
     }
 }
 
+// Log timestamps preceded by the instance name, when there is one
+struct InstanceTimer(Option<String>);
+
+impl FormatTime for InstanceTimer {
+    fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
+        use std::fmt::Write as _;
+
+        if let Some(name) = &self.0 {
+            write!(w, "[{}] ", name)?;
+        }
+        SystemTime.format_time(w)
+    }
+}
+
+// The OpenAI organization and project headers the environment asks for, as
+// (variable, header) pairs, given a lookup of the environment
+fn requested_openai_headers(var: impl Fn(&str) -> Option<String>) -> Vec<(&'static str, &'static str)> {
+    [("OPENAI_ORG_ID", "OpenAI-Organization"), ("OPENAI_PROJECT_ID", "OpenAI-Project")]
+        .into_iter()
+        .filter(|(name, _)| var(name).is_some())
+        .collect()
+}
+
+// Prefix a line of output with the instance name, when there is one
+fn with_instance(instance: Option<&str>, text: &str) -> String {
+    match instance {
+        Some(name) => format!("[{}] {}", name, text),
+        None => text.to_string(),
+    }
+}
+
+// Flush what outlives a turn before the process exits: the session, the
+// final status and, with the `otel` feature, buffered spans
+fn shutdown(session: Option<(&Path, &[Message])>, status: Option<&StatusFile>) {
+    if let Some((path, chat_history)) = session {
+        if let Err(e) = session::save_session(path, chat_history) {
+            eprintln!("Error: {}", e);
+        }
+    }
+
+    if let Some(status) = status {
+        status.set_state(RunState::Stopped);
+    }
+
+    #[cfg(feature = "otel")]
+    telemetry::shutdown();
+
+    let _ = std::io::stdout().flush();
+}
+
+// Load the review config, apply the command-line overrides and fetch the
+// panel when an agents URL is configured
+async fn load_review_config(cli: &Cli, config_path: Option<&Path>) -> Result<CodeReviewConfig, Box<dyn Error>> {
+    let mut review_config = CodeReviewConfig::load(config_path)?;
+    if cli.strict_verdict {
+        review_config.strict_verdict = true;
+    }
+    if cli.compile_check {
+        review_config.compile_check = true;
+    }
+    if cli.stream_output {
+        review_config.stream_output = true;
+    }
+    if cli.review_dump_dir.is_some() {
+        review_config.review_dump_dir = cli.review_dump_dir.clone();
+    }
+    if let Some(path) = &cli.rubric_file {
+        review_config.load_rubric(path)?;
+    }
+    if let Some(path) = &cli.tests_file {
+        review_config.load_tests(path)?;
+    }
+    #[cfg(feature = "discovery")]
+    discovery::discover_panel(&mut review_config).await;
+    #[cfg(not(feature = "discovery"))]
+    if review_config.agents_url.is_some() {
+        tracing::warn!(target: "rig-magi", "Ignoring the agents URL: built without the discovery feature");
+    }
+    review_config.validate()?;
+    Ok(review_config)
+}
+
+// Review existing files as one change, without generating anything
+async fn review_files(paths: &[PathBuf], review_config: CodeReviewConfig) -> Result<ReviewReport, Box<dyn Error>> {
+    let args = file_args(paths, None)?;
+    Ok(CodeReviewTool::with_config(review_config).review_report(args).await?)
+}
+
+// The `review` subcommand: print the report of reviewing the files and
+// return the verdict's exit status, `EXIT_UNDECIDED` if the review failed
+async fn review_command(paths: &[PathBuf], review_config: CodeReviewConfig) -> Result<i32, Box<dyn Error>> {
+    Ok(match review_files(paths, review_config).await {
+        Ok(report) => {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            report.output.exit_code()
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            EXIT_UNDECIDED
+        }
+    })
+}
+
+// Review two versions of the same code concurrently and rank them
+async fn compare_files(
+    old: &Path,
+    new: &Path,
+    prompt: Option<&str>,
+    review_config: CodeReviewConfig,
+) -> Result<Comparison, Box<dyn Error>> {
+    let required_positive = review_config.required_positive();
+    let tool = CodeReviewTool::with_config(review_config);
+    let (old_review, new_review) = futures_util::future::join(
+        tool.review_report(file_args(&[old.to_path_buf()], prompt)?),
+        tool.review_report(file_args(&[new.to_path_buf()], prompt)?),
+    )
+    .await;
+    let (old_review, new_review) = (old_review?, new_review?);
+
+    let old_score = VersionScore::new(&old.display().to_string(), &old_review.output, required_positive);
+    let new_score = VersionScore::new(&new.display().to_string(), &new_review.output, required_positive);
+    Ok(Comparison::new(old_score, old_review, new_score, new_review))
+}
+
+// Review arguments for existing files as one change; `prompt` is what the
+// change is meant to do
+fn file_args(paths: &[PathBuf], prompt: Option<&str>) -> Result<CodeReviewArgs, Box<dyn Error>> {
+    let mut files = Vec::new();
+    for path in paths {
+        files.push(ReviewFile {
+            path: path.display().to_string(),
+            content: std::fs::read_to_string(path)?,
+        });
+    }
+    let names: Vec<&str> = files.iter().map(|file| file.path.as_str()).collect();
+    let user_input = match prompt {
+        Some(prompt) => prompt.to_string(),
+        None => format!("Review this change to {}", names.join(", ")),
+    };
+    Ok(CodeReviewArgs {
+        user_input,
+        code: String::new(),
+        files,
+        correlation_id: None,
+        language: None,
+        rubric: None,
+        tests: None,
+    })
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    tracing_subscriber::fmt()
+    dotenv().ok();
+    
+    let cli = Cli::parse();
+    let instance = cli
+        .instance_name
+        .clone()
+        .or_else(|| env::var("MAGI_INSTANCE").ok())
+        .filter(|name| !name.trim().is_empty());
+    
+    // Logs go to stderr, leaving stdout to the results
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
         .with_max_level(tracing::Level::DEBUG)
         .with_target(true)
-        .init();
+        .with_timer(InstanceTimer(instance.clone()));
 
-    dotenv().ok();
+    #[cfg(feature = "otel")]
+    {
+        use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+        let tracer = telemetry::init_tracer()?;
+        subscriber
+            .finish()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer))
+            .init();
+    }
+
+    #[cfg(not(feature = "otel"))]
+    subscriber.init();
+
+    let config_path = config::resolve_config_path(cli.config.clone());
+    
+    if let Some(Command::Config { action: ConfigCommand::Check }) = &cli.command {
+        match config::check(config_path.as_deref()) {
+            Ok(summary) => {
+                println!("{}", summary);
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    
+    // Review files from the command line, exiting with the verdict's status
+    if let Some(Command::Review { files }) = &cli.command {
+        let status = match load_review_config(&cli, config_path.as_deref()).await {
+            Ok(review_config) => review_command(files, review_config).await?,
+            Err(e) => {
+                eprintln!("{}", e);
+                EXIT_UNDECIDED
+            }
+        };
+        
+        #[cfg(feature = "otel")]
+        telemetry::shutdown();
+        std::process::exit(status);
+    }
+
+    // Review two versions side by side and report which did better
+    if let Some(Command::Compare { files, prompt }) = &cli.command {
+        let [old, new] = files.as_slice() else {
+            eprintln!("compare takes exactly two --file arguments, the old version and the new");
+            std::process::exit(EXIT_UNDECIDED);
+        };
+        let comparison = match load_review_config(&cli, config_path.as_deref()).await {
+            Ok(review_config) => compare_files(old, new, prompt.as_deref(), review_config).await,
+            Err(e) => Err(e),
+        };
+        let status = match comparison {
+            Ok(comparison) => {
+                println!("{}", serde_json::to_string_pretty(&comparison)?);
+                0
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                EXIT_UNDECIDED
+            }
+        };
+
+        #[cfg(feature = "otel")]
+        telemetry::shutdown();
+        std::process::exit(status);
+    }
+    
+    let review_config = load_review_config(&cli, config_path.as_deref()).await?;
+    
+    // The model that writes the code
+    let provider = match env::var("MAGI_PROVIDER") {
+        Ok(provider) => provider.parse::<Provider>()?,
+        Err(_) => Provider::default(),
+    };
+    match provider {
+        Provider::OpenAi => {
+            let openai_client = match env::var("OPENAI_BASE_URL") {
+                Ok(base_url) => {
+                    tracing::debug!(target: "rig-magi",
+                        "Custom OpenAI base URL: {base_url}"
+                    );
+
+                    openai::Client::from_url(
+                        &env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY unset"),
+                        &base_url
+                    )
+                },
+                Err(_) => openai::Client::from_env()
+            };
+    
+            // The pinned rig revision builds its own HTTP client with only the
+            // Authorization header and has no way to add `OpenAI-Organization` or
+            // `OpenAI-Project`, so these can't be applied yet. Say so rather than
+            // billing the default organization silently.
+            for (var, header) in requested_openai_headers(|var| env::var(var).ok()) {
+                tracing::warn!(target: "rig-magi",
+                    "{} is set, but the {} header is not supported by this rig version and will not be sent",
+                    var,
+                    header
+                );
+            }
+
+            run(&cli, instance, review_config, openai_client.agent(openai::GPT_4O)).await
+        }
+        Provider::Anthropic => {
+            let anthropic_client = anthropic::Client::from_env();
+            let builder = anthropic_client
+                .agent(anthropic::CLAUDE_3_5_SONNET)
+                .max_tokens(ANTHROPIC_MAX_TOKENS);
+            run(&cli, instance, review_config, builder).await
+        }
+    }
+}
+
+// Run the interactive loop with the given model
+async fn run<M: CompletionModel>(
+    cli: &Cli,
+    instance: Option<String>,
+    review_config: CodeReviewConfig,
+    code_agent_builder: AgentBuilder<M>,
+) -> Result<(), Box<dyn Error>> {
+    // Relay review events to stderr for scripts watching progress, and to a
+    // WebSocket endpoint such as a dashboard
+    let event_sink = (cli.events || cli.events_ws.is_some()).then(|| {
+        let (sink, mut receiver) = events::channel(review_config.event_buffer, review_config.event_backpressure);
+        let print = cli.events;
+        let relay = cli.events_ws.clone().map(EventRelay::spawn);
+        tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                if print {
+                    if let Ok(line) = serde_json::to_string(&event) {
+                        eprintln!("{}", line);
+                    }
+                }
+                if let Some(relay) = &relay {
+                    relay.send(event);
+                }
+            }
+        });
+        sink
+    });
+
+    // Resume the saved conversation when running with a session file
+    let chat_history = match &cli.session {
+        Some(path) => {
+            let loaded = session::load_session(path, cli.strict_session)?;
+            if loaded.dropped > 0 {
+                eprintln!(
+                    "Skipped {} unreadable message(s) in {}; resuming with the other {}",
+                    loaded.dropped,
+                    path.display(),
+                    loaded.chat_history.len()
+                );
+            }
+            loaded.chat_history
+        }
+        None => Vec::new(),
+    };
+
+    let review_direct_text = cli.review_direct_text
+        || config::env_bool("MAGI_REVIEW_DIRECT_TEXT").unwrap_or(false);
+    let summarize_tool_results = cli.summarize_tool_results
+        || config::env_bool("MAGI_SUMMARIZE_TOOL_RESULTS").unwrap_or(false);
+    let keep_history = cli.keep_history
+        || config::env_bool("MAGI_KEEP_HISTORY").unwrap_or(false);
+    let max_history = cli
+        .max_history
+        .or_else(|| env::var("MAGI_MAX_HISTORY").ok().and_then(|v| v.parse().ok()));
+    let max_iterations = cli
+        .max_iterations
+        .or_else(|| env::var("MAGI_MAX_ITERATIONS").ok().and_then(|v| v.parse().ok()))
+        .unwrap_or(DEFAULT_MAX_ITERATIONS);
+    let feedback_budget = cli
+        .feedback_budget
+        .or_else(|| env::var("MAGI_FEEDBACK_BUDGET").ok().and_then(|v| v.parse().ok()))
+        .unwrap_or(DEFAULT_FEEDBACK_BUDGET);
+    let token_budget = cli
+        .token_budget
+        .or_else(|| env::var("MAGI_TOKEN_BUDGET").ok().and_then(|v| v.parse().ok()));
+    // Polled by supervisors; rewritten on every state change
+    let status = cli.status_file.clone().map(|path| Arc::new(StatusFile::new(path)));
     
-    let openai_client = match env::var("OPENAI_BASE_URL") {
-        Ok(base_url) => {
-            // println!("Custom OpenAI base URL: {}", base_url);
-            tracing::debug!(target: "rig-magi",
-                "Custom OpenAI base URL: {base_url}"
-            );
-
-            openai::Client::from_url(
-                &env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY unset"),
-                &base_url
-            )
-        },
-        Err(_) => openai::Client::from_env()
+    let agent = build_code_agent(code_agent_builder, &review_config, event_sink)
+        .with_human_tiebreak(cli.human_tiebreak)
+        .with_review_gate(cli.review_gate)
+        .with_review_direct_text(review_direct_text)
+        .with_summarized_tool_results(summarize_tool_results)
+        .with_max_history(max_history)
+        .with_max_iterations(max_iterations)
+        .with_feedback_budget(feedback_budget)
+        .with_token_budget(token_budget)
+        .with_single_pass(cli.single_pass)
+        .with_post_approval_prompt(cli.post_approval_prompt.clone())
+        .with_post_approval_review(cli.post_approval_review)
+        .with_debug_completions(cli.debug_completions)
+        .with_status_file(status.clone());
+    let mut agent = match env::var("MAGI_REFUSAL_PHRASES") {
+        Ok(phrases) => agent.with_refusal_phrases(
+            phrases.split(',').map(str::trim).filter(|p| !p.is_empty()).map(String::from).collect(),
+        ),
+        Err(_) => agent,
     };
+    agent.set_history(chat_history);
+
+    // Ctrl-C stops whatever is running. The session file already holds every
+    // completed turn, and an interrupted one is dropped like a failed turn.
+    {
+        let status = status.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                eprintln!("\nInterrupted, shutting down");
+                shutdown(None, status.as_deref());
+                std::process::exit(130);
+            }
+        });
+    }
 
-    let code_agent = openai_client
-        .agent(openai::GPT_4O)
-        .preamble(
-            "You are a code generation assistant with access to the code_review tool.\
-            \
-            IMPORTANT: You MUST follow this EXACT workflow:\
-            1. First, generate the requested code.\
-            2. Then, IMMEDIATELY call the code_review tool with these parameters:\
-               - user_input: user's first message\
-               - code: your generated code\
-            3. Wait for the review results.\
-            4. If approved, output the code.\
-            5. If rejected, improve and try again.\
-            \
-            DO NOT output any explanations or comments.\
-            DO NOT skip the code review step.\
-            ALWAYS use the code_review tool after generating ANY code.\
-            \
-            Example tool usage:\
-            {\"name\": \"code_review\",\
-             \"arguments\": {\
-                \"user_input\": \"hello world program in python\",\
-                \"code\": \"def add(a, b): return a + b\"\
-             }\
-            }\
-            \
-            Type 'exit' to quit."
-        )
-        .tool(CodeReviewTool::new())
-        .build();
-
-    let mut agent = MultiTurnAgent {
-        agent: code_agent,
-        chat_history: Vec::new(),
+    // One prompt from the command line, or the interactive loop
+    let exit_code = match cli.prompt.as_deref() {
+        Some(prompt) => Some(one_shot(cli, &mut agent, status.as_deref(), prompt).await?),
+        None => {
+            repl(cli, instance.as_deref(), &mut agent, status.as_deref(), keep_history).await;
+            None
+        }
     };
 
-    println!("🤖 MAGI System Interactive Mode");
-    println!("Type 'exit' to quit");
+    // Save the history as it stands, e.g. a session that was resumed with
+    // unreadable messages skipped and not saved since
+    let session = cli.session.as_deref().map(|path| (path, agent.history()));
+    shutdown(session, status.as_deref());
+
+    if let Some(exit_code) = exit_code {
+        std::process::exit(exit_code);
+    }
+    Ok(())
+}
+
+// Apply `/pause` and `/resume` to the loop's state. `None` lets the input
+// through as a prompt; otherwise it is held back and the notice is printed.
+fn pause_action(paused: &mut bool, input: &str) -> Option<&'static str> {
+    match input {
+        "/pause" => {
+            *paused = true;
+            Some("Paused; prompts are ignored until /resume")
+        }
+        "/resume" if *paused => {
+            *paused = false;
+            Some("Resumed")
+        }
+        "/resume" => Some("Not paused"),
+        _ if *paused => Some("Paused, prompt ignored; type /resume to continue"),
+        _ => None,
+    }
+}
+
+// Read prompts from stdin until `exit` or the end of input
+async fn repl<M: CompletionModel>(
+    cli: &Cli,
+    instance: Option<&str>,
+    agent: &mut MultiTurnAgent<M>,
+    status: Option<&StatusFile>,
+    keep_history: bool,
+) {
+    println!("{}", with_instance(instance, "🤖 MAGI System Interactive Mode"));
+    println!("Type 'exit' to quit, '/budget' for token usage, '/pause' and '/resume' to hold prompts");
     println!("-------------------");
+    let prompt = with_instance(instance, "> ");
 
     let stdin = std::io::stdin();
     let mut stdout = std::io::stdout();
 
+    // While paused, prompts are ignored until /resume
+    let mut paused = false;
+
     loop {
-        print!("> ");
+        print!("{}", prompt);
         stdout.flush().unwrap();
 
         let mut input = String::new();
         match stdin.read_line(&mut input) {
+            // End of input
+            Ok(0) => break,
             Ok(_) => {
                 let input = input.trim();
                 if input == "exit" {
                     break;
                 }
 
-                match agent.multi_turn_prompt(input).await {
+                // Nothing to ask; prompt again
+                if input.is_empty() {
+                    continue;
+                }
+
+                if input == "/budget" {
+                    match agent.remaining_budget() {
+                        Some(remaining) => println!(
+                            "Used ~{} estimated tokens, {} remaining",
+                            agent.tokens_used(),
+                            remaining
+                        ),
+                        None => println!("Used ~{} estimated tokens (no budget set)", agent.tokens_used()),
+                    }
+                    continue;
+                }
+
+                if let Some(notice) = pause_action(&mut paused, input) {
+                    println!("{}", notice);
+                    continue;
+                }
+
+                let turn_start = agent.history().len();
+                match run_turn(cli, agent, status, input).await {
                     Ok(result) => {
                         println!("🤖 Result:");
                         println!("{}", result);
+                    
+                        // Show the verdict when the code may not have passed
+                        if cli.single_pass || cli.candidates.is_some_and(|count| count > 1) {
+                            if let Some(review) = agent.last_review() {
+                                let passed = review.get("passed").and_then(|v| v.as_bool()).unwrap_or(false);
+                                let verdict = review.get("result").and_then(|v| v.as_str()).unwrap_or("unknown");
+                                println!("Review: {} (passed: {})", verdict, passed);
+                            }
+                        }
                         println!("-------------------");
-                        agent.chat_history.clear();
+                    
+                        // A session keeps the conversation and saves it after every
+                        // completed turn, and --keep-history keeps it for this run;
+                        // otherwise each prompt starts fresh
+                        match &cli.session {
+                            Some(path) => {
+                                if let Err(e) = session::save_session(path, agent.history()) {
+                                    println!("Error: {}", e);
+                                }
+                            }
+                            None if keep_history => {}
+                            None => agent.clear_history(),
+                        }
 
                     }
                     Err(e) => {
-                        println!("Error: {}", e);
+                        match &e {
+                            MultiTurnError::Refused(response) => {
+                                println!("🚫 The model refused to generate code:");
+                                println!("{}", response);
+                                println!("-------------------");
+                            }
+                            MultiTurnError::IterationsExhausted { rounds, code, feedback } => {
+                                println!("❌ Review did not pass after {} rounds. Last code:", rounds);
+                                println!("{}", code);
+                                println!("Last feedback:");
+                                println!("{}", feedback);
+                                println!("-------------------");
+                            }
+                            _ => println!("Error: {}", e),
+                        }
+                    
+                        // Drop the failed turn's partial exchange so it isn't persisted
+                        // or carried into the next prompt
+                        if cli.session.is_some() || keep_history {
+                            agent.truncate_history(turn_start);
+                        }
                     }
                 }
             }
             Err(error) => println!("Error reading input: {}", error),
         }
     }
+}
 
-    Ok(())
+// Generate and review one prompt, updating the status file around it
+async fn run_turn<M: CompletionModel>(
+    cli: &Cli,
+    agent: &mut MultiTurnAgent<M>,
+    status: Option<&StatusFile>,
+    input: &str,
+) -> Result<String, MultiTurnError> {
+    if let Some(status) = status {
+        status.set_state(RunState::Generating);
+    }
+    let outcome = match cli.candidates {
+        Some(count) if count > 1 => agent.candidates_prompt(input, count).await,
+        _ => agent.multi_turn_prompt(input).await,
+    };
+
+    // A turn without a review was accepted as is
+    if let Some(status) = status {
+        status.finish_turn(outcome.as_ref().ok().map(|_| match agent.last_review() {
+            Some(review) => (
+                review.get("result").and_then(|v| v.as_str()).map(String::from),
+                review.get("passed").and_then(|v| v.as_bool()).unwrap_or(false),
+            ),
+            None => (None, true),
+        }));
+    }
+
+    // The raw response, to tell model behavior apart from the loop's handling of it
+    if let Some(completion) = agent.last_completion() {
+        eprintln!("Completion: {}", completion);
+    }
+    outcome
+}
+
+// Exit status for a one-shot turn's code: approved only when a review
+// passed it; code that was never reviewed is undecided
+fn review_exit_code(review: Option<&serde_json::Value>) -> i32 {
+    match review.map(|review| review.get("passed").and_then(|v| v.as_bool()).unwrap_or(false)) {
+        Some(true) => EXIT_APPROVED,
+        Some(false) => EXIT_REJECTED,
+        None => EXIT_UNDECIDED,
+    }
+}
+
+// Run a single prompt for scripts: the code goes to stdout, everything else
+// to stderr. Returns the exit status, `EXIT_APPROVED` only for code that
+// passed review. `-` reads the prompt from stdin.
+async fn one_shot<M: CompletionModel>(
+    cli: &Cli,
+    agent: &mut MultiTurnAgent<M>,
+    status: Option<&StatusFile>,
+    prompt: &str,
+) -> Result<i32, Box<dyn Error>> {
+    let prompt = match prompt {
+        "-" => std::io::read_to_string(std::io::stdin())?,
+        prompt => prompt.to_string(),
+    };
+    let prompt = prompt.trim();
+    if prompt.is_empty() {
+        eprintln!("The prompt is empty");
+        return Ok(EXIT_UNDECIDED);
+    }
+
+    let turn_start = agent.history().len();
+    match run_turn(cli, agent, status, prompt).await {
+        Ok(code) => {
+            println!("{}", code);
+
+            let review = agent.last_review();
+            let exit_code = review_exit_code(review);
+            match review {
+                None => eprintln!("The code was not reviewed"),
+                Some(review) if exit_code != EXIT_APPROVED => {
+                    let verdict = review.get("result").and_then(|v| v.as_str()).unwrap_or("unknown");
+                    eprintln!("Review did not pass: {}", verdict);
+                }
+                Some(_) => {}
+            }
+            Ok(exit_code)
+        }
+        Err(e) => {
+            if cli.session.is_some() {
+                agent.truncate_history(turn_start);
+            }
+            match &e {
+                MultiTurnError::Refused(response) => {
+                    eprintln!("The model refused to generate code:");
+                    eprintln!("{}", response);
+                    Ok(EXIT_REJECTED)
+                }
+                MultiTurnError::IterationsExhausted { rounds, feedback, .. } => {
+                    eprintln!("Review did not pass after {} rounds. Last feedback:", rounds);
+                    eprintln!("{}", feedback);
+                    Ok(EXIT_REJECTED)
+                }
+                _ => {
+                    eprintln!("Error: {}", e);
+                    Ok(EXIT_UNDECIDED)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rig_magi::tools::code_review::MAGIDecision;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn only_reviewed_code_that_passed_exits_approved() {
+        assert_eq!(review_exit_code(Some(&serde_json::json!({ "passed": true }))), EXIT_APPROVED);
+        assert_eq!(review_exit_code(Some(&serde_json::json!({ "passed": false }))), EXIT_REJECTED);
+        assert_eq!(review_exit_code(Some(&serde_json::json!({ "result": "POSITIVE" }))), EXIT_REJECTED);
+        assert_eq!(review_exit_code(None), EXIT_UNDECIDED);
+    }
+
+    #[test]
+    fn providers_parse_by_name_in_any_case() {
+        assert_eq!(" OpenAI ".parse::<Provider>(), Ok(Provider::OpenAi));
+        assert_eq!("anthropic".parse::<Provider>(), Ok(Provider::Anthropic));
+        assert!("mistral".parse::<Provider>().unwrap_err().contains("mistral"));
+    }
+
+    #[test]
+    fn org_and_project_headers_are_requested_only_when_set() {
+        assert!(requested_openai_headers(|_| None).is_empty());
+
+        let org_only = requested_openai_headers(|var| (var == "OPENAI_ORG_ID").then(|| "org-1".to_string()));
+        assert_eq!(org_only, [("OPENAI_ORG_ID", "OpenAI-Organization")]);
+
+        let both = requested_openai_headers(|_| Some("set".to_string()));
+        assert_eq!(both.len(), 2);
+    }
+
+    #[test]
+    fn files_are_read_into_one_change() {
+        let dir = env::temp_dir().join(format!("rig-magi-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (add, lib) = (dir.join("add.rs"), dir.join("lib.rs"));
+        std::fs::write(&add, "pub fn add() {}").unwrap();
+        std::fs::write(&lib, "mod add;").unwrap();
+
+        let args = file_args(&[add.clone(), lib.clone()], None).unwrap();
+
+        let contents: Vec<&str> = args.files.iter().map(|file| file.content.as_str()).collect();
+        assert_eq!(contents, ["pub fn add() {}", "mod add;"]);
+        assert!(args.code.is_empty());
+        assert_eq!(args.user_input, format!("Review this change to {}, {}", add.display(), lib.display()));
+        assert_eq!(file_args(&[add], Some("Add addition")).unwrap().user_input, "Add addition");
+        assert!(file_args(&[dir.join("missing.rs")], None).is_err());
+    }
+
+    // A file with this content in a fresh temp dir
+    fn temp_file(content: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rig-magi-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("add.rs");
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    // A config that can't reach a gateway, with a forced verdict for `path`
+    fn forcing(path: &Path, decision: Option<MAGIDecision>) -> CodeReviewConfig {
+        let mut review_config = CodeReviewConfig {
+            server_url: "ws://127.0.0.1:1/review".to_string(),
+            reconnect_attempts: 0,
+            ..CodeReviewConfig::default()
+        };
+        if let Some(decision) = decision {
+            let submission = file_args(&[path.to_path_buf()], None).unwrap().submission();
+            review_config.verdict_overrides.insert(hex::encode(Sha256::digest(submission.as_bytes())), decision);
+        }
+        review_config
+    }
+
+    #[tokio::test]
+    async fn the_review_subcommand_exits_with_the_verdict() {
+        let path = temp_file("pub fn add() {}");
+
+        let approved = review_command(&[path.clone()], forcing(&path, Some(MAGIDecision::POSITIVE))).await.unwrap();
+        let rejected = review_command(&[path.clone()], forcing(&path, Some(MAGIDecision::NEGATIVE))).await.unwrap();
+        let unreachable = review_command(&[path.clone()], forcing(&path, None)).await.unwrap();
+        let missing = review_command(&[path.with_extension("py")], forcing(&path, None)).await.unwrap();
+
+        assert_eq!((approved, rejected), (EXIT_APPROVED, EXIT_REJECTED));
+        assert_eq!((unreachable, missing), (EXIT_UNDECIDED, EXIT_UNDECIDED));
+    }
+
+    #[test]
+    fn the_instance_name_prefixes_the_prompt() {
+        assert_eq!(with_instance(Some("proj"), "> "), "[proj] > ");
+        assert_eq!(with_instance(None, "> "), "> ");
+    }
+
+    #[test]
+    fn prompts_are_held_between_pause_and_resume() {
+        let mut paused = false;
+
+        assert_eq!(pause_action(&mut paused, "add two numbers"), None);
+        assert_eq!(pause_action(&mut paused, "/pause"), Some("Paused; prompts are ignored until /resume"));
+        assert!(paused);
+        assert_eq!(
+            pause_action(&mut paused, "add two numbers"),
+            Some("Paused, prompt ignored; type /resume to continue")
+        );
+        assert_eq!(pause_action(&mut paused, "/resume"), Some("Resumed"));
+        assert!(!paused);
+        assert_eq!(pause_action(&mut paused, "/resume"), Some("Not paused"));
+        assert_eq!(pause_action(&mut paused, "add two numbers"), None);
+    }
+
+    #[test]
+    fn shutdown_saves_the_session_and_the_stopped_status() {
+        let dir = temp_file("").parent().unwrap().to_path_buf();
+        let (session_path, status_path) = (dir.join("session.json"), dir.join("status.json"));
+        let status = StatusFile::new(status_path.clone());
+        let history = vec![Message::from("write add")];
+
+        shutdown(Some((&session_path, &history)), Some(&status));
+
+        let saved = session::load_session(&session_path, true).unwrap();
+        assert_eq!(serde_json::to_value(&saved.chat_history).unwrap(), serde_json::to_value(&history).unwrap());
+        let written: serde_json::Value = serde_json::from_slice(&std::fs::read(&status_path).unwrap()).unwrap();
+        assert_eq!(written["state"], "stopped");
+    }
 }
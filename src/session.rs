@@ -0,0 +1,244 @@
+use chrono::{DateTime, Utc};
+use rig::completion::Message;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Bumped when the saved layout changes incompatibly
+const SESSION_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum SessionError {
+    IoError(String),
+    FormatError(String),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::IoError(msg) => write!(f, "Session file error: {}", msg),
+            SessionError::FormatError(msg) => write!(f, "Session format error: {}", msg),
+        }
+    }
+}
+
+impl Error for SessionError {}
+
+/// Chat history persisted between runs with `--session`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub version: u32,
+    pub saved_at: DateTime<Utc>,
+    pub chat_history: Vec<Message>,
+}
+
+// The saved layout with each message left unparsed, so one message from an
+// incompatible schema doesn't sink the rest
+#[derive(Deserialize)]
+struct RawSession {
+    version: u32,
+    chat_history: Vec<serde_json::Value>,
+}
+
+/// A loaded session's chat history
+#[derive(Debug, Default)]
+pub struct LoadedSession {
+    pub chat_history: Vec<Message>,
+    /// Messages skipped because they couldn't be parsed
+    pub dropped: usize,
+}
+
+/// Load a session's chat history. A missing file is an empty session.
+///
+/// Messages that don't parse, e.g. ones saved by an incompatible version,
+/// are logged and skipped, keeping the rest; with `strict` the first one
+/// fails the load instead. A file that isn't a session at all always fails.
+pub fn load_session(path: &Path, strict: bool) -> Result<LoadedSession, SessionError> {
+    if !path.exists() {
+        return Ok(LoadedSession::default());
+    }
+
+    let text = fs::read_to_string(path).map_err(|e| {
+        SessionError::IoError(format!("Failed to read {}: {}", path.display(), e))
+    })?;
+
+    let session: RawSession = serde_json::from_str(&text).map_err(|e| {
+        SessionError::FormatError(format!("Failed to parse {}: {}", path.display(), e))
+    })?;
+
+    if session.version != SESSION_VERSION {
+        return Err(SessionError::FormatError(format!(
+            "{} has session version {}, expected {}",
+            path.display(),
+            session.version,
+            SESSION_VERSION
+        )));
+    }
+
+    let mut loaded = LoadedSession::default();
+    for (index, value) in session.chat_history.into_iter().enumerate() {
+        match serde_json::from_value::<Message>(value) {
+            Ok(message) => loaded.chat_history.push(message),
+            Err(e) if strict => {
+                return Err(SessionError::FormatError(format!(
+                    "Failed to parse message {} of {}: {}",
+                    index,
+                    path.display(),
+                    e
+                )));
+            }
+            Err(e) => {
+                tracing::warn!(target: "rig-magi", "Skipping message {} of {}: {}", index, path.display(), e);
+                loaded.dropped += 1;
+            }
+        }
+    }
+
+    Ok(loaded)
+}
+
+/// Save the chat history atomically.
+///
+/// The session is written to a temporary file in the same directory, synced,
+/// then renamed over `path`, so a process killed mid-write leaves the previous
+/// file intact rather than a truncated one.
+pub fn save_session(path: &Path, chat_history: &[Message]) -> Result<(), SessionError> {
+    let session = Session {
+        version: SESSION_VERSION,
+        saved_at: Utc::now(),
+        chat_history: chat_history.to_vec(),
+    };
+    let json = serde_json::to_string_pretty(&session).map_err(|e| {
+        SessionError::FormatError(format!("Failed to serialize session: {}", e))
+    })?;
+
+    write_atomically(path, json.as_bytes())
+}
+
+/// Write a file through a synced temporary file renamed over `path`, so
+/// readers see either the old contents or the new, never a partial write
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), SessionError> {
+    let temp_path = temp_path_for(path);
+    let result = write_synced(&temp_path, contents).and_then(|_| {
+        fs::rename(&temp_path, path).map_err(|e| {
+            SessionError::IoError(format!("Failed to replace {}: {}", path.display(), e))
+        })
+    });
+
+    if result.is_err() {
+        // Don't leave a half-written temp file behind
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+// Same directory as the target, so the rename stays on one filesystem
+fn temp_path_for(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "session".to_string());
+    path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()))
+}
+
+fn write_synced(path: &Path, contents: &[u8]) -> Result<(), SessionError> {
+    let io_error = |e: std::io::Error| {
+        SessionError::IoError(format!("Failed to write {}: {}", path.display(), e))
+    };
+
+    let mut file = File::create(path).map_err(io_error)?;
+    file.write_all(contents).map_err(io_error)?;
+    file.sync_all().map_err(io_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use rig::{message::{Text, UserContent}, OneOrMany};
+
+    fn user(text: &str) -> Message {
+        Message::User {
+            content: OneOrMany::one(UserContent::Text(Text { text: text.to_string() })),
+        }
+    }
+
+    // Messages compared through their JSON, as saved
+    fn json(messages: &[Message]) -> serde_json::Value {
+        serde_json::to_value(messages).unwrap()
+    }
+
+    #[test]
+    fn saved_history_loads_back() {
+        let path = test_util::temp_dir().join("session.json");
+        let history = vec![user("write add"), user("add tests")];
+
+        save_session(&path, &history).unwrap();
+        let loaded = load_session(&path, true).unwrap();
+
+        assert_eq!(json(&loaded.chat_history), json(&history));
+        assert_eq!(loaded.dropped, 0);
+        assert!(!temp_path_for(&path).exists());
+    }
+
+    #[test]
+    fn a_failed_write_leaves_the_previous_session_intact() {
+        let path = test_util::temp_dir().join("session.json");
+        save_session(&path, &[user("first")]).unwrap();
+
+        // The temporary file can't be created where a directory stands
+        fs::create_dir(temp_path_for(&path)).unwrap();
+        let result = save_session(&path, &[user("second")]);
+
+        assert!(matches!(result, Err(SessionError::IoError(_))));
+        assert_eq!(json(&load_session(&path, true).unwrap().chat_history), json(&[user("first")]));
+    }
+
+    #[test]
+    fn a_write_cut_short_doesnt_replace_the_session() {
+        let path = test_util::temp_dir().join("session.json");
+        save_session(&path, &[user("first")]).unwrap();
+
+        // What a process killed mid-write leaves behind
+        fs::write(temp_path_for(&path), "{\"version\": 1, \"chat_hist").unwrap();
+
+        assert_eq!(json(&load_session(&path, true).unwrap().chat_history), json(&[user("first")]));
+        save_session(&path, &[user("second")]).unwrap();
+        assert_eq!(json(&load_session(&path, true).unwrap().chat_history), json(&[user("second")]));
+    }
+
+    #[test]
+    fn a_missing_session_is_empty() {
+        let loaded = load_session(&test_util::temp_dir().join("none.json"), true).unwrap();
+
+        assert!(loaded.chat_history.is_empty());
+    }
+
+    // A saved session with an unparseable message between two valid ones
+    fn with_corrupt_message() -> PathBuf {
+        let path = test_util::temp_dir().join("session.json");
+        let mut chat_history = json(&[user("write add"), user("add tests")]);
+        chat_history.as_array_mut().unwrap().insert(1, serde_json::json!({ "role": "narrator", "text": "?" }));
+        let session = serde_json::json!({ "version": 1, "saved_at": Utc::now(), "chat_history": chat_history });
+        fs::write(&path, session.to_string()).unwrap();
+        path
+    }
+
+    #[test]
+    fn a_corrupt_message_is_skipped_unless_strict() {
+        let path = with_corrupt_message();
+
+        let lenient = load_session(&path, false).unwrap();
+        let strict = load_session(&path, true);
+
+        assert_eq!(json(&lenient.chat_history), json(&[user("write add"), user("add tests")]));
+        assert_eq!(lenient.dropped, 1);
+        let Err(SessionError::FormatError(message)) = strict else {
+            panic!("strict load kept a corrupt message");
+        };
+        assert!(message.starts_with("Failed to parse message 1 of"));
+    }
+}
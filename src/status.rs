@@ -0,0 +1,133 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::session::write_atomically;
+
+/// What the process is doing
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunState {
+    /// Waiting for a prompt
+    Idle,
+    /// Waiting on the model
+    Generating,
+    /// Waiting on the review panel
+    Reviewing,
+    /// Shut down by `exit`, end of input or Ctrl-C
+    Stopped,
+}
+
+#[derive(Debug, Serialize)]
+struct Status {
+    state: RunState,
+    pid: u32,
+    uptime_secs: u64,
+    updated_at: DateTime<Utc>,
+    turns: u64,
+    approved: u64,
+    rejected: u64,
+    failed: u64,
+    /// `result` of the last finished turn's review, if it had one
+    last_decision: Option<String>,
+}
+
+/// A JSON status file for `--status-file`, rewritten atomically on every
+/// state change so a supervisor can poll it
+pub struct StatusFile {
+    path: PathBuf,
+    started: Instant,
+    status: Mutex<Status>,
+}
+
+impl StatusFile {
+    /// Start in the idle state and write the file
+    pub fn new(path: PathBuf) -> Self {
+        let status_file = Self {
+            path,
+            started: Instant::now(),
+            status: Mutex::new(Status {
+                state: RunState::Idle,
+                pid: std::process::id(),
+                uptime_secs: 0,
+                updated_at: Utc::now(),
+                turns: 0,
+                approved: 0,
+                rejected: 0,
+                failed: 0,
+                last_decision: None,
+            }),
+        };
+        status_file.update(|_| {});
+        status_file
+    }
+
+    pub fn set_state(&self, state: RunState) {
+        self.update(|status| status.state = state);
+    }
+
+    /// Record a finished turn and go back to idle. `decision` is the review's
+    /// `result` and `passed` its outcome; `None` when the turn failed.
+    pub fn finish_turn(&self, outcome: Option<(Option<String>, bool)>) {
+        self.update(|status| {
+            status.state = RunState::Idle;
+            status.turns += 1;
+            match outcome {
+                Some((decision, passed)) => {
+                    if passed {
+                        status.approved += 1;
+                    } else {
+                        status.rejected += 1;
+                    }
+                    status.last_decision = decision;
+                }
+                None => status.failed += 1,
+            }
+        });
+    }
+
+    // Apply the change and rewrite the file. A failed write is logged; the
+    // status file is never a reason to stop.
+    fn update(&self, change: impl FnOnce(&mut Status)) {
+        let mut status = self.status.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        change(&mut status);
+        status.uptime_secs = self.started.elapsed().as_secs();
+        status.updated_at = Utc::now();
+
+        let result = serde_json::to_vec_pretty(&*status)
+            .map_err(|e| e.to_string())
+            .and_then(|json| write_atomically(&self.path, &json).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            tracing::warn!(target: "rig-magi", "Failed to write status file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn read(path: &std::path::Path) -> serde_json::Value {
+        serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn the_file_follows_each_state_change() {
+        let path = test_util::temp_dir().join("status.json");
+        let status = StatusFile::new(path.clone());
+        assert_eq!(read(&path)["state"], "idle");
+
+        status.set_state(RunState::Reviewing);
+        assert_eq!(read(&path)["state"], "reviewing");
+
+        status.finish_turn(Some((Some("POSITIVE".to_string()), true)));
+        let written = read(&path);
+        assert_eq!(written["state"], "idle");
+        assert_eq!((written["turns"].as_u64(), written["approved"].as_u64()), (Some(1), Some(1)));
+        assert_eq!(written["last_decision"], "POSITIVE");
+        assert_eq!(written["pid"], std::process::id());
+    }
+}
@@ -0,0 +1,23 @@
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, runtime, trace, Resource};
+
+/// Set up an OTLP span exporter and the W3C trace-context propagator.
+///
+/// The exporter is configured through the standard OpenTelemetry variables,
+/// e.g. `OTEL_EXPORTER_OTLP_ENDPOINT` (default `http://localhost:4317`).
+pub fn init_tracer() -> Result<trace::Tracer, opentelemetry::trace::TraceError> {
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new("service.name", "rig-magi")])),
+        )
+        .install_batch(runtime::Tokio)
+}
+
+/// Flush any spans still buffered in the batch exporter
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
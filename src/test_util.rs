@@ -0,0 +1,276 @@
+//! Helpers shared by the unit tests: a scripted MAGI gateway on a local
+//! port and builders for the frames it sends back.
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::handshake::server::{ErrorResponse, Request, Response},
+    tungstenite::protocol::Message,
+};
+use uuid::Uuid;
+
+use crate::config::CodeReviewConfig;
+use crate::tools::code_review::CodeReviewArgs;
+
+/// What the gateway does in answer to a frame, in order
+pub(crate) enum Reply {
+    /// Send this frame
+    Frame(Value),
+    /// Pause before the next reply
+    Wait(Duration),
+    /// Close the connection
+    Close,
+}
+
+type Handler = dyn Fn(usize, &Value) -> Vec<Reply> + Send + Sync;
+
+/// A gateway that answers every text frame it receives with the handler's
+/// replies. The handler is given the index of the connection the frame
+/// arrived on (0 for the first) and the frame. Frames and handshake URIs
+/// are recorded for the test to inspect.
+pub(crate) struct MockGateway {
+    url: String,
+    received: Arc<Mutex<Vec<Value>>>,
+    uris: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockGateway {
+    pub(crate) async fn start(handler: impl Fn(usize, &Value) -> Vec<Reply> + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/review", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let uris = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let (frames, handshakes) = (received.clone(), uris.clone());
+        tokio::spawn(async move {
+            let mut connection = 0;
+            while let Ok((stream, _)) = listener.accept().await {
+                let (handler, frames, handshakes) = (handler.clone(), frames.clone(), handshakes.clone());
+                let index = connection;
+                connection += 1;
+
+                tokio::spawn(async move {
+                    let record_uri = move |request: &Request, response: Response| {
+                        handshakes.lock().unwrap().push(request.uri().to_string());
+                        Ok::<_, ErrorResponse>(response)
+                    };
+                    let Ok(ws) = accept_hdr_async(stream, record_uri).await else {
+                        return;
+                    };
+                    let (mut write, mut read) = ws.split();
+
+                    while let Some(Ok(message)) = read.next().await {
+                        let Message::Text(text) = message else {
+                            continue;
+                        };
+                        let Ok(frame) = serde_json::from_str::<Value>(&text) else {
+                            continue;
+                        };
+                        frames.lock().unwrap().push(frame.clone());
+
+                        for reply in handler(index, &frame) {
+                            match reply {
+                                Reply::Frame(frame) => {
+                                    if write.send(Message::Text(frame.to_string())).await.is_err() {
+                                        return;
+                                    }
+                                }
+                                Reply::Wait(delay) => tokio::time::sleep(delay).await,
+                                Reply::Close => {
+                                    let _ = write.close().await;
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        Self { url, received, uris }
+    }
+
+    /// A gateway on which the panel's agents vote with `contents`, in the
+    /// order the request lists them
+    pub(crate) async fn voting(contents: &[&str]) -> Self {
+        let contents: Vec<String> = contents.iter().map(|content| content.to_string()).collect();
+        Self::start(move |_, frame| {
+            if !is_judgement(frame) {
+                return Vec::new();
+            }
+            agent_ids(frame)
+                .iter()
+                .zip(&contents)
+                .flat_map(|(agent_id, content)| vote(frame, agent_id, content))
+                .collect()
+        })
+        .await
+    }
+
+    pub(crate) fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// Every frame received so far, across connections
+    pub(crate) fn received(&self) -> Vec<Value> {
+        self.received.lock().unwrap().clone()
+    }
+
+    /// The request URI of each connection's handshake, query included
+    pub(crate) fn uris(&self) -> Vec<String> {
+        self.uris.lock().unwrap().clone()
+    }
+
+    /// The judgement requests received so far
+    pub(crate) fn judgements(&self) -> Vec<Value> {
+        self.received().into_iter().filter(is_judgement).collect()
+    }
+
+    /// A config with the default panel that reviews against this gateway
+    /// without a heartbeat and with short timeouts
+    pub(crate) fn config(&self) -> CodeReviewConfig {
+        CodeReviewConfig {
+            server_url: self.url(),
+            heartbeat_interval: None,
+            review_timeout: Some(Duration::from_secs(10)),
+            reconnect_delay: Duration::from_millis(10),
+            ..CodeReviewConfig::default()
+        }
+    }
+}
+
+/// Whether a frame is a judgement request rather than one of its chunks
+pub(crate) fn is_judgement(frame: &Value) -> bool {
+    frame.get("agents").is_some()
+}
+
+/// The agent ids a judgement request asks, in order
+pub(crate) fn agent_ids(request: &Value) -> Vec<String> {
+    request["agents"]
+        .as_array()
+        .map(|agents| {
+            agents
+                .iter()
+                .filter_map(|agent| agent["agent_id"].as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// A streamed chunk of an agent's review of `request`
+pub(crate) fn streaming(request: &Value, agent_id: &str, content: &str) -> Value {
+    message(request, agent_id, "streaming", content)
+}
+
+/// The frame marking an agent's review of `request` complete
+pub(crate) fn completed(request: &Value, agent_id: &str) -> Value {
+    message(request, agent_id, "completed", "")
+}
+
+/// An agent's whole review as one chunk followed by its completion
+pub(crate) fn vote(request: &Value, agent_id: &str, content: &str) -> Vec<Reply> {
+    vec![
+        Reply::Frame(streaming(request, agent_id, content)),
+        Reply::Frame(completed(request, agent_id)),
+    ]
+}
+
+/// An error reported for one agent, with the gateway's `error_type`
+pub(crate) fn agent_error(request: &Value, agent_id: &str, error_type: &str, message: &str) -> Value {
+    json!({
+        "type": "agent_response",
+        "session_id": "mock-session",
+        "status": "error",
+        "request_id": request["request_id"],
+        "agent_id": agent_id,
+        "error": message,
+        "error_type": error_type,
+        "timestamp": "2024-01-01T00:00:00Z",
+    })
+}
+
+/// The gateway's consolidated verdict on `request`, with a per-agent
+/// breakdown of (agent id, decision, content)
+pub(crate) fn final_decision(request: &Value, decision: &str, agents: &[(&str, &str, &str)]) -> Value {
+    json!({
+        "type": "final_decision",
+        "request_id": request["request_id"],
+        "decision": decision,
+        "agents": agents
+            .iter()
+            .map(|(agent_id, decision, content)| json!({
+                "agent_id": agent_id,
+                "decision": decision,
+                "content": content,
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn message(request: &Value, agent_id: &str, status: &str, content: &str) -> Value {
+    json!({
+        "type": "agent_response",
+        "session_id": "mock-session",
+        "status": status,
+        "request_id": request["request_id"],
+        "agent_id": agent_id,
+        "content": content,
+        "timestamp": "2024-01-01T00:00:00Z",
+    })
+}
+
+/// Review arguments for a single snippet
+pub(crate) fn args(code: &str) -> CodeReviewArgs {
+    CodeReviewArgs {
+        user_input: "Write a function".to_string(),
+        code: code.to_string(),
+        files: Vec::new(),
+        correlation_id: None,
+        language: None,
+        rubric: None,
+        tests: None,
+    }
+}
+
+/// A fresh directory under the system temp dir
+pub(crate) fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rig-magi-test-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Everything `f` logs at debug level and above, as plain text
+pub(crate) fn capture_logs(f: impl FnOnce()) -> String {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || LogWriter(writer.clone()))
+        .with_max_level(tracing::Level::DEBUG)
+        .with_ansi(false)
+        .finish();
+
+    tracing::subscriber::with_default(subscriber, f);
+    let logs = buffer.lock().unwrap().clone();
+    String::from_utf8(logs).unwrap()
+}
+
+// Appends log output to a shared buffer
+struct LogWriter(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
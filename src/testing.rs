@@ -0,0 +1,145 @@
+use rig::{
+    completion::{CompletionError, CompletionModel, CompletionRequest, CompletionResponse, ToolDefinition},
+    message::{self, AssistantContent, Message, ToolCall, ToolFunction},
+    tool::Tool,
+    OneOrMany,
+};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::tools::code_review::{tool_definition, CodeReviewArgs, CodeReviewError, CodeReviewTool};
+
+/// What the model was sent for one completion
+#[derive(Debug, Clone)]
+pub struct ScriptedCall {
+    pub prompt: Message,
+    pub chat_history: Vec<Message>,
+}
+
+/// A completion model that plays back scripted responses in order, for
+/// driving `MultiTurnAgent` without a provider. Every request is recorded;
+/// clones share the script and the record.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedModel {
+    responses: Arc<Mutex<VecDeque<OneOrMany<AssistantContent>>>>,
+    calls: Arc<Mutex<Vec<ScriptedCall>>>,
+}
+
+impl ScriptedModel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer the next request by calling the review tool with this code
+    pub fn then_submit(self, user_input: &str, code: &str) -> Self {
+        let id = format!("call_{}", self.responses.lock().unwrap().len());
+        self.then(AssistantContent::ToolCall(ToolCall {
+            id,
+            function: ToolFunction {
+                name: CodeReviewTool::NAME.to_string(),
+                arguments: json!({ "user_input": user_input, "code": code }),
+            },
+        }))
+    }
+
+    /// Answer the next request with plain text
+    pub fn then_text(self, text: &str) -> Self {
+        self.then(AssistantContent::Text(message::Text { text: text.to_string() }))
+    }
+
+    pub fn then(self, content: AssistantContent) -> Self {
+        self.responses.lock().unwrap().push_back(OneOrMany::one(content));
+        self
+    }
+
+    /// Requests received so far, oldest first
+    pub fn calls(&self) -> Vec<ScriptedCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Scripted responses not yet played
+    pub fn remaining(&self) -> usize {
+        self.responses.lock().unwrap().len()
+    }
+}
+
+impl CompletionModel for ScriptedModel {
+    type Response = ();
+
+    async fn completion(&self, request: CompletionRequest) -> Result<CompletionResponse<()>, CompletionError> {
+        self.calls.lock().unwrap().push(ScriptedCall {
+            prompt: request.prompt.clone(),
+            chat_history: request.chat_history.clone(),
+        });
+
+        let choice = self.responses.lock().unwrap().pop_front().ok_or_else(|| {
+            CompletionError::ProviderError("Scripted model has no responses left".to_string())
+        })?;
+        Ok(CompletionResponse { choice, raw_response: () })
+    }
+}
+
+/// A review tool with the `code_review` name and schema that returns
+/// scripted verdicts in order instead of contacting a gateway. A rejection
+/// carries one NEGATIVE reviewer whose content is the given concern, so it
+/// reaches the model's next prompt like a real objection.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedReview {
+    verdicts: Arc<Mutex<VecDeque<Option<String>>>>,
+    reviewed: Arc<Mutex<Vec<CodeReviewArgs>>>,
+}
+
+impl ScriptedReview {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn then_approve(self) -> Self {
+        self.verdicts.lock().unwrap().push_back(None);
+        self
+    }
+
+    pub fn then_reject(self, concern: &str) -> Self {
+        self.verdicts.lock().unwrap().push_back(Some(concern.to_string()));
+        self
+    }
+
+    /// Submissions reviewed so far, oldest first
+    pub fn reviewed(&self) -> Vec<CodeReviewArgs> {
+        self.reviewed.lock().unwrap().clone()
+    }
+}
+
+impl Tool for ScriptedReview {
+    const NAME: &'static str = CodeReviewTool::NAME;
+    type Error = CodeReviewError;
+    type Args = CodeReviewArgs;
+    type Output = serde_json::Value;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        tool_definition()
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let verdict = self.verdicts.lock().unwrap().pop_front().ok_or_else(|| {
+            CodeReviewError::ConfigError("Scripted review has no verdicts left".to_string())
+        })?;
+        let code = args.submission();
+        self.reviewed.lock().unwrap().push(args);
+
+        let (decision, content) = match &verdict {
+            None => ("POSITIVE", "POSITIVE"),
+            Some(concern) => ("NEGATIVE", concern.as_str()),
+        };
+        Ok(json!({
+            "reviews": [format!("Reviewer melchior: {}", content)],
+            "result": decision,
+            "passed": verdict.is_none(),
+            "magi_state": {
+                "agents": [{ "name": "melchior", "decision": decision, "content": content }],
+            },
+            "code": code,
+        }))
+    }
+}
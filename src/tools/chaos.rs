@@ -0,0 +1,100 @@
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+/// Faults injected into the frames a review receives from the gateway, for
+/// exercising timeouts, reconnection and partial results against a real
+/// gateway. Frames are counted from 0 in the order they arrive.
+#[derive(Debug, Clone, Default)]
+pub struct FaultPlan {
+    /// Hold every frame back this long before handling it
+    pub frame_delay: Option<Duration>,
+    /// Treat the connection as closed when this frame arrives
+    pub drop_at: Option<usize>,
+    /// Replace this frame's text with a truncated, unparseable copy
+    pub corrupt_at: Option<usize>,
+}
+
+impl FaultPlan {
+    /// Apply the plan to a received frame. `None` means the connection dropped.
+    pub async fn apply(&self, index: usize, msg: Message) -> Option<Message> {
+        if let Some(delay) = self.frame_delay {
+            tokio::time::sleep(delay).await;
+        }
+
+        if self.drop_at == Some(index) {
+            tracing::warn!(target: "rig-magi", "Fault injection: dropping connection at frame {}", index);
+            return None;
+        }
+
+        if self.corrupt_at == Some(index) {
+            if let Message::Text(text) = msg {
+                tracing::warn!(target: "rig-magi", "Fault injection: corrupting frame {}", index);
+                let cut = text.char_indices().nth(text.chars().count() / 2).map_or(0, |(i, _)| i);
+                return Some(Message::Text(text[..cut].to_string()));
+            }
+        }
+
+        Some(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, MockGateway};
+    use crate::tools::code_review::CodeReviewTool;
+    use rig::tool::Tool;
+
+    async fn review_with(faults: FaultPlan) -> serde_json::Value {
+        let gateway = MockGateway::voting(&["POSITIVE", "NEGATIVE", "POSITIVE"]).await;
+        let tool = CodeReviewTool::with_config(gateway.config()).with_faults(faults);
+        tool.call(test_util::args("fn add() {}")).await.unwrap().to_json()
+    }
+
+    #[tokio::test]
+    async fn faults_are_applied_at_their_frame() {
+        let plan = FaultPlan {
+            drop_at: Some(1),
+            corrupt_at: Some(2),
+            ..FaultPlan::default()
+        };
+        let frame = || Message::Text(r#"{"type":"agent_response"}"#.to_string());
+
+        assert_eq!(plan.apply(0, frame()).await, Some(frame()));
+        assert_eq!(plan.apply(1, frame()).await, None);
+        assert_eq!(plan.apply(2, frame()).await, Some(Message::Text(r#"{"type":"ag"#.to_string())));
+    }
+
+    #[tokio::test]
+    async fn frames_are_delayed() {
+        let plan = FaultPlan {
+            frame_delay: Some(Duration::from_millis(20)),
+            ..FaultPlan::default()
+        };
+        let started = std::time::Instant::now();
+
+        plan.apply(0, Message::Text(String::new())).await;
+
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn a_corrupt_frame_is_skipped() {
+        // Frame 0 is melchior's only chunk, so it completes without a verdict
+        let output = review_with(FaultPlan { corrupt_at: Some(0), ..FaultPlan::default() }).await;
+
+        assert_eq!(output["magi_state"]["agents"][0]["content"], "");
+        assert_eq!(output["magi_state"]["agents"][0]["decision"], "NEGATIVE");
+        assert_eq!(output["passed"], false);
+    }
+
+    #[tokio::test]
+    async fn a_dropped_connection_leaves_a_partial_result() {
+        // Melchior's vote arrives; the connection drops before balthasar's
+        let output = review_with(FaultPlan { drop_at: Some(2), ..FaultPlan::default() }).await;
+
+        assert_eq!(output["passed"], false);
+        assert_eq!(output["magi_state"]["agents"][0]["decision"], "POSITIVE");
+        assert_eq!(output["pending_agents"], serde_json::json!(["balthasar", "casper"]));
+    }
+}
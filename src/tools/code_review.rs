@@ -1,4 +1,4 @@
-use futures_util::{SinkExt, StreamExt};
+use futures_util::{stream::SplitSink, SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use rig::{
@@ -8,18 +8,39 @@ use rig::{
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
 use std::error::Error;
 use std::fmt;
-use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::Instrument;
 use url::Url;
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 use sha2::{Sha256, Digest};
 use hex;
 
+use crate::config::{CodeReviewConfig, MinimumMarginMode, ReviewerAgent, WeakApprovalMode};
+use super::compile_check::{CompileCheck, CompileOutcome};
+use super::events::{EventSink, ReviewEvent};
+use super::latency::LatencyTracker;
+use super::pool::{ConnectionPool, WsStream};
+use super::redact;
+use super::report::ReviewReport;
+use super::review_cache::ReviewCache;
+#[cfg(feature = "chaos")]
+use super::chaos::FaultPlan;
+use super::verdict::{self, HeuristicExtractor, JsonVerdictExtractor, VerdictExtractor};
+
 #[derive(Debug)]
 pub enum CodeReviewError {
     WebSocketError(String),
     ConnectionError(String),
-    DeserializationError(String),
+    SerializationError(String),
+    ConfigError(String),
+    AgentError(String),
+    /// The gateway doesn't speak the pinned protocol version
+    ProtocolError(String),
 }
 
 impl fmt::Display for CodeReviewError {
@@ -27,17 +48,74 @@ impl fmt::Display for CodeReviewError {
         match self {
             CodeReviewError::WebSocketError(msg) => write!(f, "WebSocket error: {}", msg),
             CodeReviewError::ConnectionError(msg) => write!(f, "Connection error: {}", msg),
-            CodeReviewError::DeserializationError(msg) => write!(f, "Deserialization error: {}", msg),
+            CodeReviewError::SerializationError(msg) => write!(f, "Serialization error: {}", msg),
+            CodeReviewError::ConfigError(msg) => write!(f, "Config error: {}", msg),
+            CodeReviewError::AgentError(msg) => write!(f, "Agent error: {}", msg),
+            CodeReviewError::ProtocolError(msg) => write!(f, "Protocol error: {}", msg),
         }
     }
 }
 
 impl Error for CodeReviewError {}
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CodeReviewArgs {
-    user_input: String,
-    code: String,
+    pub user_input: String,
+    /// The code under review; may be empty when `files` is given
+    #[serde(default)]
+    pub code: String,
+    /// Files reviewed together as one change, after any `code`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<ReviewFile>,
+    /// Client-side id tying the review to the turn that requested it.
+    /// Filled in by the caller, not by the model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Language of the code, used to pick a compile check
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Criteria every reviewer should check; overrides the configured rubric
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rubric: Option<String>,
+    /// Tests the code should pass; overrides the configured tests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tests: Option<String>,
+}
+
+/// One file of a multi-file change
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReviewFile {
+    pub path: String,
+    pub content: String,
+}
+
+impl CodeReviewArgs {
+    /// Everything under review: the code, then each file in a labeled section
+    pub fn submission(&self) -> String {
+        let mut sections: Vec<String> = Vec::new();
+        if !self.code.is_empty() || self.files.is_empty() {
+            sections.push(self.code.clone());
+        }
+        for file in &self.files {
+            sections.push(format!("<file path=\"{}\">\n{}\n</file>", file.path, file.content));
+        }
+        sections.join("\n")
+    }
+
+    /// The code's language: the `language` argument if given, otherwise the
+    /// info string of the first code fence in `code`, e.g. `rust` for
+    /// ```` ```rust ````
+    pub fn detected_language(&self) -> Option<&str> {
+        if let Some(language) = self.language.as_deref().map(str::trim).filter(|l| !l.is_empty()) {
+            return Some(language);
+        }
+        let fence = self.code.find("```")?;
+        self.code[fence + 3..]
+            .lines()
+            .next()?
+            .split_whitespace()
+            .next()
+    }
 }
 
 // MAGI Gateway message types
@@ -45,7 +123,26 @@ pub struct CodeReviewArgs {
 struct ConnectionEstablished {
     #[serde(rename = "type")]
     message_type: String,
+    #[allow(dead_code)]
     session_id: String,
+    /// The gateway's own protocol version, when it reports one
+    #[serde(default)]
+    protocol_version: Option<u32>,
+    /// Every version the gateway accepts, when it reports them
+    #[serde(default)]
+    supported_versions: Option<Vec<u32>>,
+}
+
+impl ConnectionEstablished {
+    // Whether the gateway accepts `version`. A gateway that reports nothing
+    // predates versioning and speaks the original protocol.
+    fn accepts(&self, version: u32) -> bool {
+        match (&self.supported_versions, self.protocol_version) {
+            (Some(supported), _) => supported.contains(&version),
+            (None, Some(own)) => own == version,
+            (None, None) => true,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -69,14 +166,167 @@ struct AgentErrorResponse {
     status: String,
     request_id: String,
     agent_id: String,
-    error: String,
+    #[serde(flatten)]
+    error: GatewayError,
     timestamp: String,
 }
 
+// Error details as sent by the gateway: either a plain `error` string with
+// optional `error_type`/`error_code` siblings, or a structured `error` object
+#[derive(Deserialize, Debug)]
+struct GatewayError {
+    error: GatewayErrorBody,
+    #[serde(default)]
+    error_type: Option<String>,
+    #[serde(default)]
+    error_code: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum GatewayErrorBody {
+    Message(String),
+    Detailed {
+        #[serde(default)]
+        message: String,
+        #[serde(default, rename = "type")]
+        error_type: Option<String>,
+        #[serde(default)]
+        code: Option<serde_json::Value>,
+    },
+}
+
+/// Whether an agent error is worth retrying
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorCategory {
+    /// E.g. an overloaded model or rate limit; a retry may succeed
+    Transient,
+    /// E.g. a malformed request or bad credentials; retrying won't help
+    Permanent,
+    /// The gateway gave no recognizable error type
+    Unknown,
+}
+
+impl ErrorCategory {
+    fn classify(error_type: Option<&str>, code: Option<&str>) -> Self {
+        for key in [error_type, code].into_iter().flatten() {
+            match key.to_lowercase().as_str() {
+                "overloaded" | "rate_limited" | "rate_limit" | "timeout" | "unavailable"
+                | "server_error" | "internal_error" | "429" | "500" | "502" | "503" | "504" => {
+                    return ErrorCategory::Transient;
+                }
+                "bad_request" | "invalid_request" | "unauthorized" | "forbidden" | "not_found"
+                | "400" | "401" | "403" | "404" => {
+                    return ErrorCategory::Permanent;
+                }
+                _ => {}
+            }
+        }
+        ErrorCategory::Unknown
+    }
+}
+
+/// An error the gateway reported for a single agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentError {
+    pub agent: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    pub category: ErrorCategory,
+}
+
+impl AgentError {
+    fn new(agent: &str, error: GatewayError) -> Self {
+        // Codes may arrive as numbers or strings
+        let code_string = |code: serde_json::Value| match code {
+            serde_json::Value::String(code) => code,
+            other => other.to_string(),
+        };
+
+        let (message, error_type, code) = match error.error {
+            GatewayErrorBody::Message(message) => (message, error.error_type, error.error_code.map(code_string)),
+            GatewayErrorBody::Detailed { message, error_type, code } => (
+                message,
+                error_type.or(error.error_type),
+                code.or(error.error_code).map(code_string),
+            ),
+        };
+
+        Self {
+            agent: agent.to_string(),
+            category: ErrorCategory::classify(error_type.as_deref(), code.as_deref()),
+            message,
+            error_type,
+            code,
+        }
+    }
+}
+
+impl fmt::Display for AgentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.error_type.as_ref().or(self.code.as_ref()) {
+            Some(kind) => write!(f, "Reviewer {} error [{}]: {}", self.agent, kind, self.message),
+            None => write!(f, "Reviewer {} error: {}", self.agent, self.message),
+        }
+    }
+}
+
+// Frame types a gateway sends while it is starting up or has no agents available
+const NOT_READY_TYPES: [&str; 2] = ["not_ready", "unavailable"];
+
+// Greeting the gateway sends on a new connection
+const CONNECTION_ESTABLISHED: &str = "connection_established";
+
+// Times a not-ready request is re-sent before giving up
+const MAX_NOT_READY_RETRIES: u32 = 3;
+
+// Longest wait between reconnects after a dropped connection
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+// Longest wait between not-ready retries
+const MAX_NOT_READY_DELAY: Duration = Duration::from_secs(60);
+
+// The gateway can't take the request yet. `retry_after` (seconds) says when
+// to try again; without it the gateway isn't expected to become ready soon.
+#[derive(Deserialize, Debug)]
+struct NotReady {
+    #[serde(rename = "type")]
+    message_type: String,
+    #[serde(default)]
+    request_id: Option<String>,
+    #[serde(default)]
+    reason: Option<String>,
+    #[serde(default)]
+    retry_after: Option<f64>,
+}
+
+// Consolidated verdict sent by some gateway versions instead of relying on
+// the client to combine per-agent votes
+#[derive(Deserialize, Debug)]
+struct FinalVerdict {
+    #[serde(rename = "type")]
+    message_type: String,
+    request_id: String,
+    decision: String,
+    #[serde(default)]
+    agents: Vec<AgentVerdict>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AgentVerdict {
+    agent_id: String,
+    decision: String,
+    #[serde(default)]
+    content: Option<String>,
+}
+
 #[derive(Deserialize, Debug)]
 struct AgentResponse {
     #[serde(rename = "type")]
-    #[allow(dead_code)]
     message_type: String,
     agent_id: String,
     request_id: String,
@@ -93,48 +343,247 @@ pub struct MAGIMessage {
     pub timestamp: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum MAGIDecision {
     POSITIVE,
     NEGATIVE,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// An agent's part of the review. `messages` is the source of truth for what
+/// the agent said; `transcript()` joins them. The serialized form also carries
+/// the transcript as `content`.
+#[derive(Debug, Deserialize)]
 pub struct MAGIAgentState {
+    pub name: String,
+    pub agent_id: String,
     pub messages: Vec<MAGIMessage>,
     pub decision: Option<MAGIDecision>,
-    pub content: String,
+    /// Confidence the extractor reported with the decision, if any
+    #[serde(default)]
+    pub confidence: Option<f32>,
+    /// Approval discounted for lacking reasoning; not counted toward the majority
+    #[serde(default)]
+    pub weak_approval: bool,
+    /// Completed without an explicit verdict in strict mode. Stays undecided
+    /// but no longer pending.
+    #[serde(default)]
+    pub inconclusive: bool,
+    /// Answered with an error instead of a review. Its decision is NEGATIVE,
+    /// but it is left out of the majority, which is taken over the agents
+    /// that responded.
+    #[serde(default)]
+    pub errored: bool,
+}
+
+impl MAGIAgentState {
+    pub fn new(agent: &ReviewerAgent) -> Self {
+        Self {
+            name: agent.name.clone(),
+            agent_id: agent.id.clone(),
+            messages: vec![],
+            decision: None,
+            confidence: None,
+            weak_approval: false,
+            inconclusive: false,
+            errored: false,
+        }
+    }
+
+    /// Record a message from the agent
+    pub fn append_message(&mut self, request_id: &str, content: &str) {
+        self.messages.push(MAGIMessage {
+            request_id: request_id.to_string(),
+            content: content.to_string(),
+            timestamp: Utc::now(),
+        });
+    }
+
+    /// Whether the content exactly repeats the last message, as a gateway
+    /// that re-sends its last chunk would produce
+    pub fn repeats_last(&self, content: &str) -> bool {
+        !content.is_empty() && self.messages.last().is_some_and(|message| message.content == content)
+    }
+
+    /// Everything the agent said, in order
+    pub fn transcript(&self) -> String {
+        self.messages.iter().map(|message| message.content.as_str()).collect()
+    }
+}
+
+impl Serialize for MAGIAgentState {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("MAGIAgentState", 9)?;
+        state.serialize_field("name", &self.name)?;
+        state.serialize_field("agent_id", &self.agent_id)?;
+        state.serialize_field("messages", &self.messages)?;
+        state.serialize_field("decision", &self.decision)?;
+        state.serialize_field("confidence", &self.confidence)?;
+        state.serialize_field("content", &self.transcript())?;
+        state.serialize_field("weak_approval", &self.weak_approval)?;
+        state.serialize_field("inconclusive", &self.inconclusive)?;
+        state.serialize_field("errored", &self.errored)?;
+        state.end()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MAGISystemState {
-    pub melchior: MAGIAgentState,
-    pub balthasar: MAGIAgentState,
-    pub casper: MAGIAgentState,
+    /// Per-agent state, in the order the panel was configured
+    pub agents: Vec<MAGIAgentState>,
 }
 
 impl Default for MAGISystemState {
     fn default() -> Self {
-        Self {
-            melchior: MAGIAgentState { messages: vec![], decision: None, content: String::new() },
-            balthasar: MAGIAgentState { messages: vec![], decision: None, content: String::new() },
-            casper: MAGIAgentState { messages: vec![], decision: None, content: String::new() },
-        }
+        Self::new(&CodeReviewConfig::default().agents)
     }
 }
 
 impl MAGISystemState {
+    /// Each agent's vote so far, in panel order
+    pub fn decisions(&self) -> Vec<AgentDecision> {
+        self.agents
+            .iter()
+            .map(|state| AgentDecision {
+                agent: state.name.clone(),
+                decision: state.decision,
+                errored: state.errored,
+            })
+            .collect()
+    }
+
+    /// Each agent's vote for logs, e.g. `melchior=POSITIVE, balthasar=NEGATIVE,
+    /// casper=ERROR`. Agents without a decision are `INCONCLUSIVE` or
+    /// `PENDING`.
+    pub fn vote_summary(&self) -> String {
+        self.agents
+            .iter()
+            .map(|state| {
+                let vote = match state.decision {
+                    _ if state.errored => "ERROR",
+                    Some(MAGIDecision::POSITIVE) => "POSITIVE",
+                    Some(MAGIDecision::NEGATIVE) => "NEGATIVE",
+                    None if state.inconclusive => "INCONCLUSIVE",
+                    None => "PENDING",
+                };
+                format!("{}={}", state.name, vote)
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn new(agents: &[ReviewerAgent]) -> Self {
+        Self {
+            agents: agents.iter().map(MAGIAgentState::new).collect(),
+        }
+    }
+
+    /// Look up an agent's state by its gateway agent id
+    pub fn agent_mut(&mut self, agent_id: &str) -> Option<&mut MAGIAgentState> {
+        self.agents.iter_mut().find(|state| state.agent_id == agent_id)
+    }
+
     pub fn get_final_decision(&self) -> Option<MAGIDecision> {
-        let positive_count = [&self.melchior, &self.balthasar, &self.casper]
+        // Simple majority of the agents that responded (2 of 3 for the default
+        // MAGI, 1 of 1 when two of them errored)
+        self.get_final_decision_with_quorum(self.responding_count() / 2 + 1)
+    }
+
+    /// Agents that haven't errored: those that voted, are yet to, or were
+    /// inconclusive
+    pub fn responding_count(&self) -> usize {
+        self.agents.iter().filter(|state| !state.errored).count()
+    }
+
+    /// POSITIVE votes that count toward the decision
+    pub fn positive_count(&self) -> usize {
+        self.agents
+            .iter()
+            .filter(|state| matches!(state.decision, Some(MAGIDecision::POSITIVE)) && !state.weak_approval)
+            .count()
+    }
+
+    /// Decide as soon as the outcome is fixed: POSITIVE once enough votes are in,
+    /// NEGATIVE once the agents still pending can no longer reach the quorum
+    /// (e.g. two NEGATIVEs under 2-of-3). `None` while either is still possible.
+    pub fn determined_decision(&self, required_positive: usize) -> Option<MAGIDecision> {
+        let positive_count = self.positive_count();
+        let pending = self.pending_agents().len();
+
+        if positive_count >= required_positive {
+            Some(MAGIDecision::POSITIVE)
+        } else if positive_count + pending < required_positive {
+            Some(MAGIDecision::NEGATIVE)
+        } else {
+            None
+        }
+    }
+
+    /// Agents that haven't given a verdict yet
+    pub fn pending_agents(&self) -> Vec<String> {
+        self.agents
             .iter()
-            .filter(|state| matches!(state.decision, Some(MAGIDecision::POSITIVE)))
-            .count();
+            .filter(|state| state.decision.is_none() && !state.inconclusive)
+            .map(|state| state.name.clone())
+            .collect()
+    }
+
+    /// Decide by weighted majority: POSITIVE once the approvals carry more
+    /// than half the total weight of the agents that didn't error, NEGATIVE
+    /// once every agent has answered without that. Agents missing from
+    /// `weights` count 1.
+    pub fn get_final_decision_weighted(&self, weights: &HashMap<String, u32>) -> Option<MAGIDecision> {
+        let (positive, _, total) = self.weighted_tally(weights);
+        if positive * 2 > total {
+            Some(MAGIDecision::POSITIVE)
+        } else if self.pending_agents().is_empty() {
+            Some(MAGIDecision::NEGATIVE)
+        } else {
+            None
+        }
+    }
+
+    /// `determined_decision` by weighted majority: NEGATIVE as soon as the
+    /// pending agents' weight could no longer carry the approvals past half
+    pub fn determined_decision_weighted(&self, weights: &HashMap<String, u32>) -> Option<MAGIDecision> {
+        let (positive, pending, total) = self.weighted_tally(weights);
+        if positive * 2 > total {
+            Some(MAGIDecision::POSITIVE)
+        } else if (positive + pending) * 2 <= total {
+            Some(MAGIDecision::NEGATIVE)
+        } else {
+            None
+        }
+    }
+
+    // Weight of the counted POSITIVE votes, of the pending agents, and of
+    // every agent that didn't error
+    fn weighted_tally(&self, weights: &HashMap<String, u32>) -> (u64, u64, u64) {
+        let weight = |state: &MAGIAgentState| u64::from(weights.get(&state.name).copied().unwrap_or(1));
+        let responding = self.agents.iter().filter(|state| !state.errored);
+        let positive = responding
+            .clone()
+            .filter(|state| matches!(state.decision, Some(MAGIDecision::POSITIVE)) && !state.weak_approval)
+            .map(weight)
+            .sum();
+        let pending = responding
+            .clone()
+            .filter(|state| state.decision.is_none() && !state.inconclusive)
+            .map(weight)
+            .sum();
+        (positive, pending, responding.map(weight).sum())
+    }
+
+    /// Decide with an explicit number of POSITIVE votes required to pass
+    pub fn get_final_decision_with_quorum(&self, required_positive: usize) -> Option<MAGIDecision> {
+        let positive_count = self.positive_count();
         
-        if positive_count >= 2 {
+        if positive_count >= required_positive {
             Some(MAGIDecision::POSITIVE)
-        } else if [&self.melchior, &self.balthasar, &self.casper]
+        } else if self.agents
             .iter()
-            .all(|state| state.decision.is_some()) {
+            .all(|state| state.decision.is_some() || state.inconclusive) {
             Some(MAGIDecision::NEGATIVE)
         } else {
             None
@@ -142,16 +591,104 @@ impl MAGISystemState {
     }
 }
 
-// Constants for MAGI Gateway
-const APP_ID: &str = "b75fce6f-e8af-4207-9c32-f8166afb4520";
-const APP_SECRET: &str = "magi-gateway-development-secret";
-const AGENT_IDS: [(&str, &str); 3] = [
-    ("melchior", "d37c1cc8-bcc4-4b73-9f49-a93a30971f2c"),
-    ("balthasar", "6634d0ec-d700-4a92-9066-4960a0f11927"),
-    ("casper", "89cbe912-25d0-47b0-97da-b25622bfac0d"),
-];
+/// The running consensus after one agent's vote or error
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceStep {
+    pub agent: String,
+    pub vote: Option<MAGIDecision>,
+    /// POSITIVE and NEGATIVE votes so far, or with weights their weight
+    pub positive: usize,
+    pub negative: usize,
+    pub pending: usize,
+    /// The side with more votes (or weight) so far; `None` on a tie
+    pub leading: Option<MAGIDecision>,
+    /// The outcome, once it can no longer change
+    pub determined: Option<MAGIDecision>,
+}
 
-#[derive(Serialize, Debug)]
+/// How the panel's consensus developed, in the order the votes arrived
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DecisionTrace {
+    pub steps: Vec<TraceStep>,
+    /// The lead changed sides at some point, i.e. the outcome was knife-edge
+    /// rather than clear from the first votes
+    pub flipped: bool,
+}
+
+impl DecisionTrace {
+    /// Record the state after `agent`'s vote was applied, with the outcome
+    /// if it can no longer change. With `weights`, each vote counts its
+    /// agent's weight (1 if unlisted) towards the sides and the lead.
+    pub fn record(
+        &mut self,
+        agent: &str,
+        magi_state: &MAGISystemState,
+        weights: Option<&HashMap<String, u32>>,
+        determined: Option<MAGIDecision>,
+    ) {
+        let weight = |state: &MAGIAgentState| {
+            weights.map_or(1, |weights| weights.get(&state.name).copied().unwrap_or(1) as usize)
+        };
+        let positive: usize = magi_state
+            .agents
+            .iter()
+            .filter(|state| matches!(state.decision, Some(MAGIDecision::POSITIVE)) && !state.weak_approval)
+            .map(weight)
+            .sum();
+        let negative: usize = magi_state
+            .agents
+            .iter()
+            .filter(|state| matches!(state.decision, Some(MAGIDecision::NEGATIVE)) && !state.errored)
+            .map(weight)
+            .sum();
+        let pending = magi_state.pending_agents().len();
+        let leading = match positive.cmp(&negative) {
+            std::cmp::Ordering::Greater => Some(MAGIDecision::POSITIVE),
+            std::cmp::Ordering::Less => Some(MAGIDecision::NEGATIVE),
+            std::cmp::Ordering::Equal => None,
+        };
+
+        // A flip is a lead for one side after an earlier lead for the other
+        let previous_lead = self.steps.iter().rev().find_map(|step| step.leading);
+        if leading.is_some() && previous_lead.is_some() && leading != previous_lead {
+            self.flipped = true;
+        }
+
+        self.steps.push(TraceStep {
+            agent: agent.to_string(),
+            vote: magi_state.agents.iter().find(|state| state.name == agent).and_then(|state| state.decision),
+            positive,
+            negative,
+            pending,
+            leading,
+            determined,
+        });
+    }
+}
+
+// A frame as the JSON text sent to the gateway; `what` names it in the error
+fn encode<T: Serialize>(frame: &T, what: &str) -> Result<String, CodeReviewError> {
+    serde_json::to_string(frame)
+        .map_err(|e| CodeReviewError::SerializationError(format!("Failed to serialize {}: {}", what, e)))
+}
+
+// The wait before reconnect number `reconnects` (from 0): the base delay,
+// doubled each time, up to MAX_RECONNECT_DELAY however many attempts are
+// configured
+fn reconnect_delay(base: Duration, reconnects: u8) -> Duration {
+    base.saturating_mul(2u32.saturating_pow(reconnects.into())).min(MAX_RECONNECT_DELAY)
+}
+
+// Capitalize an agent name for the summary lines ("melchior" -> "Melchior")
+pub(crate) fn display_name(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
 struct AgentJudgementRequest {
     #[serde(rename = "type")]
     message_type: String,
@@ -159,334 +696,3377 @@ struct AgentJudgementRequest {
     request: String,
     timestamp: f64,
     agents: Vec<AgentInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunk_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<String>,
+    /// W3C trace context of the review span, so gateway spans can join the trace
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace_context: Option<HashMap<String, String>>,
+    /// Gateway protocol version the request and the expected replies follow
+    protocol_version: u32,
 }
 
-#[derive(Serialize, Debug)]
-struct AgentInfo {
-    agent_id: String,
+#[cfg(feature = "otel")]
+fn trace_context() -> Option<HashMap<String, String>> {
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let context = tracing::Span::current().context();
+    let mut carrier = HashMap::new();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut carrier)
+    });
+
+    (!carrier.is_empty()).then_some(carrier)
 }
 
-#[derive(Serialize)]
-pub struct CodeReviewOutput {
-    reviews: Vec<String>,
-    result: String,
-    passed: bool,
-    magi_state: MAGISystemState,
-    code: String,
+#[cfg(not(feature = "otel"))]
+fn trace_context() -> Option<HashMap<String, String>> {
+    None
+}
+
+/// One piece of a chunked judgement request.
+///
+/// Chunk protocol: when the request body is larger than the configured
+/// `chunk_size`, it is sent as `agent_judgement_chunk` frames numbered
+/// `0..chunk_count`, followed by the regular `agent_judgement` frame with an
+/// empty `request` and `chunk_count` set. The gateway rebuilds the body by
+/// concatenating `data` in `chunk_index` order.
+#[derive(Serialize, Debug)]
+struct AgentJudgementChunk {
+    #[serde(rename = "type")]
+    message_type: String,
+    request_id: String,
+    chunk_index: usize,
+    chunk_count: usize,
+    data: String,
 }
 
-pub struct CodeReviewTool;
+// Split text into pieces of at most `max_bytes` without cutting a UTF-8 character
+fn split_into_chunks(text: &str, max_bytes: usize) -> Vec<&str> {
+    // A single character can take up to 4 bytes
+    let max_bytes = max_bytes.max(4);
+    let mut chunks = Vec::new();
+    let mut rest = text;
 
-impl CodeReviewTool {
-    pub fn new() -> Self {
-        Self {}
+    while !rest.is_empty() {
+        let mut end = rest.len().min(max_bytes);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
     }
+
+    chunks
 }
 
-impl Default for CodeReviewTool {
-    fn default() -> Self {
-        Self::new()
+/// Gateway credentials for one tenant, when a process reviews on behalf of
+/// several (see `CodeReviewTool::with_credentials`)
+#[derive(Clone)]
+pub enum GatewayCredentials {
+    /// App id and secret; a fresh token is derived for each connection
+    Secret { app_id: String, app_secret: String },
+    /// App id and a token the caller computed (see `auth_token`), sent as is
+    Token { app_id: String, token: String },
+}
+
+impl fmt::Debug for GatewayCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GatewayCredentials::Secret { app_id, .. } => {
+                f.debug_struct("Secret").field("app_id", app_id).field("app_secret", &redact::MASK).finish()
+            }
+            GatewayCredentials::Token { app_id, .. } => {
+                f.debug_struct("Token").field("app_id", app_id).field("token", &redact::MASK).finish()
+            }
+        }
     }
 }
 
-impl Tool for CodeReviewTool {
-    const NAME: &'static str = "code_review";
-    type Error = CodeReviewError;
-    type Args = CodeReviewArgs;
-    type Output = CodeReviewOutput;
+/// The gateway's auth token for the current minute
+pub fn auth_token(app_id: &str, app_secret: &str) -> String {
+    generate_token(app_id, app_secret, chrono::Utc::now().timestamp() / 60)
+}
 
-    async fn definition(&self, _prompt: String) -> ToolDefinition {
-        // println!("[DEBUG] CodeReviewTool::definition called");
-        ToolDefinition {
-            name: Self::NAME.to_string(),
-            description: "Review generated code through a panel of expert reviewers".to_string(),
-            parameters: json!({
-                "type": "object",
-                "properties": {
-                    "user_input": {
-                        "type": "string",
-                        "description": "The user input to the code review tool"
-                    },
-                    "code": {
-                        "type": "string",
-                        "description": "The code to be reviewed"
-                    }
-                },
-                "required": ["code"]
-            }),
+/// The gateway's auth token for `minute`, counted in minutes since the Unix
+/// epoch: the first 10 hex characters of SHA-256 over the app id, the secret
+/// and the minute
+pub fn generate_token(app_id: &str, app_secret: &str, minute: i64) -> String {
+    let raw_str = format!("{}{}{}", app_id, app_secret, minute);
+    let mut hasher = Sha256::new();
+    hasher.update(raw_str.as_bytes());
+    hex::encode(&hasher.finalize())[..10].to_string()
+}
+
+/// The gateway URL to connect to: `http://` and `https://` become `ws://`
+/// and `wss://` with a warning, and any other scheme is refused. A URL
+/// without a path, such as `ws://localhost:8080`, connects to `/`.
+pub fn normalize_ws_url(raw: &str) -> Result<Url, String> {
+    let mut url = Url::parse(raw.trim())
+        .map_err(|e| format!("Invalid gateway URL {}: {}", redact::url(raw), e))?;
+    let scheme = match url.scheme() {
+        "ws" | "http" => "ws",
+        "wss" | "https" => "wss",
+        other => return Err(format!("Gateway URL must use ws:// or wss://, got {}://", other)),
+    };
+    if url.scheme() != scheme {
+        // Say so, since the gateway may not expect it
+        if url.set_scheme(scheme).is_err() {
+            return Err(format!("Gateway URL {} can't be used as {}://", redact::url(raw), scheme));
         }
+        tracing::warn!(target: "rig-magi",
+            "Gateway URL {} is not a WebSocket URL, connecting with {}:// instead",
+            redact::url(raw),
+            scheme
+        );
     }
+    Ok(url)
+}
 
-    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
-        // println!("[DEBUG] CodeReviewTool::call called with args: {:?}", args);
-        // Get WebSocket URL from environment variable
-        let review_server_url = std::env::var("CODE_REVIEW_SERVER_URL")
-            .unwrap_or_else(|_| "ws://localhost:8080/review".to_string());
-
-        // Parse WebSocket URL
-        let mut url = Url::parse(&review_server_url).map_err(|e| {
-            CodeReviewError::ConnectionError(format!("Invalid WebSocket URL: {}", e))
-        })?;
+/// Open an authenticated connection to the gateway
+pub(crate) async fn connect(config: &CodeReviewConfig) -> Result<WsStream, CodeReviewError> {
+    let mut url = normalize_ws_url(&config.server_url).map_err(CodeReviewError::ConnectionError)?;
+    
+    // Use the caller's token, or generate one for this minute. Every
+    // connection, reconnects included, gets a fresh one, so a review that
+    // crosses a minute boundary never reconnects with an expired token.
+    let token = match &config.auth_token {
+        Some(token) => token.clone(),
+        None => auth_token(&config.app_id, &config.app_secret),
+    };
+    
+    // Add query parameters for authentication
+    url.query_pairs_mut()
+        .append_pair("appid", &config.app_id)
+        .append_pair("token", &token);
         
-        // Generate authentication token
-        let current_minute = chrono::Utc::now().timestamp() / 60;
-        let raw_str = format!("{}{}{}", APP_ID, APP_SECRET, current_minute);
-        let mut hasher = Sha256::new();
-        hasher.update(raw_str.as_bytes());
-        let token = hex::encode(&hasher.finalize())[..10].to_string();
-        
-        // Add query parameters for authentication
-        url.query_pairs_mut()
-            .append_pair("appid", APP_ID)
-            .append_pair("token", &token);
-            
-        // println!("[DEBUG] Connecting to WebSocket with URL: {}", url);
+    tracing::debug!(target: "rig-magi", "Connecting to {}", redact::url(url.as_str()));
 
-        // Connect to WebSocket server
-        let (ws_stream, _) = connect_async(url).await.map_err(|e| {
-            CodeReviewError::ConnectionError(format!("Failed to connect to WebSocket server: {}", e))
+    // Connect to WebSocket server; the error may quote the URL or the response
+    let (ws_stream, _) = connect_async(url)
+        .await
+        .map_err(|e| {
+            CodeReviewError::ConnectionError(format!(
+                "Failed to connect to WebSocket server: {}",
+                redact::secrets(&e.to_string(), &[&config.app_secret, &token])
+            ))
         })?;
-        
-        let (mut write, mut read) = ws_stream.split();
-        
-        // Generate a unique request ID
-        let request_id = Uuid::new_v4().to_string();
-        
-        // Create agent judgement request
-        let agent_request = AgentJudgementRequest {
-            message_type: "agent_judgement".to_string(),
-            request_id: request_id.clone(),
-            request: format!("<user_input>\n{}\n</user_input>\n<response>\n{}\n</response>", args.user_input, args.code),
-            timestamp: chrono::Utc::now().timestamp() as f64,
-            agents: AGENT_IDS.iter().map(|(_, id)| AgentInfo {
-                agent_id: id.to_string(),
-            }).collect(),
+    
+    Ok(ws_stream)
+}
+
+// Reviewers' streamed output printed to stderr, one line at a time prefixed
+// with the agent's name. Each agent's unfinished line is held until it ends
+// or the agent completes, so interleaved chunks stay readable.
+struct LiveOutput<W: Write> {
+    partial: HashMap<String, String>,
+    out: W,
+}
+
+impl<W: Write> LiveOutput<W> {
+    fn new(out: W) -> Self {
+        Self { partial: HashMap::new(), out }
+    }
+
+    fn push(&mut self, agent: &str, chunk: &str) {
+        let partial = self.partial.entry(agent.to_string()).or_default();
+        partial.push_str(chunk);
+        let Some(end) = partial.rfind('\n') else {
+            return;
         };
-        
-        // Send the request
-        write.send(Message::Text(serde_json::to_string(&agent_request).map_err(|e| {
-            CodeReviewError::DeserializationError(format!("Failed to serialize request: {}", e))
-        })?)).await.map_err(|e| {
-            CodeReviewError::WebSocketError(format!("Failed to send review request: {}", e))
-        })?;
-        
-        // Process streaming responses
-        let mut reviews = Vec::new();
-        let mut final_result = String::new();
-        let mut passed = false;
-        let mut magi_state = MAGISystemState::default();
-        let mut completed_agents = HashSet::new();
-        let mut error_messages = Vec::new();
-        
-        // Wait for responses from all three agents
-        while let Some(msg) = read.next().await {
-            let msg = msg.map_err(|e| {
-                CodeReviewError::WebSocketError(format!("Error receiving message: {}", e))
-            })?;
-            
-            if let Message::Text(text) = msg {
-                // println!("[DEBUG] Received message: {}", text);
-                
-                // Try to parse as different message types
-                if let Ok(response) = serde_json::from_str::<AgentResponse>(&text) {
-                    // Only process messages for our request
-                    if response.request_id != request_id {
-                        continue;
-                    }
-                    
-                    // Find which agent this is
-                    let agent_name = AGENT_IDS.iter()
-                        .find(|(_, id)| *id == response.agent_id)
-                        .map(|(name, _)| name)
-                        .unwrap_or(&"unknown");
+        let lines: String = partial.drain(..=end).collect();
+        for line in lines.lines() {
+            let _ = writeln!(self.out, "[{}] {}", display_name(agent), line);
+        }
+        let _ = self.out.flush();
+    }
+
+    // Print what is left of an agent's output
+    fn finish(&mut self, agent: &str) {
+        let Some(rest) = self.partial.remove(agent).filter(|rest| !rest.is_empty()) else {
+            return;
+        };
+        let _ = writeln!(self.out, "[{}] {}", display_name(agent), rest);
+        let _ = self.out.flush();
+    }
+}
+
+// Write each agent's decision and transcript to `<dir>/<request_id>/<agent>.md`
+fn dump_transcripts(dir: &Path, request_id: &str, magi_state: &MAGISystemState) -> std::io::Result<()> {
+    let dir = dir.join(request_id);
+    std::fs::create_dir_all(&dir)?;
+
+    for agent_state in &magi_state.agents {
+        let decision = match agent_state.decision {
+            _ if agent_state.errored => "ERROR",
+            Some(MAGIDecision::POSITIVE) => "POSITIVE",
+            Some(MAGIDecision::NEGATIVE) => "NEGATIVE",
+            None if agent_state.inconclusive => "INCONCLUSIVE",
+            None => "PENDING",
+        };
+        let contents = format!(
+            "# {}\n\nDecision: {}\n\n{}\n",
+            display_name(&agent_state.name),
+            decision,
+            agent_state.transcript()
+        );
+        std::fs::write(dir.join(format!("{}.md", agent_state.name)), contents)?;
+    }
+
+    Ok(())
+}
+
+// The first `[SECURITY][CRITICAL]` issue any agent raised, as
+// `Agent: issue`, in panel order
+fn security_veto(magi_state: &MAGISystemState) -> Option<String> {
+    magi_state.agents.iter().find_map(|agent_state| {
+        verdict::tagged_issues(&agent_state.transcript())
+            .into_iter()
+            .find(|issue| issue.category == "SECURITY" && issue.severity == "CRITICAL")
+            .map(|issue| format!("{}: [SECURITY][CRITICAL] {}", display_name(&agent_state.name), issue.text))
+    })
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct AgentInfo {
+    agent_id: String,
+}
+
+/// Exit status of a passed review
+pub const EXIT_APPROVED: i32 = 0;
+/// Exit status of a review the panel rejected
+pub const EXIT_REJECTED: i32 = 1;
+/// Exit status when there is no clear verdict or the review failed
+pub const EXIT_UNDECIDED: i32 = 2;
+
+/// Version of the `CodeReviewOutput` JSON. Bumped when a field is renamed,
+/// removed or changes meaning; new fields may be added without a bump.
+pub const OUTPUT_SCHEMA_VERSION: u32 = 1;
+
+/// One panel agent's vote, as listed in the output's `decisions`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentDecision {
+    pub agent: String,
+    /// `None` for an agent that hadn't decided when the review finished
+    pub decision: Option<MAGIDecision>,
+    /// The agent failed rather than voted; `decision` is the NEGATIVE it was
+    /// given in its place, which the majority doesn't count
+    #[serde(default)]
+    pub errored: bool,
+}
+
+/// The result of a review, and the tool's output. Its JSON is a stable
+/// contract for programmatic callers, versioned by `schema_version` (see
+/// `OUTPUT_SCHEMA_VERSION` and the README).
+#[derive(Serialize, Deserialize)]
+pub struct CodeReviewOutput {
+    schema_version: u32,
+    reviews: Vec<String>,
+    result: String,
+    passed: bool,
+    /// Every panel agent's vote, in panel order
+    decisions: Vec<AgentDecision>,
+    magi_state: MAGISystemState,
+    code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<String>,
+    /// Agents whose approval was shorter than `min_approval_content`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    weak_approvals: Vec<String>,
+    /// Passed, but only with the help of weak approvals
+    weakly_approved: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    agent_errors: Vec<AgentError>,
+    /// Rejected only because of transient agent errors; reviewing again may succeed
+    retryable: bool,
+    /// Agents that completed without an explicit verdict (strict mode only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    inconclusive: Vec<String>,
+    /// Diagnostics from a failed local compile check; the panel was skipped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compile_errors: Option<String>,
+    /// When passed, the verdict and reasoning sentences of each approving agent
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    approval_evidence: Vec<String>,
+    /// The vote was within the configured close-call band
+    close_call: bool,
+    /// Passed with exactly the required POSITIVE votes (`minimum_margin_mode`
+    /// `flag` only)
+    minimum_margin: bool,
+    /// The result came from the gateway's consolidated verdict rather than
+    /// being computed from the agents' votes
+    gateway_decided: bool,
+    /// Consensus after each vote, in arrival order
+    decision_trace: DecisionTrace,
+    /// Agents still reviewing when the outcome was settled, whose verdicts
+    /// were not waited for
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pending_agents: Vec<String>,
+    /// Extra reviews run because the first was a split (`rereview_on_split`)
+    rereviews: usize,
+    /// The verdict came from `verdict_overrides`; the panel wasn't asked
+    overridden: bool,
+    /// The critical security issue that vetoed the panel's approval
+    /// (`security_veto`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    veto: Option<String>,
+    /// The risk tier whose quorum applied (`risk_tiers`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    risk_tier: Option<String>,
+    /// Taken from the review cache (`review_cache_dir`); the panel wasn't asked
+    #[serde(default)]
+    cached: bool,
+    /// The panel didn't finish within `review_timeout`. The agents listed in
+    /// `pending_agents` were counted NEGATIVE.
+    #[serde(default)]
+    timed_out: bool,
+}
+
+impl CodeReviewOutput {
+    pub fn passed(&self) -> bool {
+        self.passed
+    }
+
+    pub fn decisions(&self) -> &[AgentDecision] {
+        &self.decisions
+    }
+
+    /// The output as the JSON described by `OUTPUT_SCHEMA_VERSION`
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or(serde_json::Value::Null)
+    }
+
+    pub fn magi_state(&self) -> &MAGISystemState {
+        &self.magi_state
+    }
+
+    pub fn decision_trace(&self) -> &DecisionTrace {
+        &self.decision_trace
+    }
+
+    pub fn retryable(&self) -> bool {
+        self.retryable
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// Process exit status for batch use: `EXIT_APPROVED`, `EXIT_REJECTED`,
+    /// or `EXIT_UNDECIDED` when a rejection isn't a clear verdict (no
+    /// decision was reached, an agent was inconclusive or errored, or the
+    /// review timed out)
+    pub fn exit_code(&self) -> i32 {
+        if self.passed {
+            EXIT_APPROVED
+        } else if self.result.is_empty() || self.timed_out || !self.inconclusive.is_empty() || !self.agent_errors.is_empty() {
+            EXIT_UNDECIDED
+        } else {
+            EXIT_REJECTED
+        }
+    }
+}
+
+// Compact JSON, as sent back to the model
+impl fmt::Display for CodeReviewOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let json = serde_json::to_string(self).map_err(|_| fmt::Error)?;
+        f.write_str(&json)
+    }
+}
+
+pub struct CodeReviewTool {
+    config: CodeReviewConfig,
+    events: Option<EventSink>,
+    extractor: Arc<dyn VerdictExtractor>,
+    #[cfg(feature = "chaos")]
+    faults: Option<FaultPlan>,
+    pool: Option<Arc<ConnectionPool>>,
+    latency: Arc<LatencyTracker>,
+}
+
+impl CodeReviewTool {
+    pub fn new() -> Self {
+        Self::with_config(CodeReviewConfig::from_env())
+    }
+
+    pub fn with_config(config: CodeReviewConfig) -> Self {
+        // Strict mode only accepts explicit verdicts
+        let markers = config.verdict_markers.clone();
+        let extractor: Arc<dyn VerdictExtractor> = if config.strict_verdict {
+            Arc::new(JsonVerdictExtractor { markers })
+        } else {
+            Arc::new(HeuristicExtractor { markers })
+        };
+
+        // Keep connections ready ahead of the first review, when enabled
+        let pool = (config.warm_connections > 0)
+            .then(|| ConnectionPool::new(config.clone(), config.warm_connections));
+
+        Self {
+            config,
+            events: None,
+            extractor,
+            #[cfg(feature = "chaos")]
+            faults: None,
+            pool,
+            latency: Arc::new(LatencyTracker::default()),
+        }
+    }
+
+    /// Decide agents' votes with a custom extractor instead of the configured one
+    pub fn with_extractor(mut self, extractor: impl VerdictExtractor + 'static) -> Self {
+        self.extractor = Arc::new(extractor);
+        self
+    }
+
+    /// Emit `ReviewEvent`s on the given channel as the review progresses
+    pub fn with_events(mut self, events: EventSink) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Authenticate as the given tenant instead of with the configured
+    /// credentials. Set it before the first review; pre-warmed connections
+    /// are reopened with the new credentials.
+    pub fn with_credentials(mut self, credentials: GatewayCredentials) -> Self {
+        match credentials {
+            GatewayCredentials::Secret { app_id, app_secret } => {
+                self.config.app_id = app_id;
+                self.config.app_secret = app_secret;
+                self.config.auth_token = None;
+            }
+            GatewayCredentials::Token { app_id, token } => {
+                self.config.app_id = app_id;
+                self.config.auth_token = Some(token);
+            }
+        }
+
+        if self.pool.is_some() {
+            self.pool = Some(ConnectionPool::new(self.config.clone(), self.config.warm_connections));
+        }
+        self
+    }
+
+    /// Inject the plan's faults into the frames received from the gateway
+    #[cfg(feature = "chaos")]
+    pub fn with_faults(mut self, faults: FaultPlan) -> Self {
+        self.faults = Some(faults);
+        self
+    }
+}
+
+impl Default for CodeReviewTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Shared with the other reviewers that expose the same `code_review` interface
+pub(crate) fn tool_definition() -> ToolDefinition {
+    ToolDefinition {
+        name: CodeReviewTool::NAME.to_string(),
+        description: "Review generated code through a panel of expert reviewers".to_string(),
+        parameters: json!({
+            "type": "object",
+            "properties": {
+                "user_input": {
+                    "type": "string",
+                    "description": "The user input to the code review tool"
+                },
+                "code": {
+                    "type": "string",
+                    "description": "The code to be reviewed"
+                },
+                "files": {
+                    "type": "array",
+                    "description": "For changes spanning several files: each file's path and content, reviewed together instead of code",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "path": { "type": "string" },
+                            "content": { "type": "string" }
+                        },
+                        "required": ["path", "content"]
+                    }
+                },
+                "language": {
+                    "type": "string",
+                    "description": "The programming language of the code, e.g. rust or python"
+                },
+                "rubric": {
+                    "type": "string",
+                    "description": "Optional checklist of review criteria; omit to use the configured rubric"
+                },
+                "tests": {
+                    "type": "string",
+                    "description": "Optional test cases the code should pass; omit to use the configured tests"
+                }
+            },
+            "required": []
+        }),
+    }
+}
+
+impl Tool for CodeReviewTool {
+    const NAME: &'static str = "code_review";
+    type Error = CodeReviewError;
+    type Args = CodeReviewArgs;
+    type Output = CodeReviewOutput;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        tool_definition()
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        // Risky code is reviewed under its tier's quorum
+        let Some(tier) = self.config.risk_tier(&args.submission()) else {
+            return self.decide(args).await;
+        };
+        let quorum = tier.quorum_for(self.config.agents.len());
+        tracing::info!(target: "rig-magi",
+            tier = %tier.name,
+            quorum = ?quorum,
+            "Submission matches a risk tier"
+        );
+
+        let name = tier.name.clone();
+        let mut output = self.with_quorum(quorum).decide(args).await?;
+        output.risk_tier = Some(name);
+        Ok(output)
+    }
+}
+
+impl CodeReviewTool {
+    // Review, re-reviewing narrow decisions when enabled
+    async fn decide(&self, args: CodeReviewArgs) -> Result<CodeReviewOutput, CodeReviewError> {
+        // A verdict reached before, possibly by another process, for the
+        // same submission under the same panel and policy
+        let cache = ReviewCache::from_config(&self.config)
+            .map(|cache| (ReviewCache::key(&self.config, &args), cache));
+        if let Some(mut output) = cache.as_ref().and_then(|(key, cache)| cache.get(key)) {
+            tracing::info!(target: "rig-magi", "Review cache hit, skipping the panel");
+            output.correlation_id = args.correlation_id;
+            output.cached = true;
+            return Ok(output);
+        }
+        
+        let output = self.review_once(args.clone()).await?;
+        
+        // A narrow loss may go the other way on another run, and a narrow
+        // pass may be re-checked the same way, when enabled
+        let split = self.config.rereview_on_split > 0 && self.is_split(&output);
+        let minimum_pass = self.config.minimum_margin_mode == MinimumMarginMode::Rerun && self.is_minimum_pass(&output);
+        let output = if split || minimum_pass {
+            self.rereview(args, output).await?
+        } else {
+            output
+        };
+        
+        // Only clear verdicts are worth reusing
+        if let Some((key, cache)) = &cache {
+            if output.exit_code() != EXIT_UNDECIDED {
+                cache.put(key, &output);
+            }
+        }
+        Ok(output)
+    }
+
+    // The same tool under a different quorum, sharing its connections and
+    // event sink
+    fn with_quorum(&self, quorum: Option<usize>) -> Self {
+        let mut config = self.config.clone();
+        config.quorum = quorum;
+        Self {
+            config,
+            events: self.events.clone(),
+            extractor: self.extractor.clone(),
+            #[cfg(feature = "chaos")]
+            faults: self.faults.clone(),
+            pool: self.pool.clone(),
+            latency: self.latency.clone(),
+        }
+    }
+
+    /// Review like `call`, bundling the output with per-agent timing, the
+    /// total duration and metadata
+    pub async fn review_report(&self, args: CodeReviewArgs) -> Result<ReviewReport, CodeReviewError> {
+        let started_at = Utc::now();
+        let output = self.call(args).await?;
+        Ok(ReviewReport::new(output, started_at, &self.config.server_url))
+    }
+
+    // One review under a fresh request id
+    async fn review_once(&self, args: CodeReviewArgs) -> Result<CodeReviewOutput, CodeReviewError> {
+        // Generate a unique request ID
+        let request_id = Uuid::new_v4().to_string();
+        
+        let review_span = tracing::info_span!(target: "rig-magi", "code_review",
+            request_id = %request_id,
+            correlation_id = ?args.correlation_id,
+        );
+        
+        self.review(args, request_id).instrument(review_span).await
+    }
+    
+    // A rejection by the narrowest margin with every agent decided, e.g. one
+    // POSITIVE vote under 2-of-3
+    fn is_split(&self, output: &CodeReviewOutput) -> bool {
+        let (positive, required) = self.tally(&output.magi_state);
+        !output.passed
+            && !output.gateway_decided
+            && output.veto.is_none()
+            && output.compile_errors.is_none()
+            && output.magi_state.pending_agents().is_empty()
+            && positive + 1 == required
+    }
+    
+    // A pass by exactly the required votes, e.g. two POSITIVE under 2-of-3
+    fn is_minimum_pass(&self, output: &CodeReviewOutput) -> bool {
+        output.passed && !output.gateway_decided && !output.overridden && self.no_margin(&output.magi_state)
+    }
+    
+    // Re-run a narrow review up to `rereview_on_split` times (at least once)
+    // and go with the majority of all runs. A tie is rejected. A failed
+    // re-run ends the re-reviews with the runs so far.
+    async fn rereview(&self, args: CodeReviewArgs, first: CodeReviewOutput) -> Result<CodeReviewOutput, CodeReviewError> {
+        let runs = usize::from(self.config.rereview_on_split.max(1)) + 1;
+        let majority = runs / 2 + 1;
+        let (mut passes, mut fails) = if first.passed { (1, 0) } else { (0, 1) };
+        let (mut latest_pass, mut latest_fail) = if first.passed { (Some(first), None) } else { (None, Some(first)) };
+        let mut rereviews = 0;
+        
+        while passes < majority && fails < majority && rereviews + 1 < runs {
+            rereviews += 1;
+            tracing::info!(target: "rig-magi",
+                "Narrow decision, re-reviewing ({}/{})",
+                rereviews,
+                runs - 1
+            );
+            
+            match self.review_once(args.clone()).await {
+                Ok(output) if output.passed => {
+                    passes += 1;
+                    latest_pass = Some(output);
+                }
+                Ok(output) => {
+                    fails += 1;
+                    latest_fail = Some(output);
+                }
+                Err(e) => {
+                    tracing::warn!(target: "rig-magi", "Re-review failed: {}", e);
+                    rereviews -= 1;
+                    break;
+                }
+            }
+        }
+        
+        let mut output = match (latest_pass, latest_fail) {
+            (Some(output), _) if passes > fails => output,
+            (_, Some(output)) | (Some(output), None) => output,
+            (None, None) => unreachable!("the first review is always kept"),
+        };
+        output.rereviews = rereviews;
+        Ok(output)
+    }
+    
+    async fn review(&self, args: CodeReviewArgs, request_id: String) -> Result<CodeReviewOutput, CodeReviewError> {
+        // Known snippets get their configured verdict without a review
+        if let Some(output) = self.verdict_override(&args) {
+            return Ok(output);
+        }
+        
+        // Code in a language this deployment doesn't handle never reaches the panel
+        if let Some(output) = self.language_gate(&args) {
+            return Ok(output);
+        }
+        
+        // Send obviously broken code straight back instead of to the panel
+        if let Some(output) = self.compile_gate(&args).await {
+            return Ok(output);
+        }
+        
+        // A duplicated agent would never complete its second slot
+        self.config.check_panel()?;
+        
+        // Reuse a pre-warmed connection when there is one
+        let ws_stream = match &self.pool {
+            Some(pool) => pool.get().await?,
+            None => connect(&self.config)
+                .instrument(tracing::info_span!(target: "rig-magi", "connect"))
+                .await?,
+        };
+        
+        let (mut write, mut read) = ws_stream.split();
+        
+        let mut request_body = format!("<user_input>\n{}\n</user_input>\n<response>\n{}\n</response>", args.user_input, args.submission());
+        
+        // Let the agents judge the code against concrete expectations
+        if let Some(tests) = args.tests.as_ref().or(self.config.tests.as_ref()) {
+            request_body.push_str(&format!("\n<tests>\n{}\n</tests>", tests.trim()));
+        }
+        
+        // Have every agent review against the same rubric, when there is one
+        if let Some(rubric) = args.rubric.as_ref().or(self.config.rubric.as_ref()) {
+            request_body.push_str(&format!("\n<rubric>\n{}\n</rubric>", rubric.trim()));
+        }
+        
+        // Send oversized requests ahead of the judgement as numbered chunks
+        let chunks = match self.config.chunk_size {
+            Some(chunk_size) if request_body.len() > chunk_size => split_into_chunks(&request_body, chunk_size),
+            _ => Vec::new(),
+        };
+        
+        // Create agent judgement request
+        let agent_request = AgentJudgementRequest {
+            message_type: self.config.message_types.judgement.clone(),
+            request_id: request_id.clone(),
+            request: if chunks.is_empty() { request_body.clone() } else { String::new() },
+            timestamp: chrono::Utc::now().timestamp() as f64,
+            agents: self.config.agents.iter().map(|agent| AgentInfo {
+                agent_id: agent.id.clone(),
+            }).collect(),
+            chunk_count: (!chunks.is_empty()).then_some(chunks.len()),
+            correlation_id: args.correlation_id.clone(),
+            trace_context: trace_context(),
+            protocol_version: self.config.protocol_version,
+        };
+        
+        tracing::debug!(target: "rig-magi",
+            "Sending judgement request {}",
+            request_id
+        );
+        
+        self.send_request(&mut write, &request_id, &chunks, &agent_request)
+            .instrument(tracing::info_span!(target: "rig-magi", "send", chunks = chunks.len()))
+            .await?;
+        
+        // Process streaming responses
+        let mut reviews = Vec::new();
+        let mut final_result = String::new();
+        let mut passed = false;
+        let mut magi_state = MAGISystemState::new(&self.config.agents);
+        let mut completed_agents = HashSet::new();
+        let mut error_messages = Vec::new();
+        let mut weak_approvals = Vec::new();
+        let mut agent_errors: Vec<AgentError> = Vec::new();
+        let mut inconclusive = Vec::new();
+        // Terminal (agent id, status) pairs already applied, so a frame that is
+        // re-reported doesn't re-run decision extraction or count twice
+        let mut seen_terminal: HashSet<(String, String)> = HashSet::new();
+        let mut gateway_decided = false;
+        let mut decision_trace = DecisionTrace::default();
+        let mut not_ready_retries = 0;
+        // Resubmissions of agents that errored transiently, by agent id
+        let mut agent_retries: HashMap<String, u8> = HashMap::new();
+        // Reviewers' output echoed as it arrives, when enabled
+        let mut live_output = self.config.stream_output.then(|| LiveOutput::new(std::io::stderr()));
+        // Once the outcome is settled, how long trailing chunks are still taken
+        let mut settle_deadline: Option<tokio::time::Instant> = None;
+        
+        // Bigger submissions get longer to review, computed once per review
+        let review_timeout = self.config.review_timeout_for(args.submission().len());
+        let review_deadline = review_timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+        let mut timed_out = false;
+        let mut timed_out_agents = Vec::new();
+        // Reconnects after the connection dropped mid-review
+        let mut reconnects: u8 = 0;
+        let mut connection_lost = false;
+        
+        // Periodically log which agents are still pending while we wait
+        let started = std::time::Instant::now();
+        let mut heartbeat = tokio::time::interval(self.config.heartbeat_interval.unwrap_or(Duration::from_secs(3600)));
+        heartbeat.tick().await; // The first tick completes immediately
+        #[cfg(feature = "chaos")]
+        let mut frame_index = 0;
+        
+        // Wait for responses from every agent on the panel
+        loop {
+            let msg = tokio::select! {
+                msg = read.next() => msg,
+                _ = heartbeat.tick(), if self.config.heartbeat_interval.is_some() => {
+                    let pending: Vec<String> = magi_state
+                        .agents
+                        .iter()
+                        .filter(|state| !completed_agents.contains(&state.name))
+                        .map(|state| state.name.clone())
+                        .collect();
+                    let elapsed = started.elapsed();
+                    let eta = self.latency.eta(pending.iter().map(String::as_str), elapsed);
+                    match eta {
+                        Some(eta) => tracing::info!(target: "rig-magi",
+                            pending = ?pending,
+                            "Waiting on {} ({}s elapsed, about {}s left)",
+                            pending.join(", "),
+                            elapsed.as_secs(),
+                            eta.as_secs()
+                        ),
+                        None => tracing::info!(target: "rig-magi",
+                            pending = ?pending,
+                            "Waiting on {} ({}s elapsed)",
+                            pending.join(", "),
+                            elapsed.as_secs()
+                        ),
+                    }
+                    self.emit(ReviewEvent::Progress {
+                        request_id: request_id.clone(),
+                        pending,
+                        elapsed_ms: elapsed.as_millis() as u64,
+                        eta_ms: eta.map(|eta| eta.as_millis() as u64),
+                    }).await;
+                    continue;
+                }
+                _ = tokio::time::sleep_until(settle_deadline.unwrap_or_else(tokio::time::Instant::now)), if settle_deadline.is_some() => {
+                    break;
+                }
+                // A settled review isn't timed out, only cut short
+                _ = tokio::time::sleep_until(review_deadline.unwrap_or_else(tokio::time::Instant::now)), if review_deadline.is_some() && settle_deadline.is_none() => {
+                    let pending = magi_state.pending_agents();
+                    tracing::warn!(target: "rig-magi",
+                        "Review timed out after {:?}, still waiting on {}",
+                        review_timeout.unwrap_or_default(),
+                        pending.join(", ")
+                    );
+                    error_messages.push(format!(
+                        "Review timed out after {}s waiting on {}",
+                        review_timeout.unwrap_or_default().as_secs(),
+                        pending.join(", ")
+                    ));
+                    // Agents that didn't answer in time count against the code
+                    for agent_state in &mut magi_state.agents {
+                        if agent_state.decision.is_none() && !agent_state.inconclusive {
+                            agent_state.decision = Some(MAGIDecision::NEGATIVE);
+                        }
+                    }
+                    if let Some(decision) = self.final_decision(&magi_state) {
+                        passed = decision == MAGIDecision::POSITIVE;
+                        final_result = if passed { "POSITIVE" } else { "NEGATIVE" }.to_string();
+                    }
+                    timed_out_agents = pending;
+                    timed_out = true;
+                    break;
+                }
+            };
+            
+            let msg = match msg {
+                Some(Ok(msg)) => msg,
+                // The connection dropped before the panel finished
+                lost => {
+                    let reason = match lost {
+                        Some(Err(e)) => format!("Error receiving message: {}", e),
+                        _ => "Connection closed by the gateway".to_string(),
+                    };
                     
-                    // Add to reviews
-                    let review_msg = format!("Reviewer {}: {}", agent_name, response.content);
-                    reviews.push(review_msg.clone());
+                    // Trailing chunks of a settled review aren't worth a reconnect
+                    let pending_ids: Vec<String> = magi_state
+                        .agents
+                        .iter()
+                        .filter(|state| !completed_agents.contains(&state.name))
+                        .map(|state| state.agent_id.clone())
+                        .collect();
+                    if settle_deadline.is_some() || pending_ids.is_empty() {
+                        break;
+                    }
+                    if reconnects >= self.config.reconnect_attempts {
+                        tracing::warn!(target: "rig-magi",
+                            "{}, giving up after {} reconnects",
+                            reason,
+                            reconnects
+                        );
+                        error_messages.push(format!("{}; gave up after {} reconnects", reason, reconnects));
+                        connection_lost = true;
+                        break;
+                    }
                     
-                    // Update MAGI state
-                    let agent_state = match *agent_name {
-                        "melchior" => &mut magi_state.melchior,
-                        "balthasar" => &mut magi_state.balthasar,
-                        "casper" => &mut magi_state.casper,
-                        _ => continue,
+                    let delay = reconnect_delay(self.config.reconnect_delay, reconnects);
+                    reconnects += 1;
+                    tracing::warn!(target: "rig-magi",
+                        "{}, reconnecting in {:?} ({}/{})",
+                        reason,
+                        delay,
+                        reconnects,
+                        self.config.reconnect_attempts
+                    );
+                    tokio::time::sleep(delay).await;
+                    
+                    // A failed reconnect counts as an attempt; the next read
+                    // on the dead stream comes straight back here
+                    let ws_stream = match connect(&self.config).await {
+                        Ok(ws_stream) => ws_stream,
+                        Err(e) => {
+                            tracing::warn!(target: "rig-magi", "Reconnect failed: {}", e);
+                            continue;
+                        }
                     };
+                    (write, read) = ws_stream.split();
                     
-                    agent_state.messages.push(MAGIMessage {
-                        request_id: response.request_id.clone(),
-                        content: response.content.clone(),
-                        timestamp: Utc::now(),
+                    // Only the agents still reviewing are asked again, under
+                    // the same request id. Their partial output is resent
+                    // from the start, and completions already counted are
+                    // ignored if they arrive twice.
+                    for agent_state in magi_state.agents.iter_mut().filter(|state| pending_ids.contains(&state.agent_id)) {
+                        agent_state.messages.clear();
+                    }
+                    let resumed_request = AgentJudgementRequest {
+                        timestamp: chrono::Utc::now().timestamp() as f64,
+                        agents: pending_ids.into_iter().map(|agent_id| AgentInfo { agent_id }).collect(),
+                        ..agent_request.clone()
+                    };
+                    self.send_request(&mut write, &request_id, &chunks, &resumed_request)
+                        .instrument(tracing::info_span!(target: "rig-magi", "send", chunks = chunks.len()))
+                        .await?;
+                    continue;
+                }
+            };
+            
+            #[cfg(feature = "chaos")]
+            let msg = {
+                let index = frame_index;
+                frame_index += 1;
+                match &self.faults {
+                    Some(faults) => match faults.apply(index, msg).await {
+                        Some(msg) => msg,
+                        None => break,
+                    },
+                    None => msg,
+                }
+            };
+            
+            if let Message::Text(text) = msg {
+                // Try to parse as different message types
+                if let Some(established) = serde_json::from_str::<ConnectionEstablished>(&text)
+                    .ok()
+                    .filter(|frame| frame.message_type == CONNECTION_ESTABLISHED)
+                {
+                    // Replies in a format this client doesn't expect would be misread
+                    if !established.accepts(self.config.protocol_version) {
+                        let offered = match (&established.supported_versions, established.protocol_version) {
+                            (Some(supported), _) => format!("{:?}", supported),
+                            (None, Some(own)) => own.to_string(),
+                            (None, None) => "unknown".to_string(),
+                        };
+                        return Err(CodeReviewError::ProtocolError(format!(
+                            "gateway speaks protocol version {}, this client is pinned to {}",
+                            offered, self.config.protocol_version
+                        )));
+                    }
+                    continue;
+                } else if let Some(not_ready) = serde_json::from_str::<NotReady>(&text)
+                    .ok()
+                    .filter(|frame| NOT_READY_TYPES.contains(&frame.message_type.as_str()))
+                {
+                    if not_ready.request_id.as_ref().is_some_and(|id| *id != request_id) {
+                        continue;
+                    }
+                    
+                    let reason = not_ready.reason.unwrap_or_else(|| "no reason given".to_string());
+                    let retry_after = match not_ready.retry_after {
+                        Some(secs) if not_ready_retries < MAX_NOT_READY_RETRIES => secs,
+                        _ => {
+                            return Err(CodeReviewError::ConnectionError(format!("gateway not ready: {}", reason)));
+                        }
+                    };
+                    
+                    // Back off from the gateway's hint, doubling on each retry
+                    let delay = Duration::from_secs_f64(retry_after.clamp(0.0, MAX_NOT_READY_DELAY.as_secs_f64()))
+                        .saturating_mul(2u32.pow(not_ready_retries))
+                        .min(MAX_NOT_READY_DELAY);
+                    not_ready_retries += 1;
+                    tracing::warn!(target: "rig-magi",
+                        "Gateway not ready ({}), retrying in {:?} ({}/{})",
+                        reason,
+                        delay,
+                        not_ready_retries,
+                        MAX_NOT_READY_RETRIES
+                    );
+                    tokio::time::sleep(delay).await;
+                    
+                    self.send_request(&mut write, &request_id, &chunks, &agent_request)
+                        .instrument(tracing::info_span!(target: "rig-magi", "send", chunks = chunks.len()))
+                        .await?;
+                    continue;
+                } else if let Some(verdict) = serde_json::from_str::<FinalVerdict>(&text)
+                    .ok()
+                    .filter(|verdict| verdict.message_type == self.config.message_types.final_decision)
+                {
+                    // Only process messages for our request
+                    if verdict.request_id != request_id {
+                        continue;
+                    }
+                    
+                    // Fill in the per-agent breakdown, when the gateway sends one
+                    for agent in &verdict.agents {
+                        let Some(agent_state) = magi_state.agent_mut(&agent.agent_id) else {
+                            continue;
+                        };
+                        agent_state.decision = self.config.verdict_markers.decision_for(&agent.decision);
+                        if let Some(content) = &agent.content {
+                            if agent_state.messages.is_empty() {
+                                agent_state.append_message(&verdict.request_id, &self.decode_content(content));
+                            }
+                        }
+                    }
+                    
+                    // The gateway's decision is authoritative; anything unrecognized fails closed
+                    let decision = self.config.verdict_markers.decision_for(&verdict.decision).unwrap_or_else(|| {
+                        tracing::warn!(target: "rig-magi", "Unrecognized final decision: {}", verdict.decision);
+                        MAGIDecision::NEGATIVE
                     });
+                    passed = decision == MAGIDecision::POSITIVE;
+                    final_result = if passed { "POSITIVE" } else { "NEGATIVE" }.to_string();
+                    gateway_decided = true;
+                    tracing::info!(target: "rig-magi",
+                        decision = ?decision,
+                        "Gateway sent final decision"
+                    );
+                    break;
+                } else if let Some(mut response) = serde_json::from_str::<AgentResponse>(&text)
+                    .ok()
+                    .filter(|response| response.message_type == self.config.message_types.agent_response)
+                {
+                    // Only process messages for our request
+                    if response.request_id != request_id {
+                        continue;
+                    }
+                    response.content = self.decode_content(&response.content);
+                    
+                    // Ignore a completion this agent already reported, e.g. after a resend
+                    if response.status == "completed"
+                        && !seen_terminal.insert((response.agent_id.clone(), response.status.clone()))
+                    {
+                        tracing::debug!(target: "rig-magi", agent_id = %response.agent_id, "Ignoring duplicate completion");
+                        continue;
+                    }
+                    
+                    // Find which agent this is
+                    let agent_state = match magi_state.agent_mut(&response.agent_id) {
+                        Some(state) => state,
+                        None => continue,
+                    };
+                    
+                    // Drop a chunk the gateway re-sent
+                    if self.config.dedupe_chunks && response.status != "completed" && agent_state.repeats_last(&response.content) {
+                        tracing::debug!(target: "rig-magi", agent = %agent_state.name, "Ignoring repeated chunk");
+                        continue;
+                    }
+                    
+                    // Add to reviews
+                    let review_msg = format!("Reviewer {}: {}", agent_state.name, response.content);
+                    reviews.push(review_msg.clone());
                     
-                    // Append content to agent state
-                    agent_state.content.push_str(&response.content);
+                    agent_state.append_message(&response.request_id, &response.content);
+                    self.trace_agent(agent_state, &response.status, &response.content);
+                    if let Some(live_output) = live_output.as_mut() {
+                        live_output.push(&agent_state.name, &response.content);
+                        if response.status == "completed" {
+                            live_output.finish(&agent_state.name);
+                        }
+                    }
+                    self.emit(ReviewEvent::Chunk {
+                        request_id: request_id.clone(),
+                        agent: agent_state.name.clone(),
+                        content: response.content.clone(),
+                    }).await;
                     
                     // Check if this is a completion message
                     if response.status == "completed" {
                         // Extract decision from content
-                        if response.content.contains("POSITIVE") {
-                            agent_state.decision = Some(MAGIDecision::POSITIVE);
-                        } else {
-                            agent_state.decision = Some(MAGIDecision::NEGATIVE);
+                        let verdict = self.extractor.extract(&agent_state.name, &response.content);
+                        agent_state.decision = verdict.map(|(decision, _)| decision);
+                        agent_state.confidence = verdict.and_then(|(_, confidence)| confidence);
+                        if agent_state.decision.is_none() {
+                            error_messages.push(format!("Reviewer {} gave no explicit verdict", agent_state.name));
+                            agent_state.inconclusive = true;
+                            inconclusive.push(agent_state.name.clone());
                         }
                         
-                        completed_agents.insert(agent_name.to_string());
+                        if self.check_weak_approval(agent_state) {
+                            weak_approvals.push(agent_state.name.clone());
+                        }
+                        self.trace_agent(agent_state, "decided", "");
                         
-                        // If all agents have completed, determine final result
-                        if completed_agents.len() >= 3 {
-                            // Get final decision
-                            if let Some(decision) = magi_state.get_final_decision() {
-                                match decision {
-                                    MAGIDecision::POSITIVE => {
-                                        final_result = "POSITIVE".to_string();
-                                        passed = true;
-                                        let output = CodeReviewOutput {
-                                            reviews,
-                                            result: final_result,
-                                            passed,
-                                            magi_state,
-                                            code: args.code,
-                                        };
-                                        return Ok(output);
-                                    },
-                                    MAGIDecision::NEGATIVE => {
-                                        final_result = "NEGATIVE".to_string();
-                                        passed = false;
-                                        let output = CodeReviewOutput {
-                                            reviews,
-                                            result: final_result,
-                                            passed,
-                                            magi_state,
-                                            code: args.code,
-                                        };
-                                        return Ok(output);
-                                    },
+                        tracing::info!(target: "rig-magi",
+                            agent = %agent_state.name,
+                            agent_id = %agent_state.agent_id,
+                            status = "completed",
+                            decision = ?agent_state.decision,
+                            "Agent completed"
+                        );
+                        self.emit(ReviewEvent::AgentCompleted {
+                            request_id: request_id.clone(),
+                            agent: agent_state.name.clone(),
+                            decision: agent_state.decision,
+                        }).await;
+                        let agent = agent_state.name.clone();
+                        completed_agents.insert(agent.clone());
+                        self.latency.record(&agent, started.elapsed());
+                        decision_trace.record(&agent, &magi_state, self.vote_weights(), self.determined(&magi_state));
+                        
+                        // Determine the final result as soon as it can't change
+                        if let Some(decision) = self.settled(&magi_state) {
+                            match decision {
+                                MAGIDecision::POSITIVE => {
+                                    final_result = "POSITIVE".to_string();
+                                    passed = true;
+                                },
+                                MAGIDecision::NEGATIVE => {
+                                    final_result = "NEGATIVE".to_string();
+                                    passed = false;
+                                },
+                            }
+                            // Exit loop once we have a final decision, unless trailing chunks are awaited
+                            match self.settle_window() {
+                                Some(window) => {
+                                    settle_deadline.get_or_insert_with(|| tokio::time::Instant::now() + window);
+                                }
+                                None => break,
+                            }
+                        }
+                    }
+                } else if let Ok(error_response) = serde_json::from_str::<AgentErrorResponse>(&text) {
+                    // Handle error responses (checked before MessageReceived, which
+                    // error frames would otherwise also parse as, with empty content)
+                    if error_response.request_id == request_id {
+                        // Ignore an error this agent already reported
+                        if !seen_terminal.insert((error_response.agent_id.clone(), "error".to_string())) {
+                            tracing::debug!(target: "rig-magi", agent_id = %error_response.agent_id, "Ignoring duplicate error");
+                            continue;
+                        }
+                        
+                        let agent_state = match magi_state.agent_mut(&error_response.agent_id) {
+                            Some(state) => state,
+                            None => continue,
+                        };
+                        
+                        let agent_error = AgentError::new(&agent_state.name, error_response.error);
+                        
+                        // Resubmit to this agent alone, keeping the other agents' verdicts
+                        let retries = agent_retries.entry(error_response.agent_id.clone()).or_insert(0);
+                        if agent_error.category == ErrorCategory::Transient && *retries < self.config.agent_retries {
+                            *retries += 1;
+                            tracing::info!(target: "rig-magi",
+                                agent = %agent_state.name,
+                                agent_id = %agent_state.agent_id,
+                                status = "retry",
+                                category = ?agent_error.category,
+                                error = %agent_error.message,
+                                retry = *retries,
+                                max_retries = self.config.agent_retries,
+                                "Resubmitting after a transient error"
+                            );
+                            self.trace_agent(agent_state, "retry", &agent_error.message);
+                            
+                            // The failed attempt's partial output isn't part of the verdict
+                            agent_state.messages.clear();
+                            seen_terminal.remove(&(error_response.agent_id.clone(), "error".to_string()));
+                            seen_terminal.remove(&(error_response.agent_id.clone(), "completed".to_string()));
+                            
+                            let retry_request = AgentJudgementRequest {
+                                timestamp: chrono::Utc::now().timestamp() as f64,
+                                agents: vec![AgentInfo {
+                                    agent_id: error_response.agent_id.clone(),
+                                }],
+                                ..agent_request.clone()
+                            };
+                            self.send_request(&mut write, &request_id, &chunks, &retry_request)
+                                .instrument(tracing::info_span!(target: "rig-magi", "send", chunks = chunks.len()))
+                                .await?;
+                            continue;
+                        }
+                        
+                        error_messages.push(agent_error.to_string());
+                        
+                        agent_state.append_message(&error_response.request_id, &format!("ERROR: {}", agent_error.message));
+                        self.trace_agent(agent_state, "error", &agent_error.message);
+                        
+                        // Mark this agent as completed with a NEGATIVE decision
+                        agent_state.decision = Some(MAGIDecision::NEGATIVE);
+                        agent_state.errored = true;
+                        tracing::info!(target: "rig-magi",
+                            agent = %agent_state.name,
+                            agent_id = %agent_state.agent_id,
+                            status = "error",
+                            decision = ?agent_state.decision,
+                            category = ?agent_error.category,
+                            error = %agent_error.message,
+                            "Agent errored"
+                        );
+                        self.emit(ReviewEvent::AgentErrored {
+                            request_id: request_id.clone(),
+                            agent: agent_state.name.clone(),
+                            message: agent_error.message.clone(),
+                        }).await;
+                        let agent = agent_state.name.clone();
+                        completed_agents.insert(agent.clone());
+                        agent_errors.push(agent_error);
+                        if let Some(live_output) = live_output.as_mut() {
+                            live_output.finish(&agent);
+                        }
+                        decision_trace.record(&agent, &magi_state, self.vote_weights(), self.determined(&magi_state));
+                        
+                        // Determine the final result as soon as it can't change
+                        if let Some(decision) = self.settled(&magi_state) {
+                            passed = decision == MAGIDecision::POSITIVE;
+                            final_result = if passed { "POSITIVE" } else { "NEGATIVE" }.to_string();
+                            match self.settle_window() {
+                                Some(window) => {
+                                    settle_deadline.get_or_insert_with(|| tokio::time::Instant::now() + window);
                                 }
-                                break; // Exit loop once we have a final decision
+                                None => break,
                             }
                         }
                     }
-                } else if let Ok(message) = serde_json::from_str::<MessageReceived>(&text) {
+                } else if let Ok(mut message) = serde_json::from_str::<MessageReceived>(&text) {
                     // Process agent_response messages
-                    if message.message_type == "agent_response" {
+                    if message.message_type == self.config.message_types.agent_response {
                         // Only process messages for our request
                         if message.request_id != request_id {
                             continue;
                         }
+                        message.content = self.decode_content(&message.content);
                         
-                        // Find which agent this is
-                        let agent_name = AGENT_IDS.iter()
-                            .find(|(_, id)| *id == message.agent_id)
-                            .map(|(name, _)| name)
-                            .unwrap_or(&"unknown");
+                        // Ignore a completion this agent already reported, e.g. after a resend
+                        if message.status == "completed"
+                            && !seen_terminal.insert((message.agent_id.clone(), message.status.clone()))
+                        {
+                            tracing::debug!(target: "rig-magi", agent_id = %message.agent_id, "Ignoring duplicate completion");
+                            continue;
+                        }
                         
-                        // Update MAGI state
-                        let agent_state = match *agent_name {
-                            "melchior" => &mut magi_state.melchior,
-                            "balthasar" => &mut magi_state.balthasar,
-                            "casper" => &mut magi_state.casper,
-                            _ => continue,
+                        // Find which agent this is
+                        let agent_state = match magi_state.agent_mut(&message.agent_id) {
+                            Some(state) => state,
+                            None => continue,
                         };
                         
                         // Handle streaming or completed status
                         if message.status == "streaming" {
-                            // Append streaming message to agent content
-                            agent_state.content.push_str(&message.content);
+                            // Drop a chunk the gateway re-sent
+                            if self.config.dedupe_chunks && agent_state.repeats_last(&message.content) {
+                                tracing::debug!(target: "rig-magi", agent = %agent_state.name, "Ignoring repeated chunk");
+                                continue;
+                            }
                             
-                            // Add to messages
-                            agent_state.messages.push(MAGIMessage {
-                                request_id: message.request_id.clone(),
+                            // Append streaming message to agent's messages
+                            agent_state.append_message(&message.request_id, &message.content);
+                            self.trace_agent(agent_state, &message.status, &message.content);
+                            if let Some(live_output) = live_output.as_mut() {
+                                live_output.push(&agent_state.name, &message.content);
+                            }
+                            self.emit(ReviewEvent::Chunk {
+                                request_id: request_id.clone(),
+                                agent: agent_state.name.clone(),
                                 content: message.content.clone(),
-                                timestamp: Utc::now(),
-                            });
+                            }).await;
                         } else if message.status == "completed" {
                             // Mark agent as completed
-                            completed_agents.insert(agent_name.to_string());
+                            completed_agents.insert(agent_state.name.clone());
+                            if let Some(live_output) = live_output.as_mut() {
+                                live_output.finish(&agent_state.name);
+                            }
+                            self.latency.record(&agent_state.name, started.elapsed());
                             
                             // Extract decision from content
-                            if agent_state.content.contains("POSITIVE") {
-                                agent_state.decision = Some(MAGIDecision::POSITIVE);
-                            } else {
-                                agent_state.decision = Some(MAGIDecision::NEGATIVE);
+                            let verdict = self.extractor.extract(&agent_state.name, &agent_state.transcript());
+                            agent_state.decision = verdict.map(|(decision, _)| decision);
+                            agent_state.confidence = verdict.and_then(|(_, confidence)| confidence);
+                            if agent_state.decision.is_none() {
+                                error_messages.push(format!("Reviewer {} gave no explicit verdict", agent_state.name));
+                                agent_state.inconclusive = true;
+                                inconclusive.push(agent_state.name.clone());
                             }
                             
-                            // If all agents have completed, determine final result
-                            if completed_agents.len() >= 3 {
-                                // Get final decision using majority rule
-                                if let Some(decision) = magi_state.get_final_decision() {
-                                    match decision {
-                                        MAGIDecision::POSITIVE => {
-                                            final_result = "POSITIVE".to_string();
-                                            passed = true;
-                                        },
-                                        MAGIDecision::NEGATIVE => {
-                                            final_result = "NEGATIVE".to_string();
-                                            passed = false;
-                                        },
+                            if self.check_weak_approval(agent_state) {
+                                weak_approvals.push(agent_state.name.clone());
+                            }
+                            self.trace_agent(agent_state, "decided", "");
+                            
+                            tracing::info!(target: "rig-magi",
+                                agent = %agent_state.name,
+                                agent_id = %agent_state.agent_id,
+                                status = "completed",
+                                decision = ?agent_state.decision,
+                                "Agent completed"
+                            );
+                            self.emit(ReviewEvent::AgentCompleted {
+                                request_id: request_id.clone(),
+                                agent: agent_state.name.clone(),
+                                decision: agent_state.decision,
+                            }).await;
+                            let agent = agent_state.name.clone();
+                            decision_trace.record(&agent, &magi_state, self.vote_weights(), self.determined(&magi_state));
+                            
+                            // Determine the final result as soon as it can't change
+                            if let Some(decision) = self.settled(&magi_state) {
+                                match decision {
+                                    MAGIDecision::POSITIVE => {
+                                        final_result = "POSITIVE".to_string();
+                                        passed = true;
+                                    },
+                                    MAGIDecision::NEGATIVE => {
+                                        final_result = "NEGATIVE".to_string();
+                                        passed = false;
+                                    },
+                                }
+                                // Exit loop once we have a final decision, unless trailing chunks are awaited
+                                match self.settle_window() {
+                                    Some(window) => {
+                                        settle_deadline.get_or_insert_with(|| tokio::time::Instant::now() + window);
                                     }
-                                    break; // Exit loop once we have a final decision
+                                    None => break,
                                 }
                             }
                         }
                     }
-                } else if let Ok(error_response) = serde_json::from_str::<AgentErrorResponse>(&text) {
-                    // Handle error responses
-                    if error_response.request_id == request_id {
-                        let agent_name = AGENT_IDS.iter()
-                            .find(|(_, id)| *id == error_response.agent_id)
-                            .map(|(name, _)| name)
-                            .unwrap_or(&"unknown");
-                        
-                        let error_msg = format!("Reviewer {} error: {}", agent_name, error_response.error);
-                        error_messages.push(error_msg.clone());
-                        
-                        // Mark this agent as completed with a NEGATIVE decision
-                        let agent_state = match *agent_name {
-                            "melchior" => &mut magi_state.melchior,
-                            "balthasar" => &mut magi_state.balthasar,
-                            "casper" => &mut magi_state.casper,
-                            _ => continue,
-                        };
-                        
-                        agent_state.messages.push(MAGIMessage {
-                            request_id: error_response.request_id.clone(),
-                            content: format!("ERROR: {}", error_response.error),
-                            timestamp: Utc::now(),
-                        });
-                        
-                        agent_state.decision = Some(MAGIDecision::NEGATIVE);
-                        completed_agents.insert(agent_name.to_string());
-                        
-                        // If all agents have completed or errored, determine final result
-                        if completed_agents.len() >= 3 {
-                            final_result = "NEGATIVE".to_string();
-                            passed = false;
-                            break;
-                        }
-                    }
                 } else {
-                    // Just log other message types
-                    // println!("[DEBUG] Received other message type: {}", text);
+                    tracing::debug!(target: "rig-magi", "Ignoring unrecognized frame: {}", text);
                 }
             }
         }
 
+        // Permanent errors won't go away on a retry, so fail the review outright
+        let permanent_errors: Vec<String> = agent_errors
+            .iter()
+            .filter(|error| error.category == ErrorCategory::Permanent)
+            .map(ToString::to_string)
+            .collect();
+        if !permanent_errors.is_empty() {
+            return Err(CodeReviewError::AgentError(permanent_errors.join("; ")));
+        }
+        
+        // A critical security issue vetoes an approval, whatever the vote
+        let veto = if passed && self.config.security_veto { security_veto(&magi_state) } else { None };
+        if let Some(issue) = &veto {
+            tracing::warn!(target: "rig-magi", "Approval vetoed: {}", issue);
+            error_messages.push(format!("Approval vetoed by a critical security issue: {}", issue));
+            passed = false;
+            final_result = "NEGATIVE".to_string();
+        }
+        
+        // A rejection caused by transient errors or a timeout is worth running again
+        let retryable = !passed
+            && (timed_out || connection_lost || agent_errors.iter().any(|error| error.category == ErrorCategory::Transient));
+        
         // If we have error messages, add them to the reviews
         if !error_messages.is_empty() {
             reviews.extend(error_messages);
         }
 
         // Add accumulated content from each agent to reviews
-        reviews.push(format!("Melchior: {}", magi_state.melchior.content));
-        reviews.push(format!("Balthasar: {}", magi_state.balthasar.content));
-        reviews.push(format!("Casper: {}", magi_state.casper.content));
+        for agent_state in &magi_state.agents {
+            reviews.push(format!("{}: {}", display_name(&agent_state.name), agent_state.transcript()));
+        }
+        
+        self.emit(ReviewEvent::Decided {
+            request_id: request_id.clone(),
+            result: final_result.clone(),
+            passed,
+        }).await;
+        tracing::info!(target: "rig-magi",
+            result = %final_result,
+            passed,
+            votes = %magi_state.vote_summary(),
+            "Review decided"
+        );
+        
+        // A settled outcome doesn't wait for the slowest agents
+        let pending_agents = if gateway_decided {
+            Vec::new()
+        } else if timed_out {
+            timed_out_agents
+        } else {
+            magi_state.pending_agents()
+        };
+        if !pending_agents.is_empty() && !final_result.is_empty() {
+            tracing::info!(target: "rig-magi",
+                result = %final_result,
+                pending = ?pending_agents,
+                "Decided {} without waiting for {}",
+                final_result,
+                pending_agents.join(", ")
+            );
+        }
+        
+        // Flag close calls, which the operator may be asked to settle
+        let close_call = !gateway_decided && self.is_close_call(&magi_state, passed);
+        
+        // An approval with no votes to spare, flagged for extra scrutiny when configured
+        let minimum_margin = self.config.minimum_margin_mode == MinimumMarginMode::Flag
+            && !gateway_decided
+            && passed
+            && self.no_margin(&magi_state);
+        let close_call = close_call || minimum_margin;
+        
+        // Summarize why the panel approved, quoting each approving agent
+        let mut approval_evidence = Vec::new();
+        if passed {
+            for agent_state in &magi_state.agents {
+                if !matches!(agent_state.decision, Some(MAGIDecision::POSITIVE)) {
+                    continue;
+                }
+                for sentence in verdict::approval_evidence(&agent_state.transcript(), &self.config.verdict_markers, self.config.approval_evidence_limit) {
+                    approval_evidence.push(format!("{}: {}", display_name(&agent_state.name), sentence));
+                }
+            }
+        }
+
+        // Keep the full transcripts around for post-mortems
+        if let Some(dir) = &self.config.review_dump_dir {
+            if let Err(e) = dump_transcripts(dir, &request_id, &magi_state) {
+                tracing::warn!(target: "rig-magi", "Failed to write review transcripts: {}", e);
+            }
+        }
 
         Ok(CodeReviewOutput {
+            schema_version: OUTPUT_SCHEMA_VERSION,
             reviews,
             result: final_result,
             passed,
+            decisions: magi_state.decisions(),
+            magi_state,
+            code: args.submission(),
+            correlation_id: args.correlation_id,
+            weakly_approved: passed && !weak_approvals.is_empty(),
+            weak_approvals,
+            agent_errors,
+            retryable,
+            inconclusive,
+            compile_errors: None,
+            approval_evidence,
+            close_call,
+            minimum_margin,
+            gateway_decided,
+            decision_trace,
+            pending_agents,
+            rereviews: 0,
+            overridden: false,
+            veto,
+            risk_tier: None,
+            cached: false,
+            timed_out,
+        })
+    }
+
+    // Send the judgement request, preceded by its chunks when it is chunked
+    async fn send_request(
+        &self,
+        write: &mut SplitSink<WsStream, Message>,
+        request_id: &str,
+        chunks: &[&str],
+        agent_request: &AgentJudgementRequest,
+    ) -> Result<(), CodeReviewError> {
+        for (chunk_index, data) in chunks.iter().enumerate() {
+            let chunk = AgentJudgementChunk {
+                message_type: self.config.message_types.judgement_chunk.clone(),
+                request_id: request_id.to_string(),
+                chunk_index,
+                chunk_count: chunks.len(),
+                data: data.to_string(),
+            };
+        
+            write.send(Message::Text(encode(&chunk, "request chunk")?)).await.map_err(|e| {
+                CodeReviewError::WebSocketError(format!("Failed to send request chunk {}: {}", chunk_index, e))
+            })?;
+        }
+        
+        // Send the request
+        write.send(Message::Text(encode(agent_request, "request")?)).await.map_err(|e| {
+            CodeReviewError::WebSocketError(format!("Failed to send review request: {}", e))
+        })
+    }
+
+    // Run the configured compile check, if any. Returns a rejection carrying the
+    // diagnostics when the code fails to compile, so the gateway can be skipped.
+    async fn compile_gate(&self, args: &CodeReviewArgs) -> Option<CodeReviewOutput> {
+        // Multi-file changes aren't compiled as a unit
+        if !self.config.compile_check || !args.files.is_empty() {
+            return None;
+        }
+
+        let check = CompileCheck::find(&self.config.compile_checks, args.language.as_deref(), &args.user_input)?;
+        let diagnostics = match check.run(&args.code).await {
+            Ok(CompileOutcome::Passed) => return None,
+            Ok(CompileOutcome::Failed(diagnostics)) => diagnostics,
+            Err(e) => {
+                // A missing compiler shouldn't block the review
+                tracing::warn!(target: "rig-magi", "Skipping compile check: {}", e);
+                return None;
+            }
+        };
+
+        tracing::info!(target: "rig-magi",
+            language = %check.language,
+            "Compile check failed, skipping the panel"
+        );
+
+        let review = format!("Compile check ({}) failed:\n{}", check.language, diagnostics);
+        let mut output = self.local_output(args, MAGIDecision::NEGATIVE, review);
+        output.compile_errors = Some(diagnostics);
+        Some(output)
+    }
+
+    // Reject code in a language outside `allowed_languages`. Code whose
+    // language can't be told is let through.
+    fn language_gate(&self, args: &CodeReviewArgs) -> Option<CodeReviewOutput> {
+        let language = args.detected_language()?;
+        if self.config.language_allowed(language) {
+            return None;
+        }
+
+        tracing::info!(target: "rig-magi",
+            language = %language,
+            "Language not allowed, skipping the panel"
+        );
+
+        let review = format!(
+            "{} is not an allowed language; use one of: {}",
+            language,
+            self.config.allowed_languages.join(", ")
+        );
+        Some(self.local_output(args, MAGIDecision::NEGATIVE, review))
+    }
+
+    // A configured verdict for this exact submission, keyed by its SHA-256
+    fn verdict_override(&self, args: &CodeReviewArgs) -> Option<CodeReviewOutput> {
+        if self.config.verdict_overrides.is_empty() {
+            return None;
+        }
+
+        let hash = hex::encode(Sha256::digest(args.submission().as_bytes()));
+        let decision = *self.config.verdict_overrides.get(&hash)?;
+
+        tracing::info!(target: "rig-magi",
+            hash = %hash,
+            decision = ?decision,
+            "Verdict override, skipping the panel"
+        );
+
+        let mut output = self.local_output(args, decision, format!("Verdict overridden for code hash {}", hash));
+        output.overridden = true;
+        Some(output)
+    }
+
+    // Output for a review decided locally, without the panel
+    fn local_output(&self, args: &CodeReviewArgs, decision: MAGIDecision, review: String) -> CodeReviewOutput {
+        let passed = decision == MAGIDecision::POSITIVE;
+        let magi_state = MAGISystemState::new(&self.config.agents);
+        CodeReviewOutput {
+            schema_version: OUTPUT_SCHEMA_VERSION,
+            reviews: vec![review],
+            result: if passed { "POSITIVE" } else { "NEGATIVE" }.to_string(),
+            passed,
+            decisions: magi_state.decisions(),
             magi_state,
-            code: args.code,
+            code: args.submission(),
+            correlation_id: args.correlation_id.clone(),
+            weak_approvals: Vec::new(),
+            weakly_approved: false,
+            agent_errors: Vec::new(),
+            retryable: false,
+            inconclusive: Vec::new(),
+            compile_errors: None,
+            approval_evidence: Vec::new(),
+            close_call: false,
+            minimum_margin: false,
+            gateway_decided: false,
+            decision_trace: DecisionTrace::default(),
+            pending_agents: Vec::new(),
+            rereviews: 0,
+            overridden: false,
+            veto: None,
+            risk_tier: None,
+            cached: false,
+            timed_out: false,
+        }
+    }
+
+    // Frame-level debug logging, only for agents listed in `debug_agents`;
+    // the others are logged at completion only
+    fn trace_agent(&self, agent_state: &MAGIAgentState, status: &str, content: &str) {
+        if !self.config.debug_agents.iter().any(|name| *name == agent_state.name) {
+            return;
+        }
+
+        tracing::debug!(target: "rig-magi",
+            agent = %agent_state.name,
+            status = %status,
+            decision = ?agent_state.decision,
+            weak_approval = agent_state.weak_approval,
+            inconclusive = agent_state.inconclusive,
+            "Agent frame: {}",
+            content
+        );
+    }
+
+    // The decision to stop waiting on: as soon as the outcome is fixed, or
+    // with `wait_for_all` only once every agent has answered
+    fn settled(&self, magi_state: &MAGISystemState) -> Option<MAGIDecision> {
+        if self.config.wait_for_all && !magi_state.pending_agents().is_empty() {
+            return None;
+        }
+        self.determined(magi_state)
+    }
+
+    // The outcome once it can no longer change
+    fn determined(&self, magi_state: &MAGISystemState) -> Option<MAGIDecision> {
+        match self.vote_weights() {
+            Some(weights) => magi_state.determined_decision_weighted(weights),
+            None => magi_state.determined_decision(self.required_positive(magi_state)),
+        }
+    }
+
+    // The decision once every agent has answered, `None` before
+    fn final_decision(&self, magi_state: &MAGISystemState) -> Option<MAGIDecision> {
+        match self.vote_weights() {
+            Some(weights) => magi_state.get_final_decision_weighted(weights),
+            None => magi_state.get_final_decision_with_quorum(self.required_positive(magi_state)),
+        }
+    }
+
+    // The weights that decide the vote. They replace the default majority,
+    // not an explicit quorum.
+    fn vote_weights(&self) -> Option<&HashMap<String, u32>> {
+        (self.config.quorum.is_none() && !self.config.weights.is_empty()).then_some(&self.config.weights)
+    }
+
+    // The counted POSITIVE support and what passing takes: votes against the
+    // quorum, or with weights the approving weight against more than half
+    // the responding agents' total
+    fn tally(&self, magi_state: &MAGISystemState) -> (u64, u64) {
+        match self.vote_weights() {
+            Some(weights) => {
+                let (positive, _, total) = magi_state.weighted_tally(weights);
+                (positive, total / 2 + 1)
+            }
+            None => (magi_state.positive_count() as u64, self.required_positive(magi_state) as u64),
+        }
+    }
+
+    // Passed with just the support required, no more
+    fn no_margin(&self, magi_state: &MAGISystemState) -> bool {
+        let (positive, required) = self.tally(magi_state);
+        positive == required
+    }
+
+    // POSITIVE votes needed to pass, leaving out agents that errored
+    fn required_positive(&self, magi_state: &MAGISystemState) -> usize {
+        self.config.required_positive_of(magi_state.responding_count())
+    }
+
+    // With `wait_for_all`, how long to keep reading after the outcome is
+    // settled, so chunks a gateway sends after an agent's `completed` frame
+    // still reach its transcript. Verdicts aren't re-extracted from them.
+    fn settle_window(&self) -> Option<Duration> {
+        self.config.settle_window.filter(|_| self.config.wait_for_all)
+    }
+
+    // Undo the configured content encoding. Content that doesn't decode is
+    // kept as received, so the agent's transcript still shows it.
+    fn decode_content(&self, content: &str) -> String {
+        self.config.content_encoding.decode(content).unwrap_or_else(|e| {
+            tracing::warn!(target: "rig-magi", "Keeping undecodable agent content as is: {}", e);
+            content.to_string()
+        })
+    }
+
+    async fn emit(&self, event: ReviewEvent) {
+        if let Some(events) = &self.events {
+            events.emit(event).await;
+        }
+    }
+
+    // A close call is a vote within `close_call_margin` of the required
+    // support, or a pass that a required approver didn't approve
+    fn is_close_call(&self, magi_state: &MAGISystemState, passed: bool) -> bool {
+        let (positive, required) = self.tally(magi_state);
+        if positive.abs_diff(required) <= self.config.close_call_margin as u64 {
+            return true;
+        }
+
+        passed
+            && magi_state.agents.iter().any(|state| {
+                self.config.required_approvers.contains(&state.name)
+                    && !matches!(state.decision, Some(MAGIDecision::POSITIVE))
+            })
+    }
+
+    // Apply `min_approval_content` to an agent that has just voted.
+    // Returns true when the vote is an approval that is too terse.
+    fn check_weak_approval(&self, agent_state: &mut MAGIAgentState) -> bool {
+        let Some(min_len) = self.config.min_approval_content else {
+            return false;
+        };
+
+        if !matches!(agent_state.decision, Some(MAGIDecision::POSITIVE))
+            || agent_state.transcript().trim().chars().count() >= min_len
+        {
+            return false;
+        }
+
+        if self.config.weak_approval_mode == WeakApprovalMode::Discount {
+            agent_state.weak_approval = true;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, MockGateway, Reply};
+    use crate::tools::events::{self, Backpressure};
+    use crate::tools::verdict::VerdictMarkers;
+
+    // The default panel's state with these votes, in panel order
+    fn votes(decisions: &[Option<MAGIDecision>]) -> MAGISystemState {
+        let mut magi_state = MAGISystemState::default();
+        for (agent_state, decision) in magi_state.agents.iter_mut().zip(decisions) {
+            agent_state.decision = *decision;
+        }
+        magi_state
+    }
+
+    // A panel of agents with these names and ids derived from them
+    fn panel(names: &[&str]) -> Vec<ReviewerAgent> {
+        names
+            .iter()
+            .map(|name| ReviewerAgent {
+                name: name.to_string(),
+                id: format!("{}-id", name),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn renamed_panel_reviews_end_to_end() {
+        let gateway = MockGateway::voting(&["POSITIVE, well tested", "NEGATIVE: too slow", "POSITIVE"]).await;
+        let config = CodeReviewConfig {
+            agents: panel(&["security", "style", "perf"]),
+            ..gateway.config()
+        };
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.passed());
+        assert_eq!(test_util::agent_ids(&gateway.judgements()[0]), ["security-id", "style-id", "perf-id"]);
+        let decisions: Vec<(&str, Option<MAGIDecision>)> =
+            output.decisions().iter().map(|decision| (decision.agent.as_str(), decision.decision)).collect();
+        assert_eq!(
+            decisions,
+            [
+                ("security", Some(MAGIDecision::POSITIVE)),
+                ("style", Some(MAGIDecision::NEGATIVE)),
+                ("perf", Some(MAGIDecision::POSITIVE)),
+            ]
+        );
+        assert!(output.reviews.contains(&"Style: NEGATIVE: too slow".to_string()));
+    }
+
+    #[test]
+    fn chunks_split_on_character_boundaries() {
+        let text = "abcé日本語xyz";
+
+        let chunks = split_into_chunks(text, 5);
+
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 5));
+        assert_eq!(chunks.concat(), text);
+        assert!(split_into_chunks("", 5).is_empty());
+    }
+
+    #[tokio::test]
+    async fn oversized_requests_are_sent_as_numbered_chunks() {
+        let gateway = MockGateway::voting(&["POSITIVE", "POSITIVE", "POSITIVE"]).await;
+        let config = CodeReviewConfig {
+            chunk_size: Some(64),
+            ..gateway.config()
+        };
+        let code = "fn long() {}\n".repeat(20);
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args(&code)).await.unwrap();
+
+        assert!(output.passed());
+        let frames = gateway.received();
+        let (request, chunks) = frames.split_last().unwrap();
+        assert_eq!(request["type"], "agent_judgement");
+        assert_eq!(request["request"], "");
+        assert_eq!(request["chunk_count"], chunks.len());
+        assert!(chunks.len() > 1);
+        for (index, chunk) in chunks.iter().enumerate() {
+            assert_eq!(chunk["type"], "agent_judgement_chunk");
+            assert_eq!(chunk["request_id"], request["request_id"]);
+            assert_eq!(chunk["chunk_index"], index);
+            assert_eq!(chunk["chunk_count"], chunks.len());
+        }
+        let body: String = chunks.iter().map(|chunk| chunk["data"].as_str().unwrap()).collect();
+        assert_eq!(body, format!("<user_input>\nWrite a function\n</user_input>\n<response>\n{}\n</response>", code));
+    }
+
+    #[tokio::test]
+    async fn the_correlation_id_reaches_the_gateway_and_the_output() {
+        let gateway = MockGateway::voting(&["POSITIVE", "POSITIVE", "POSITIVE"]).await;
+        let args = CodeReviewArgs {
+            correlation_id: Some("turn-1".to_string()),
+            ..test_util::args("fn add() {}")
+        };
+
+        let output = CodeReviewTool::with_config(gateway.config()).call(args).await.unwrap();
+
+        assert_eq!(gateway.judgements()[0]["correlation_id"], "turn-1");
+        assert_eq!(output.correlation_id.as_deref(), Some("turn-1"));
+    }
+
+    #[cfg(not(feature = "otel"))]
+    #[tokio::test]
+    async fn no_trace_context_is_sent_without_otel() {
+        let gateway = MockGateway::voting(&["POSITIVE", "POSITIVE", "POSITIVE"]).await;
+
+        CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(gateway.judgements()[0].get("trace_context").is_none());
+    }
+
+    #[cfg(feature = "otel")]
+    #[test]
+    fn trace_context_carries_the_current_span() {
+        use opentelemetry::trace::TracerProvider as _;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        opentelemetry::global::set_text_map_propagator(opentelemetry_sdk::propagation::TraceContextPropagator::new());
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder().build();
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+        let context = tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("code_review").in_scope(trace_context)
+        });
+
+        assert!(context.unwrap().contains_key("traceparent"));
+    }
+
+    // A review under `min_approval_content` where the first approval is terse
+    async fn review_terse_approval(mode: WeakApprovalMode) -> CodeReviewOutput {
+        let gateway = MockGateway::voting(&[
+            "POSITIVE",
+            "POSITIVE because every overflow case is checked",
+            "NEGATIVE: no tests",
+        ])
+        .await;
+        let config = CodeReviewConfig {
+            min_approval_content: Some(20),
+            weak_approval_mode: mode,
+            ..gateway.config()
+        };
+
+        CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn terse_approvals_are_flagged_but_counted() {
+        let output = review_terse_approval(WeakApprovalMode::Flag).await;
+
+        assert!(output.passed());
+        assert!(output.weakly_approved);
+        assert_eq!(output.weak_approvals, ["melchior"]);
+        assert!(!output.magi_state.agents[0].weak_approval);
+    }
+
+    #[tokio::test]
+    async fn discounted_terse_approvals_dont_count() {
+        let output = review_terse_approval(WeakApprovalMode::Discount).await;
+
+        assert!(!output.passed());
+        assert!(!output.weakly_approved);
+        assert_eq!(output.weak_approvals, ["melchior"]);
+        assert!(output.magi_state.agents[0].weak_approval);
+        assert_eq!(output.magi_state.positive_count(), 1);
+    }
+
+    // A gateway on which the first agent fails with `error_type` and the
+    // others vote POSITIVE and NEGATIVE
+    async fn first_agent_fails(error_type: &'static str) -> MockGateway {
+        MockGateway::start(move |_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            let ids = test_util::agent_ids(frame);
+            let mut replies = vec![Reply::Frame(test_util::agent_error(frame, &ids[0], error_type, "model failed"))];
+            replies.extend(ids.get(1).into_iter().flat_map(|id| test_util::vote(frame, id, "POSITIVE")));
+            replies.extend(ids.get(2).into_iter().flat_map(|id| test_util::vote(frame, id, "NEGATIVE")));
+            replies
+        })
+        .await
+    }
+
+    #[test]
+    fn errors_are_categorized_by_type_or_code() {
+        assert_eq!(ErrorCategory::classify(Some("Overloaded"), None), ErrorCategory::Transient);
+        assert_eq!(ErrorCategory::classify(None, Some("503")), ErrorCategory::Transient);
+        assert_eq!(ErrorCategory::classify(Some("unauthorized"), Some("500")), ErrorCategory::Permanent);
+        assert_eq!(ErrorCategory::classify(Some("weird"), Some("404")), ErrorCategory::Permanent);
+        assert_eq!(ErrorCategory::classify(Some("weird"), None), ErrorCategory::Unknown);
+    }
+
+    #[test]
+    fn structured_errors_fall_back_to_the_sibling_fields() {
+        let error: GatewayError = serde_json::from_value(json!({
+            "error": { "message": "slow down", "code": 429 },
+            "error_type": "rate_limited",
+        }))
+        .unwrap();
+
+        let error = AgentError::new("melchior", error);
+
+        assert_eq!(error.message, "slow down");
+        assert_eq!(error.error_type.as_deref(), Some("rate_limited"));
+        assert_eq!(error.code.as_deref(), Some("429"));
+        assert_eq!(error.category, ErrorCategory::Transient);
+        assert_eq!(error.to_string(), "Reviewer melchior error [rate_limited]: slow down");
+    }
+
+    #[tokio::test]
+    async fn a_transient_agent_error_makes_the_rejection_retryable() {
+        let gateway = first_agent_fails("overloaded").await;
+
+        let output = CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(!output.passed());
+        assert!(output.retryable());
+        assert_eq!(output.agent_errors.len(), 1);
+        assert_eq!(output.agent_errors[0].agent, "melchior");
+        assert_eq!(output.agent_errors[0].category, ErrorCategory::Transient);
+        assert_eq!(output.exit_code(), EXIT_UNDECIDED);
+    }
+
+    // A gateway on which only the first `answering` agents ever vote
+    async fn silent_after(answering: usize) -> MockGateway {
+        MockGateway::start(move |_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            test_util::agent_ids(frame)
+                .iter()
+                .take(answering)
+                .flat_map(|agent_id| test_util::vote(frame, agent_id, "DECISION: POSITIVE"))
+                .collect()
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn agents_still_reviewing_at_the_timeout_count_negative() {
+        let gateway = silent_after(1).await;
+        let config = CodeReviewConfig {
+            review_timeout: Some(Duration::from_millis(200)),
+            ..gateway.config()
+        };
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.timed_out());
+        assert_eq!(output.result, "NEGATIVE");
+        assert!(!output.passed());
+        assert_eq!(output.pending_agents, ["balthasar", "casper"]);
+        assert_eq!(output.decisions()[1].decision, Some(MAGIDecision::NEGATIVE));
+        assert_eq!(output.decisions()[2].decision, Some(MAGIDecision::NEGATIVE));
+        assert!(output.retryable());
+        assert_eq!(output.exit_code(), EXIT_UNDECIDED);
+    }
+
+    #[tokio::test]
+    async fn a_timeout_waiting_for_all_still_reaches_a_verdict() {
+        let gateway = silent_after(2).await;
+        let config = CodeReviewConfig {
+            review_timeout: Some(Duration::from_millis(200)),
+            wait_for_all: true,
+            ..gateway.config()
+        };
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.timed_out());
+        assert_eq!(output.result, "POSITIVE");
+        assert!(output.passed());
+        assert_eq!(output.pending_agents, ["casper"]);
+        assert_eq!(output.exit_code(), EXIT_APPROVED);
+    }
+
+    // A gateway that drops the first connection after melchior's vote and
+    // a partial chunk from balthasar, then answers whoever is asked again
+    async fn dropping_first_connection() -> MockGateway {
+        MockGateway::start(|connection, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            let agent_ids = test_util::agent_ids(frame);
+            if connection == 0 {
+                let mut replies = test_util::vote(frame, &agent_ids[0], "DECISION: POSITIVE");
+                replies.push(Reply::Frame(test_util::streaming(frame, &agent_ids[1], "DECISION: NEG")));
+                replies.push(Reply::Close);
+                return replies;
+            }
+            agent_ids
+                .iter()
+                .flat_map(|agent_id| test_util::vote(frame, agent_id, "DECISION: POSITIVE"))
+                .collect()
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn a_dropped_connection_resumes_with_the_pending_agents() {
+        let gateway = dropping_first_connection().await;
+
+        let output = CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.passed());
+        let judgements = gateway.judgements();
+        assert_eq!(judgements.len(), 2);
+        assert_eq!(judgements[1]["request_id"], judgements[0]["request_id"]);
+        let pending: Vec<String> = output.magi_state.agents[1..].iter().map(|state| state.agent_id.clone()).collect();
+        assert_eq!(test_util::agent_ids(&judgements[1]), pending);
+        assert_eq!(output.magi_state.agents[1].transcript(), "DECISION: POSITIVE");
+        assert!(!output.retryable());
+    }
+
+    #[tokio::test]
+    async fn reconnects_give_up_with_the_votes_so_far() {
+        let gateway = MockGateway::start(|connection, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            let mut replies = Vec::new();
+            if connection == 0 {
+                replies = test_util::vote(frame, &test_util::agent_ids(frame)[0], "DECISION: POSITIVE");
+            }
+            replies.push(Reply::Close);
+            replies
         })
+        .await;
+        let config = CodeReviewConfig {
+            reconnect_attempts: 2,
+            ..gateway.config()
+        };
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert_eq!(gateway.judgements().len(), 3);
+        assert!(!output.passed());
+        assert!(output.retryable());
+        assert_eq!(output.decisions()[0].decision, Some(MAGIDecision::POSITIVE));
+        assert_eq!(output.pending_agents, ["balthasar", "casper"]);
+        assert!(output.reviews.iter().any(|review| review.contains("gave up after 2 reconnects")));
+    }
+
+    #[test]
+    fn every_agent_event_is_logged_under_the_reviews_span() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+        let mut request_id = String::new();
+        let logs = test_util::capture_logs(|| {
+            runtime.block_on(async {
+                let gateway = MockGateway::voting(&["DECISION: POSITIVE"; 3]).await;
+                CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await.unwrap();
+                request_id = gateway.judgements()[0]["request_id"].as_str().unwrap().to_string();
+            })
+        });
+
+        let span = format!("code_review{{request_id={}", request_id);
+        let completed: Vec<&str> = logs.lines().filter(|line| line.contains("Agent completed")).collect();
+        assert_eq!(completed.len(), 2);
+        for line in completed {
+            assert!(line.contains(&span), "{}", line);
+            assert!(line.contains("rig-magi"));
+            assert!(line.contains("agent_id=") && line.contains("status=") && line.contains("decision=Some(POSITIVE)"));
+        }
+        let decided = logs.lines().find(|line| line.contains("Review decided")).unwrap();
+        assert!(decided.contains(&span));
+    }
+
+    #[test]
+    fn an_erroring_agent_is_reported_as_errored_not_as_a_vote() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+        let mut output = None;
+        let logs = test_util::capture_logs(|| {
+            output = Some(runtime.block_on(async {
+                let gateway = first_agent_fails("overloaded").await;
+                CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await.unwrap()
+            }))
+        });
+        let output = output.unwrap();
+
+        assert!(output.decisions()[0].errored);
+        assert!(!output.decisions()[1].errored && !output.decisions()[2].errored);
+        let json = output.to_json();
+        assert_eq!(json["decisions"][0]["errored"], true);
+        assert_eq!(json["magi_state"]["agents"][0]["errored"], true);
+        assert_eq!(json["magi_state"]["agents"][2]["errored"], false);
+        assert!(logs.contains("melchior=ERROR, balthasar=POSITIVE, casper=NEGATIVE"));
+    }
+
+    #[tokio::test]
+    async fn a_permanent_agent_error_fails_the_review() {
+        let gateway = first_agent_fails("unauthorized").await;
+
+        let result = CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await;
+
+        assert!(matches!(result, Err(CodeReviewError::AgentError(message)) if message.contains("[unauthorized]")));
+    }
+
+    #[tokio::test]
+    async fn strict_mode_leaves_an_agent_without_a_verdict_inconclusive() {
+        let gateway =
+            MockGateway::voting(&["DECISION: POSITIVE", "Looks POSITIVE to me overall", "VERDICT: NEGATIVE"]).await;
+        let config = CodeReviewConfig {
+            strict_verdict: true,
+            ..gateway.config()
+        };
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(!output.passed());
+        assert_eq!(output.inconclusive, ["balthasar"]);
+        assert!(output.magi_state.agents[1].inconclusive);
+        assert_eq!(output.decisions()[1].decision, None);
+        assert!(output.magi_state.pending_agents().is_empty());
+        assert_eq!(output.exit_code(), EXIT_UNDECIDED);
+    }
+
+    #[tokio::test]
+    async fn code_failing_the_compile_check_skips_the_panel() {
+        let config = CodeReviewConfig {
+            server_url: "ws://127.0.0.1:1/review".to_string(),
+            compile_check: true,
+            compile_checks: vec![CompileCheck {
+                language: "rust".to_string(),
+                extension: "rs".to_string(),
+                command: "grep -q fn {file}".to_string(),
+            }],
+            ..CodeReviewConfig::default()
+        };
+        let args = CodeReviewArgs {
+            language: Some("rust".to_string()),
+            ..test_util::args("main() {}")
+        };
+
+        let output = CodeReviewTool::with_config(config).call(args).await.unwrap();
+
+        assert!(!output.passed());
+        assert!(output.compile_errors.is_some());
+        assert_eq!(output.exit_code(), EXIT_REJECTED);
+    }
+
+    #[tokio::test]
+    async fn approval_evidence_quotes_each_approving_agent() {
+        let gateway = MockGateway::voting(&[
+            "Clean code.\n\nPOSITIVE because the bounds are checked.",
+            "NEGATIVE: needs docs",
+            "It handles errors since it returns Result.\n\nDECISION: POSITIVE",
+        ])
+        .await;
+
+        let output = CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.passed());
+        assert_eq!(
+            output.approval_evidence,
+            [
+                "Melchior: POSITIVE because the bounds are checked.",
+                "Casper: It handles errors since it returns Result.",
+                "Casper: DECISION: POSITIVE",
+            ]
+        );
+    }
+
+    #[test]
+    fn close_calls_are_votes_near_the_quorum_or_a_required_approvers_dissent() {
+        use MAGIDecision::{NEGATIVE, POSITIVE};
+
+        let majority = CodeReviewTool::with_config(CodeReviewConfig::default());
+        assert!(majority.is_close_call(&votes(&[Some(POSITIVE), Some(POSITIVE), Some(NEGATIVE)]), true));
+        assert!(!majority.is_close_call(&votes(&[Some(POSITIVE), Some(POSITIVE), Some(POSITIVE)]), true));
+
+        let approver = CodeReviewTool::with_config(CodeReviewConfig {
+            quorum: Some(1),
+            required_approvers: vec!["casper".to_string()],
+            ..CodeReviewConfig::default()
+        });
+        assert!(approver.is_close_call(&votes(&[Some(POSITIVE), Some(POSITIVE), Some(NEGATIVE)]), true));
+        assert!(!approver.is_close_call(&votes(&[Some(POSITIVE), Some(POSITIVE), Some(POSITIVE)]), true));
+        assert!(!approver.is_close_call(&votes(&[Some(POSITIVE), Some(POSITIVE), Some(NEGATIVE)]), false));
+    }
+
+    fn casper_counts(weight: u32) -> HashMap<String, u32> {
+        HashMap::from([("casper".to_string(), weight)])
+    }
+
+    #[test]
+    fn weights_can_flip_the_unweighted_majority() {
+        use MAGIDecision::{NEGATIVE, POSITIVE};
+        let weights = casper_counts(3);
+
+        let casper_alone = votes(&[Some(NEGATIVE), Some(NEGATIVE), Some(POSITIVE)]);
+        assert_eq!(casper_alone.get_final_decision(), Some(NEGATIVE));
+        assert_eq!(casper_alone.get_final_decision_weighted(&weights), Some(POSITIVE));
+
+        let against_casper = votes(&[Some(POSITIVE), Some(POSITIVE), Some(NEGATIVE)]);
+        assert_eq!(against_casper.get_final_decision(), Some(POSITIVE));
+        assert_eq!(against_casper.get_final_decision_weighted(&weights), Some(NEGATIVE));
+
+        // Two of four is no majority
+        assert_eq!(casper_alone.get_final_decision_weighted(&casper_counts(2)), Some(NEGATIVE));
+
+        // Casper, still pending, could carry it alone
+        let casper_pending = votes(&[Some(NEGATIVE), Some(NEGATIVE), None]);
+        assert_eq!(casper_pending.determined_decision(2), Some(NEGATIVE));
+        assert_eq!(casper_pending.determined_decision_weighted(&weights), None);
+        assert_eq!(casper_pending.get_final_decision_weighted(&weights), None);
+    }
+
+    #[test]
+    fn margins_and_close_calls_use_the_weighted_tally() {
+        use MAGIDecision::{NEGATIVE, POSITIVE};
+        let majority = CodeReviewTool::with_config(CodeReviewConfig::default());
+        let weighted = CodeReviewTool::with_config(CodeReviewConfig {
+            weights: casper_counts(3),
+            ..CodeReviewConfig::default()
+        });
+        let casper_alone = votes(&[Some(NEGATIVE), Some(NEGATIVE), Some(POSITIVE)]);
+        let unanimous = votes(&[Some(POSITIVE), Some(POSITIVE), Some(POSITIVE)]);
+
+        assert_eq!(majority.tally(&casper_alone), (1, 2));
+        assert_eq!(weighted.tally(&casper_alone), (3, 3));
+        assert!(weighted.no_margin(&casper_alone));
+        assert!(!weighted.no_margin(&unanimous));
+        assert!(weighted.is_close_call(&casper_alone, true));
+        assert!(!weighted.is_close_call(&unanimous, true));
+
+        // An explicit quorum counts votes whatever the weights
+        let quorum = CodeReviewTool::with_config(CodeReviewConfig {
+            quorum: Some(3),
+            ..weighted.config.clone()
+        });
+        assert_eq!(quorum.tally(&casper_alone), (1, 3));
+    }
+
+    #[tokio::test]
+    async fn a_heavy_approval_passes_with_no_margin_and_a_weighted_trace() {
+        use MAGIDecision::{NEGATIVE, POSITIVE};
+        let gateway = MockGateway::voting(&["NEGATIVE", "NEGATIVE", "POSITIVE"]).await;
+        let config = CodeReviewConfig {
+            weights: casper_counts(3),
+            minimum_margin_mode: MinimumMarginMode::Flag,
+            ..gateway.config()
+        };
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.passed());
+        assert!(output.minimum_margin && output.close_call);
+        let trace = output.decision_trace();
+        let steps: Vec<_> = trace
+            .steps
+            .iter()
+            .map(|step| (step.positive, step.negative, step.leading, step.determined))
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                (0, 1, Some(NEGATIVE), None),
+                (0, 2, Some(NEGATIVE), None),
+                (3, 2, Some(POSITIVE), Some(POSITIVE)),
+            ]
+        );
+        assert!(trace.flipped);
+        assert_eq!(output.decisions()[0].decision, Some(NEGATIVE));
+    }
+
+    #[tokio::test]
+    async fn the_heartbeat_reports_the_pending_agents() {
+        let gateway = MockGateway::start(|_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            let ids = test_util::agent_ids(frame);
+            let mut replies = test_util::vote(frame, &ids[0], "POSITIVE");
+            replies.extend(test_util::vote(frame, &ids[1], "NEGATIVE"));
+            replies.push(Reply::Wait(Duration::from_millis(300)));
+            replies.extend(test_util::vote(frame, &ids[2], "POSITIVE"));
+            replies
+        })
+        .await;
+        let (events, mut receiver) = events::channel(64, Backpressure::DropChunks);
+        let tool = CodeReviewTool::with_config(CodeReviewConfig {
+            heartbeat_interval: Some(Duration::from_millis(50)),
+            ..gateway.config()
+        })
+        .with_events(events);
+
+        let output = tool.call(test_util::args("fn add() {}")).await.unwrap();
+        assert_eq!(output.result, "POSITIVE");
+
+        let mut progress = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            if let ReviewEvent::Progress { pending, .. } = event {
+                progress.push(pending);
+            }
+        }
+        assert!(!progress.is_empty());
+        assert!(progress.iter().all(|pending| pending == &["casper".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn custom_verdict_markers_decide_the_review() {
+        let gateway = MockGateway::voting(&["APPROVE", "REJECT", "APPROVE"]).await;
+        let tool = CodeReviewTool::with_config(CodeReviewConfig {
+            verdict_markers: VerdictMarkers {
+                positive: vec!["APPROVE".to_string()],
+                negative: vec!["REJECT".to_string()],
+            },
+            ..gateway.config()
+        });
+
+        let output = tool.call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert_eq!(output.result, "POSITIVE");
+        assert_eq!(output.magi_state.agents[1].decision, Some(MAGIDecision::NEGATIVE));
+    }
+
+    #[test]
+    fn the_schema_offers_a_rubric() {
+        let definition = tool_definition();
+
+        assert_eq!(definition.parameters["properties"]["rubric"]["type"], "string");
+        assert!(!definition.parameters["required"].as_array().unwrap().contains(&json!("rubric")));
+    }
+
+    #[tokio::test]
+    async fn the_rubric_is_sent_with_the_request_and_args_override_the_config() {
+        let gateway = MockGateway::voting(&["POSITIVE", "POSITIVE", "POSITIVE"]).await;
+        let tool = CodeReviewTool::with_config(CodeReviewConfig {
+            rubric: Some("- configured criterion\n".to_string()),
+            ..gateway.config()
+        });
+
+        tool.call(test_util::args("fn add() {}")).await.unwrap();
+        let mut args = test_util::args("fn add() {}");
+        args.rubric = Some("- runtime criterion".to_string());
+        tool.call(args).await.unwrap();
+
+        let judgements = gateway.judgements();
+        let configured = judgements[0]["request"].as_str().unwrap();
+        let runtime = judgements[1]["request"].as_str().unwrap();
+        assert!(configured.contains("<rubric>\n- configured criterion\n</rubric>"));
+        assert!(runtime.contains("<rubric>\n- runtime criterion\n</rubric>"));
+        assert!(!runtime.contains("configured criterion"));
+    }
+
+    #[tokio::test]
+    async fn a_duplicated_completion_is_applied_once() {
+        let gateway = MockGateway::start(|_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            let ids = test_util::agent_ids(frame);
+            let mut replies = test_util::vote(frame, &ids[0], "POSITIVE");
+            replies.push(Reply::Frame(test_util::completed(frame, &ids[0])));
+            replies.extend(test_util::vote(frame, &ids[1], "NEGATIVE"));
+            replies.extend(test_util::vote(frame, &ids[2], "POSITIVE"));
+            replies
+        })
+        .await;
+        let tool = CodeReviewTool::with_config(gateway.config());
+
+        let output = tool.call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert_eq!(output.result, "POSITIVE");
+        assert_eq!(output.decision_trace().steps.len(), 3);
+        assert_eq!(output.magi_state.agents[0].messages.len(), 1);
+    }
+
+    #[test]
+    fn the_decision_is_determined_as_soon_as_either_side_is_certain() {
+        use MAGIDecision::{NEGATIVE, POSITIVE};
+
+        assert_eq!(votes(&[Some(NEGATIVE), Some(NEGATIVE), None]).determined_decision(2), Some(NEGATIVE));
+        assert_eq!(votes(&[Some(POSITIVE), Some(POSITIVE), None]).determined_decision(2), Some(POSITIVE));
+        assert_eq!(votes(&[Some(POSITIVE), Some(NEGATIVE), None]).determined_decision(2), None);
+        assert_eq!(votes(&[Some(NEGATIVE), None, None]).determined_decision(2), None);
+        assert_eq!(votes(&[Some(NEGATIVE), None, None]).determined_decision(3), Some(NEGATIVE));
+    }
+
+    #[tokio::test]
+    async fn two_rejections_end_the_review_without_the_third_agent() {
+        let gateway = MockGateway::voting(&["NEGATIVE", "NEGATIVE"]).await;
+        let tool = CodeReviewTool::with_config(gateway.config());
+
+        let output = tool.call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert_eq!(output.result, "NEGATIVE");
+        assert!(!output.passed);
+        assert_eq!(output.magi_state.pending_agents(), ["casper"]);
+    }
+
+    // A gateway that answers each judgement with a consolidated verdict
+    async fn deciding(decision: &'static str, agents: &'static [(&'static str, &'static str, &'static str)]) -> MockGateway {
+        MockGateway::start(move |_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            vec![Reply::Frame(test_util::final_decision(frame, decision, agents))]
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn the_gateways_final_decision_is_authoritative() {
+        let gateway = deciding(
+            "POSITIVE",
+            &[("melchior-id", "POSITIVE", "Fine"), ("balthasar-id", "NEGATIVE", "Too slow")],
+        )
+        .await;
+        let tool = CodeReviewTool::with_config(CodeReviewConfig {
+            agents: panel(&["melchior", "balthasar", "casper"]),
+            ..gateway.config()
+        });
+
+        let output = tool.call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.passed());
+        assert!(output.gateway_decided);
+        let agents = &output.magi_state.agents;
+        assert_eq!(agents[1].decision, Some(MAGIDecision::NEGATIVE));
+        assert_eq!(agents[1].transcript(), "Too slow");
+        assert_eq!(agents[2].decision, None);
+    }
+
+    #[tokio::test]
+    async fn an_unrecognized_final_decision_fails_closed() {
+        let gateway = deciding("MAYBE", &[]).await;
+        let tool = CodeReviewTool::with_config(gateway.config());
+
+        let output = tool.call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(!output.passed());
+        assert_eq!(output.result, "NEGATIVE");
+    }
+
+    #[test]
+    fn the_transcript_joins_the_messages_and_is_serialized_as_content() {
+        let mut magi_state = MAGISystemState::default();
+        let agent_state = &mut magi_state.agents[0];
+        agent_state.append_message("request", "Looks ");
+        agent_state.append_message("request", "POSITIVE");
+
+        assert_eq!(agent_state.transcript(), "Looks POSITIVE");
+        let value = serde_json::to_value(&*agent_state).unwrap();
+        assert_eq!(value["content"], "Looks POSITIVE");
+        assert_eq!(value["messages"].as_array().unwrap().len(), 2);
+        assert_eq!(MAGISystemState::default().agents[0].transcript(), "");
+    }
+
+    // Decides "yes" and "no", with full confidence
+    struct YesNoExtractor;
+
+    impl VerdictExtractor for YesNoExtractor {
+        fn extract(&self, _agent: &str, content: &str) -> Option<(MAGIDecision, Option<f32>)> {
+            match content {
+                "yes" => Some((MAGIDecision::POSITIVE, Some(1.0))),
+                "no" => Some((MAGIDecision::NEGATIVE, Some(1.0))),
+                _ => None,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn a_custom_extractor_decides_the_votes() {
+        let gateway = MockGateway::voting(&["yes", "no", "yes"]).await;
+        let tool = CodeReviewTool::with_config(gateway.config()).with_extractor(YesNoExtractor);
+
+        let output = tool.call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert_eq!(output.result, "POSITIVE");
+        let agents = &output.magi_state.agents;
+        assert_eq!(agents[1].decision, Some(MAGIDecision::NEGATIVE));
+        assert_eq!(agents[0].confidence, Some(1.0));
+    }
+
+    #[tokio::test]
+    async fn the_trace_follows_the_votes_in_arrival_order() {
+        use MAGIDecision::{NEGATIVE, POSITIVE};
+        let gateway = MockGateway::voting(&["POSITIVE", "NEGATIVE", "NEGATIVE"]).await;
+
+        let output = CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await.unwrap();
+
+        let trace = output.decision_trace();
+        let steps: Vec<_> = trace
+            .steps
+            .iter()
+            .map(|step| (step.agent.as_str(), step.vote, step.positive, step.negative, step.pending, step.leading, step.determined))
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                ("melchior", Some(POSITIVE), 1, 0, 2, Some(POSITIVE), None),
+                ("balthasar", Some(NEGATIVE), 1, 1, 1, None, None),
+                ("casper", Some(NEGATIVE), 1, 2, 0, Some(NEGATIVE), Some(NEGATIVE)),
+            ]
+        );
+        assert!(trace.flipped);
+    }
+
+    #[test]
+    fn a_steady_lead_is_no_flip() {
+        use MAGIDecision::POSITIVE;
+        let mut trace = DecisionTrace::default();
+
+        let first = votes(&[Some(POSITIVE), None, None]);
+        trace.record("melchior", &first, None, first.determined_decision(2));
+        let second = votes(&[Some(POSITIVE), Some(POSITIVE), None]);
+        trace.record("balthasar", &second, None, second.determined_decision(2));
+
+        assert!(!trace.flipped);
+        assert_eq!(trace.steps[1].determined, Some(POSITIVE));
+    }
+
+    // A value that can't be serialized
+    struct Unserializable;
+
+    impl Serialize for Unserializable {
+        fn serialize<S: serde::Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("not serializable"))
+        }
+    }
+
+    #[test]
+    fn a_frame_that_fails_to_serialize_is_a_serialization_error() {
+        let error = encode(&Unserializable, "request").unwrap_err();
+
+        assert!(matches!(&error, CodeReviewError::SerializationError(msg) if msg.contains("request: not serializable")));
+        assert!(error.to_string().starts_with("Serialization error: "));
+    }
+
+    #[tokio::test]
+    async fn each_agents_transcript_is_dumped_under_the_request_id() {
+        let gateway = MockGateway::voting(&["POSITIVE, clear", "NEGATIVE, slow", "POSITIVE"]).await;
+        let dir = test_util::temp_dir().join("dumps");
+        let config = CodeReviewConfig {
+            review_dump_dir: Some(dir.clone()),
+            ..gateway.config()
+        };
+
+        CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        let request_id = gateway.judgements()[0]["request_id"].as_str().unwrap().to_string();
+        let dump = |agent: &str| std::fs::read_to_string(dir.join(&request_id).join(format!("{}.md", agent))).unwrap();
+        assert_eq!(dump("melchior"), "# Melchior\n\nDecision: POSITIVE\n\nPOSITIVE, clear\n");
+        assert_eq!(dump("balthasar"), "# Balthasar\n\nDecision: NEGATIVE\n\nNEGATIVE, slow\n");
+        assert!(dump("casper").starts_with("# Casper\n\nDecision: POSITIVE"));
+    }
+
+    #[tokio::test]
+    async fn two_approvals_pass_over_an_erroring_agent() {
+        let gateway = MockGateway::start(|_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            let ids = test_util::agent_ids(frame);
+            let mut replies = vec![Reply::Frame(test_util::agent_error(frame, &ids[0], "overloaded", "model failed"))];
+            replies.extend(ids[1..].iter().flat_map(|id| test_util::vote(frame, id, "DECISION: POSITIVE")));
+            replies
+        })
+        .await;
+        let dir = test_util::temp_dir().join("dumps");
+        let config = CodeReviewConfig {
+            agent_retries: 0,
+            review_dump_dir: Some(dir.clone()),
+            ..gateway.config()
+        };
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.passed());
+        assert_eq!(output.result, "POSITIVE");
+        assert!(output.magi_state.agents[0].errored);
+        assert_eq!(output.magi_state.responding_count(), 2);
+        let request_id = gateway.judgements()[0]["request_id"].as_str().unwrap().to_string();
+        let dump = std::fs::read_to_string(dir.join(&request_id).join("melchior.md")).unwrap();
+        assert!(dump.starts_with("# Melchior\n\nDecision: ERROR\n"));
+    }
+
+    // Melchior and balthasar reject at once; casper approves a little later
+    async fn late_third_vote() -> MockGateway {
+        MockGateway::start(|_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            let ids = test_util::agent_ids(frame);
+            let mut replies = test_util::vote(frame, &ids[0], "NEGATIVE");
+            replies.extend(test_util::vote(frame, &ids[1], "NEGATIVE"));
+            replies.push(Reply::Wait(Duration::from_millis(200)));
+            replies.extend(test_util::vote(frame, &ids[2], "POSITIVE"));
+            replies
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn a_locked_rejection_lists_the_agents_not_waited_for() {
+        let gateway = late_third_vote().await;
+
+        let output = CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert_eq!(output.result, "NEGATIVE");
+        assert_eq!(output.pending_agents, ["casper"]);
+        assert_eq!(output.to_json()["pending_agents"], json!(["casper"]));
+    }
+
+    #[tokio::test]
+    async fn wait_for_all_waits_for_the_last_verdict() {
+        let gateway = late_third_vote().await;
+        let config = CodeReviewConfig {
+            wait_for_all: true,
+            ..gateway.config()
+        };
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert_eq!(output.result, "NEGATIVE");
+        assert!(output.pending_agents.is_empty());
+        assert_eq!(output.magi_state.agents[2].decision, Some(MAGIDecision::POSITIVE));
+    }
+
+    // An agent's whole review in one completed frame with a numeric
+    // timestamp, under the given message type
+    fn agent_response(message_type: &str, request: &serde_json::Value, agent_id: &str, content: &str) -> Reply {
+        Reply::Frame(json!({
+            "type": message_type,
+            "request_id": request["request_id"],
+            "agent_id": agent_id,
+            "content": content,
+            "status": "completed",
+            "timestamp": 1704067200.0,
+        }))
+    }
+
+    #[tokio::test]
+    async fn custom_message_types_are_sent_and_expected() {
+        let gateway = MockGateway::start(|_, frame| {
+            if frame["type"] != "review_request" {
+                return Vec::new();
+            }
+            let ids = test_util::agent_ids(frame);
+            vec![
+                // Frames of the default type are another protocol's and ignored
+                agent_response("agent_response", frame, &ids[0], "NEGATIVE"),
+                agent_response("review_reply", frame, &ids[0], "POSITIVE"),
+                agent_response("review_reply", frame, &ids[1], "POSITIVE"),
+            ]
+        })
+        .await;
+        let mut config = gateway.config();
+        config.message_types.judgement = "review_request".to_string();
+        config.message_types.agent_response = "review_reply".to_string();
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.passed());
+        assert_eq!(output.magi_state.agents[0].transcript(), "POSITIVE");
+    }
+
+    #[tokio::test]
+    async fn results_keep_panel_order_whatever_order_agents_finish_in() {
+        let gateway = MockGateway::start(|_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            let ids = test_util::agent_ids(frame);
+            let mut replies = test_util::vote(frame, &ids[2], "NEGATIVE");
+            replies.extend(test_util::vote(frame, &ids[1], "POSITIVE"));
+            replies.extend(test_util::vote(frame, &ids[0], "POSITIVE"));
+            replies
+        })
+        .await;
+        let tool = CodeReviewTool::with_config(gateway.config());
+
+        for _ in 0..2 {
+            let output = tool.call(test_util::args("fn add() {}")).await.unwrap();
+
+            let agents: Vec<&str> = output.decisions().iter().map(|decision| decision.agent.as_str()).collect();
+            assert_eq!(agents, ["melchior", "balthasar", "casper"]);
+            let names: Vec<&str> = output.magi_state.agents.iter().map(|state| state.name.as_str()).collect();
+            assert_eq!(names, agents);
+            let trace: Vec<&str> = output.decision_trace().steps.iter().map(|step| step.agent.as_str()).collect();
+            assert_eq!(trace, ["casper", "balthasar", "melchior"]);
+        }
+    }
+
+    #[test]
+    fn review_args_round_trip_with_their_tests() {
+        let mut args = test_util::args("fn add(a: i32, b: i32) -> i32 { a + b }");
+        args.tests = Some("assert_eq!(add(1, 2), 3);".to_string());
+
+        let json = serde_json::to_value(&args).unwrap();
+        assert_eq!(json["tests"], "assert_eq!(add(1, 2), 3);");
+        let back: CodeReviewArgs = serde_json::from_value(json).unwrap();
+        assert_eq!(back.tests, args.tests);
+
+        let without = serde_json::to_value(test_util::args("fn add() {}")).unwrap();
+        assert!(without.get("tests").is_none());
+        assert_eq!(tool_definition().parameters["properties"]["tests"]["type"], "string");
+    }
+
+    #[tokio::test]
+    async fn tests_are_sent_in_their_own_section() {
+        let gateway = MockGateway::voting(&["POSITIVE", "POSITIVE", "POSITIVE"]).await;
+        let mut args = test_util::args("fn add() {}");
+        args.tests = Some("assert_eq!(add(), ());\n".to_string());
+
+        CodeReviewTool::with_config(gateway.config()).call(args).await.unwrap();
+
+        let request = gateway.judgements()[0]["request"].as_str().unwrap().to_string();
+        assert!(request.contains("\n<tests>\nassert_eq!(add(), ());\n</tests>"));
+    }
+
+    #[tokio::test]
+    async fn a_not_ready_gateway_is_retried_after_its_hint() {
+        let judgements = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = judgements.clone();
+        let gateway = MockGateway::start(move |_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            if counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                return vec![Reply::Frame(json!({ "type": "not_ready", "reason": "warming up", "retry_after": 0.01 }))];
+            }
+            test_util::agent_ids(frame).iter().flat_map(|id| test_util::vote(frame, id, "POSITIVE")).collect()
+        })
+        .await;
+
+        let output = CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.passed());
+        assert_eq!(gateway.judgements().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_not_ready_gateway_without_a_hint_fails_the_review() {
+        let gateway = MockGateway::start(|_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            vec![Reply::Frame(json!({ "type": "unavailable", "request_id": frame["request_id"], "reason": "no agents" }))]
+        })
+        .await;
+
+        let result = CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await;
+
+        assert!(matches!(result, Err(CodeReviewError::ConnectionError(msg)) if msg == "gateway not ready: no agents"));
+    }
+
+    fn two_files() -> Vec<ReviewFile> {
+        vec![
+            ReviewFile { path: "src/add.rs".to_string(), content: "pub fn add() {}".to_string() },
+            ReviewFile { path: "src/lib.rs".to_string(), content: "mod add;".to_string() },
+        ]
+    }
+
+    #[test]
+    fn files_are_submitted_as_labeled_sections_after_the_code() {
+        let mut args = test_util::args("");
+        args.files = two_files();
+        assert_eq!(
+            args.submission(),
+            "<file path=\"src/add.rs\">\npub fn add() {}\n</file>\n<file path=\"src/lib.rs\">\nmod add;\n</file>"
+        );
+
+        args.code = "fn main() {}".to_string();
+        assert!(args.submission().starts_with("fn main() {}\n<file path=\"src/add.rs\">"));
+        assert_eq!(test_util::args("fn add() {}").submission(), "fn add() {}");
+    }
+
+    #[tokio::test]
+    async fn a_multi_file_change_gets_one_verdict() {
+        let gateway = MockGateway::voting(&["POSITIVE", "NEGATIVE", "POSITIVE"]).await;
+        let mut args = test_util::args("");
+        args.files = two_files();
+
+        let output = CodeReviewTool::with_config(gateway.config()).call(args).await.unwrap();
+
+        assert!(output.passed());
+        let request = gateway.judgements()[0]["request"].as_str().unwrap().to_string();
+        assert!(request.contains("<file path=\"src/add.rs\">\npub fn add() {}\n</file>"));
+        assert!(request.contains("<file path=\"src/lib.rs\">\nmod add;\n</file>"));
+    }
+
+    #[tokio::test]
+    async fn a_split_rejection_is_rereviewed_and_the_majority_of_runs_wins() {
+        let judgements = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = judgements.clone();
+        let gateway = MockGateway::start(move |_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            let votes = match counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => ["POSITIVE", "NEGATIVE", "NEGATIVE"],
+                _ => ["POSITIVE", "POSITIVE", "POSITIVE"],
+            };
+            test_util::agent_ids(frame)
+                .iter()
+                .zip(votes)
+                .flat_map(|(id, content)| test_util::vote(frame, id, content))
+                .collect()
+        })
+        .await;
+        let config = CodeReviewConfig {
+            rereview_on_split: 2,
+            ..gateway.config()
+        };
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.passed());
+        assert_eq!(output.rereviews, 2);
+        assert_eq!(gateway.judgements().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn a_clear_rejection_isnt_rereviewed() {
+        let gateway = MockGateway::voting(&["NEGATIVE", "NEGATIVE", "NEGATIVE"]).await;
+        let config = CodeReviewConfig {
+            rereview_on_split: 2,
+            ..gateway.config()
+        };
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(!output.passed());
+        assert_eq!(output.rereviews, 0);
+        assert_eq!(gateway.judgements().len(), 1);
+    }
+
+    // A config forcing `decision` for the SHA-256 of `code`
+    fn overriding(code: &str, decision: MAGIDecision, server_url: String) -> CodeReviewConfig {
+        let hash = hex::encode(Sha256::digest(code.as_bytes()));
+        CodeReviewConfig {
+            server_url,
+            verdict_overrides: HashMap::from([(hash, decision)]),
+            ..CodeReviewConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn an_overridden_hash_skips_the_gateway() {
+        // Nothing listens on port 1, so reaching the gateway would fail the call
+        let unreachable = "ws://127.0.0.1:1/review".to_string();
+
+        let allowed = CodeReviewTool::with_config(overriding("fn add() {}", MAGIDecision::POSITIVE, unreachable.clone()))
+            .call(test_util::args("fn add() {}"))
+            .await
+            .unwrap();
+        assert!(allowed.passed());
+        assert!(allowed.overridden);
+
+        let denied = CodeReviewTool::with_config(overriding("fn add() {}", MAGIDecision::NEGATIVE, unreachable))
+            .call(test_util::args("fn add() {}"))
+            .await
+            .unwrap();
+        assert!(!denied.passed());
+        assert!(denied.overridden);
+    }
+
+    #[tokio::test]
+    async fn other_code_falls_through_to_the_panel() {
+        let gateway = MockGateway::voting(&["NEGATIVE", "NEGATIVE", "NEGATIVE"]).await;
+        let config = CodeReviewConfig {
+            review_timeout: gateway.config().review_timeout,
+            ..overriding("fn add() {}", MAGIDecision::POSITIVE, gateway.url())
+        };
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args("fn sub() {}")).await.unwrap();
+
+        assert!(!output.passed());
+        assert!(!output.overridden);
+        assert_eq!(gateway.judgements().len(), 1);
+    }
+
+    #[test]
+    fn only_listed_agents_log_their_frames() {
+        let tool = CodeReviewTool::with_config(CodeReviewConfig {
+            debug_agents: vec!["casper".to_string()],
+            ..CodeReviewConfig::default()
+        });
+        let magi_state = MAGISystemState::default();
+
+        let logs = test_util::capture_logs(|| {
+            for agent_state in &magi_state.agents {
+                tool.trace_agent(agent_state, "streaming", &format!("chunk from {}", agent_state.name));
+            }
+        });
+
+        assert!(logs.contains("Agent frame: chunk from casper"));
+        assert!(!logs.contains("chunk from melchior"));
+        assert!(!logs.contains("chunk from balthasar"));
+    }
+
+    #[tokio::test]
+    async fn a_chunk_after_completion_is_kept_within_the_settle_window() {
+        let gateway = MockGateway::start(|_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            let ids = test_util::agent_ids(frame);
+            let mut replies: Vec<Reply> = ids.iter().flat_map(|id| test_util::vote(frame, id, "POSITIVE")).collect();
+            replies.push(Reply::Wait(Duration::from_millis(20)));
+            replies.push(Reply::Frame(test_util::streaming(frame, &ids[0], " Also well named.")));
+            replies
+        })
+        .await;
+        let config = CodeReviewConfig {
+            wait_for_all: true,
+            settle_window: Some(Duration::from_millis(300)),
+            ..gateway.config()
+        };
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.passed());
+        assert_eq!(output.magi_state.agents[0].transcript(), "POSITIVE Also well named.");
+    }
+
+    #[tokio::test]
+    async fn each_tool_authenticates_with_its_own_credentials() {
+        let gateway = MockGateway::voting(&["POSITIVE", "POSITIVE", "POSITIVE"]).await;
+        let first = CodeReviewTool::with_config(gateway.config()).with_credentials(GatewayCredentials::Secret {
+            app_id: "tenant-a".to_string(),
+            app_secret: "secret-a".to_string(),
+        });
+        let second = CodeReviewTool::with_config(gateway.config()).with_credentials(GatewayCredentials::Token {
+            app_id: "tenant-b".to_string(),
+            token: "0123456789".to_string(),
+        });
+
+        first.call(test_util::args("fn add() {}")).await.unwrap();
+        second.call(test_util::args("fn add() {}")).await.unwrap();
+
+        let uris = gateway.uris();
+        assert_eq!(uris.len(), 2);
+        let token = uris[0].split("token=").nth(1).unwrap();
+        assert!(uris[0].contains("appid=tenant-a"));
+        assert_eq!(token.len(), 10);
+        assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+        assert!(uris[1].ends_with("?appid=tenant-b&token=0123456789"));
+    }
+
+    #[tokio::test]
+    async fn a_duplicated_agent_fails_before_reaching_the_gateway() {
+        let gateway = MockGateway::voting(&["POSITIVE", "POSITIVE", "POSITIVE"]).await;
+        let mut agents = panel(&["melchior", "balthasar"]);
+        agents.push(agents[0].clone());
+        let config = CodeReviewConfig { agents, ..gateway.config() };
+
+        let result = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await;
+
+        assert!(matches!(result, Err(CodeReviewError::ConfigError(_))));
+        assert!(gateway.received().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_transient_error_is_resubmitted_to_that_agent_alone() {
+        let gateway = MockGateway::start(|_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            let ids = test_util::agent_ids(frame);
+            if ids.len() == 1 {
+                return test_util::vote(frame, &ids[0], "POSITIVE");
+            }
+            let mut replies = vec![Reply::Frame(test_util::agent_error(frame, &ids[0], "overloaded", "model failed"))];
+            replies.extend(test_util::vote(frame, &ids[1], "POSITIVE"));
+            replies.extend(test_util::vote(frame, &ids[2], "NEGATIVE"));
+            replies
+        })
+        .await;
+        let config = CodeReviewConfig {
+            agent_retries: 1,
+            ..gateway.config()
+        };
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.passed());
+        assert!(output.agent_errors.is_empty());
+        let judgements = gateway.judgements();
+        assert_eq!(judgements.len(), 2);
+        assert_eq!(test_util::agent_ids(&judgements[1]), [output.magi_state.agents[0].agent_id.clone()]);
+        assert_eq!(output.magi_state.agents[0].decision, Some(MAGIDecision::POSITIVE));
+    }
+
+    #[tokio::test]
+    async fn a_resent_chunk_is_appended_once_when_deduping() {
+        let gateway = MockGateway::start(|_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            test_util::agent_ids(frame)
+                .iter()
+                .flat_map(|id| {
+                    vec![
+                        Reply::Frame(test_util::streaming(frame, id, "POSITIVE, clear.")),
+                        Reply::Frame(test_util::streaming(frame, id, "POSITIVE, clear.")),
+                        Reply::Frame(test_util::completed(frame, id)),
+                    ]
+                })
+                .collect()
+        })
+        .await;
+        let deduping = CodeReviewConfig {
+            dedupe_chunks: true,
+            ..gateway.config()
+        };
+
+        let kept = CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await.unwrap();
+        let deduped = CodeReviewTool::with_config(deduping).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert_eq!(kept.magi_state.agents[0].transcript(), "POSITIVE, clear.POSITIVE, clear.");
+        assert_eq!(deduped.magi_state.agents[0].transcript(), "POSITIVE, clear.");
+    }
+
+    #[tokio::test]
+    async fn a_critical_security_issue_vetoes_a_majority_approval() {
+        let gateway =
+            MockGateway::voting(&["POSITIVE\n- [SECURITY][CRITICAL] SQL injection in the query", "POSITIVE", "POSITIVE"])
+                .await;
+        let vetoing = CodeReviewConfig {
+            security_veto: true,
+            ..gateway.config()
+        };
+
+        let counted = CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await.unwrap();
+        let vetoed = CodeReviewTool::with_config(vetoing).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(counted.passed());
+        assert!(counted.veto.is_none());
+        assert!(!vetoed.passed());
+        assert_eq!(vetoed.result, "NEGATIVE");
+        assert!(vetoed.veto.as_deref().unwrap().ends_with("[SECURITY][CRITICAL] SQL injection in the query"));
+    }
+
+    #[tokio::test]
+    async fn an_exact_quorum_pass_is_flagged_in_flag_mode() {
+        let gateway = MockGateway::voting(&["POSITIVE", "POSITIVE", "POSITIVE"]).await;
+        let flagging = CodeReviewConfig {
+            minimum_margin_mode: MinimumMarginMode::Flag,
+            ..gateway.config()
+        };
+
+        let exact = CodeReviewTool::with_config(flagging.clone()).call(test_util::args("fn add() {}")).await.unwrap();
+        let unanimous = CodeReviewTool::with_config(CodeReviewConfig { wait_for_all: true, ..flagging })
+            .call(test_util::args("fn add() {}"))
+            .await
+            .unwrap();
+        let unflagged = CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(exact.passed() && exact.minimum_margin && exact.close_call);
+        assert!(unanimous.passed() && !unanimous.minimum_margin);
+        assert!(unflagged.passed() && !unflagged.minimum_margin);
+    }
+
+    #[test]
+    fn live_output_prints_whole_lines_prefixed_with_the_agent() {
+        let mut live = LiveOutput::new(Vec::new());
+
+        live.push("melchior", "Looks ");
+        live.push("casper", "DECISION: NEG");
+        live.push("melchior", "fine\nDECISION: POS");
+        live.finish("melchior");
+        live.push("casper", "ATIVE\n");
+        live.finish("casper");
+
+        assert_eq!(
+            String::from_utf8(live.out).unwrap(),
+            "[Melchior] Looks fine\n[Melchior] DECISION: POS\n[Casper] DECISION: NEGATIVE\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn the_json_output_lists_each_agents_decision_under_its_schema_version() {
+        let gateway = MockGateway::voting(&["DECISION: POSITIVE", "DECISION: NEGATIVE", "DECISION: POSITIVE"]).await;
+
+        let output = CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await.unwrap();
+
+        let json = output.to_json();
+        assert_eq!(json["schema_version"], OUTPUT_SCHEMA_VERSION);
+        assert_eq!(json["result"], "POSITIVE");
+        assert_eq!(json["passed"], true);
+        assert_eq!(
+            json["decisions"],
+            json!([
+                { "agent": "melchior", "decision": "POSITIVE", "errored": false },
+                { "agent": "balthasar", "decision": "NEGATIVE", "errored": false },
+                { "agent": "casper", "decision": "POSITIVE", "errored": false },
+            ])
+        );
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&output.to_string()).unwrap(), json);
+    }
+
+    #[test]
+    fn the_reconnect_delay_doubles_up_to_the_cap() {
+        let base = Duration::from_millis(500);
+
+        assert_eq!(reconnect_delay(base, 0), base);
+        assert_eq!(reconnect_delay(base, 3), Duration::from_secs(4));
+        assert_eq!(reconnect_delay(base, 32), MAX_RECONNECT_DELAY);
+        assert_eq!(reconnect_delay(base, u8::MAX), MAX_RECONNECT_DELAY);
+    }
+
+    #[test]
+    fn gateway_urls_are_normalized_to_websocket_schemes() {
+        let url = |raw: &str| normalize_ws_url(raw).map(|url| url.to_string());
+
+        assert_eq!(url("http://gw.example/review"), Ok("ws://gw.example/review".to_string()));
+        assert_eq!(url(" https://gw.example:8443/review "), Ok("wss://gw.example:8443/review".to_string()));
+        assert_eq!(url("ws://localhost:8080"), Ok("ws://localhost:8080/".to_string()));
+        assert_eq!(url("ws://localhost:8080/review"), Ok("ws://localhost:8080/review".to_string()));
+        assert!(url("ftp://gw.example/review").unwrap_err().contains("got ftp://"));
+        assert!(url("not a url").unwrap_err().starts_with("Invalid gateway URL"));
+    }
+
+    #[test]
+    fn connecting_over_http_warns_about_the_upgrade() {
+        let config = CodeReviewConfig {
+            server_url: "http://127.0.0.1:9/review".to_string(),
+            ..CodeReviewConfig::default()
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+        let logs = test_util::capture_logs(|| {
+            let _ = runtime.block_on(connect(&config));
+        });
+
+        assert!(logs.contains("is not a WebSocket URL, connecting with ws:// instead"));
+        assert!(logs.contains("Connecting to ws://127.0.0.1:9/review?appid="));
+    }
+
+    #[test]
+    fn the_auth_token_is_ten_hex_characters_fixed_per_minute() {
+        let token = generate_token("app-id", "secret", 28_000_000);
+
+        assert_eq!(token, "92a84526a7");
+        assert_eq!(token.len(), 10);
+        assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(generate_token("app-id", "secret", 28_000_000), token);
+        assert_ne!(generate_token("app-id", "secret", 28_000_001), token);
+        assert_ne!(generate_token("app-id", "other", 28_000_000), token);
+    }
+
+    #[test]
+    fn the_logged_gateway_url_has_its_token_masked() {
+        let config = CodeReviewConfig {
+            server_url: "ws://127.0.0.1:9/review".to_string(),
+            auth_token: Some("0123456789".to_string()),
+            ..CodeReviewConfig::default()
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+        let mut result = None;
+        let logs = test_util::capture_logs(|| result = Some(runtime.block_on(connect(&config)).map(|_| ())));
+
+        assert!(logs.contains("Connecting to ws://127.0.0.1:9/review?appid="));
+        assert!(logs.contains("token=***"));
+        assert!(!logs.contains("0123456789"));
+        let Some(Err(CodeReviewError::ConnectionError(message))) = result else {
+            panic!("connected to a closed port");
+        };
+        assert!(!message.contains("0123456789"));
+    }
+
+    #[tokio::test]
+    async fn base64_content_is_decoded_before_the_verdict() {
+        use base64::prelude::{Engine as _, BASE64_STANDARD};
+
+        let encoded = [
+            BASE64_STANDARD.encode("POSITIVE, clear 👍"),
+            BASE64_STANDARD.encode("POSITIVE"),
+            BASE64_STANDARD.encode("NEGATIVE"),
+        ];
+        let gateway = MockGateway::voting(&[&encoded[0], &encoded[1], &encoded[2]]).await;
+        let config = CodeReviewConfig {
+            content_encoding: crate::config::ContentEncoding::Base64,
+            ..gateway.config()
+        };
+
+        let output = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.passed());
+        assert_eq!(output.magi_state.agents[0].transcript(), "POSITIVE, clear 👍");
+    }
+
+    fn tiers() -> Vec<crate::config::RiskTier> {
+        let tier = |name: &str, patterns: &[&str], quorum, unanimous| crate::config::RiskTier {
+            name: name.to_string(),
+            patterns: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+            quorum,
+            unanimous,
+        };
+        vec![tier("high", &["password", "crypto"], None, true), tier("low", &["fn get_"], Some(1), false)]
+    }
+
+    #[tokio::test]
+    async fn the_matched_risk_tier_sets_the_quorum() {
+        let split_approval = MockGateway::voting(&["POSITIVE", "POSITIVE", "NEGATIVE"]).await;
+        let single_approval = MockGateway::voting(&["NEGATIVE", "POSITIVE", "NEGATIVE"]).await;
+        let review = |gateway: &MockGateway, code: &'static str| {
+            let tool = CodeReviewTool::with_config(CodeReviewConfig {
+                risk_tiers: tiers(),
+                ..gateway.config()
+            });
+            async move { tool.call(test_util::args(code)).await.unwrap() }
+        };
+
+        let untiered = review(&split_approval, "fn add() {}").await;
+        let high = review(&split_approval, "fn check_Password() {}").await;
+        let low = review(&single_approval, "fn get_name() {}").await;
+
+        assert!(untiered.passed() && untiered.risk_tier.is_none());
+        assert!(!high.passed());
+        assert_eq!(high.risk_tier.as_deref(), Some("high"));
+        assert!(low.passed());
+        assert_eq!(low.risk_tier.as_deref(), Some("low"));
+    }
+
+    // A gateway that greets each request with `supported_versions`, then
+    // approves
+    async fn speaking(supported_versions: &'static [u32]) -> MockGateway {
+        MockGateway::start(move |_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            let mut replies = vec![Reply::Frame(json!({
+                "type": "connection_established",
+                "session_id": "mock-session",
+                "supported_versions": supported_versions,
+            }))];
+            replies.extend(test_util::agent_ids(frame).iter().flat_map(|id| test_util::vote(frame, id, "POSITIVE")));
+            replies
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn a_gateway_without_the_pinned_protocol_version_fails_the_review() {
+        let gateway = speaking(&[2, 3]).await;
+
+        let result = CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await;
+
+        let Err(CodeReviewError::ProtocolError(message)) = result else {
+            panic!("reviewed over an incompatible protocol");
+        };
+        assert_eq!(message, "gateway speaks protocol version [2, 3], this client is pinned to 1");
+        assert_eq!(gateway.judgements()[0]["protocol_version"], 1);
+    }
+
+    #[tokio::test]
+    async fn a_gateway_supporting_the_pinned_version_reviews_as_usual() {
+        let gateway = speaking(&[1, 2]).await;
+
+        let output = CodeReviewTool::with_config(gateway.config()).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.passed());
+    }
+
+    #[test]
+    fn the_quorum_decides_unanimous_split_and_incomplete_votes() {
+        use MAGIDecision::{NEGATIVE, POSITIVE};
+
+        let unanimous = votes(&[Some(POSITIVE), Some(POSITIVE), Some(POSITIVE)]);
+        let split = votes(&[Some(POSITIVE), Some(NEGATIVE), Some(POSITIVE)]);
+        let incomplete = votes(&[Some(POSITIVE), Some(NEGATIVE), None]);
+
+        assert_eq!(unanimous.get_final_decision_with_quorum(3), Some(POSITIVE));
+        assert_eq!(split.get_final_decision_with_quorum(2), Some(POSITIVE));
+        assert_eq!(split.get_final_decision_with_quorum(3), Some(NEGATIVE));
+        assert_eq!(incomplete.get_final_decision_with_quorum(1), Some(POSITIVE));
+        assert_eq!(incomplete.get_final_decision_with_quorum(2), None);
+        assert_eq!(votes(&[Some(NEGATIVE), Some(NEGATIVE), None]).get_final_decision_with_quorum(2), None);
+        assert_eq!(split.get_final_decision(), Some(POSITIVE));
+    }
+
+    #[test]
+    fn the_language_comes_from_the_argument_or_the_first_fence() {
+        let fenced = test_util::args("Here:\n```Python title=add.py\ndef add(): pass\n```");
+        let given = CodeReviewArgs {
+            language: Some(" rust ".to_string()),
+            ..fenced.clone()
+        };
+
+        assert_eq!(fenced.detected_language(), Some("Python"));
+        assert_eq!(given.detected_language(), Some("rust"));
+        assert_eq!(test_util::args("fn add() {}").detected_language(), None);
+    }
+
+    #[tokio::test]
+    async fn code_in_a_language_not_allowed_never_reaches_the_panel() {
+        let gateway = MockGateway::voting(&["POSITIVE", "POSITIVE", "POSITIVE"]).await;
+        let tool = CodeReviewTool::with_config(CodeReviewConfig {
+            allowed_languages: vec!["rust".to_string(), "python".to_string()],
+            ..gateway.config()
+        });
+
+        let go = tool.call(test_util::args("```go\nfunc add() {}\n```")).await.unwrap();
+        let python = tool.call(test_util::args("```python\ndef add(): pass\n```")).await.unwrap();
+        let unknown = tool.call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(!go.passed());
+        assert!(go.reviews[0].contains("go is not an allowed language; use one of: rust, python"));
+        assert!(python.passed() && unknown.passed());
+        assert_eq!(gateway.judgements().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_verdict_cached_on_disk_is_reused_by_another_tool() {
+        let gateway = MockGateway::voting(&["POSITIVE", "POSITIVE", "NEGATIVE"]).await;
+        let config = CodeReviewConfig {
+            review_cache_dir: Some(test_util::temp_dir()),
+            ..gateway.config()
+        };
+
+        let first = CodeReviewTool::with_config(config.clone()).call(test_util::args("fn add() {}")).await.unwrap();
+        let second = CodeReviewTool::with_config(config).call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(!first.cached);
+        assert!(second.cached);
+        assert_eq!(gateway.judgements().len(), 1);
+        assert_eq!((second.passed(), second.result.as_str()), (true, "POSITIVE"));
+        assert_eq!(second.magi_state.agents[0].transcript(), "POSITIVE");
+    }
+
+    #[tokio::test]
+    async fn panels_of_one_and_five_decide_by_their_own_majority() {
+        let five = MockGateway::voting(&["POSITIVE", "NEGATIVE", "POSITIVE", "NEGATIVE", "POSITIVE"]).await;
+        let one = MockGateway::voting(&["NEGATIVE"]).await;
+        let config = |gateway: &MockGateway, names: &[&str]| CodeReviewConfig {
+            agents: panel(names),
+            wait_for_all: true,
+            ..gateway.config()
+        };
+
+        let five_output = CodeReviewTool::with_config(config(&five, &["a", "b", "c", "d", "e"]))
+            .call(test_util::args("fn add() {}"))
+            .await
+            .unwrap();
+        let one_output = CodeReviewTool::with_config(config(&one, &["solo"]))
+            .call(test_util::args("fn add() {}"))
+            .await
+            .unwrap();
+
+        assert!(five_output.passed());
+        assert_eq!(five_output.magi_state.agents.len(), 5);
+        assert_eq!(five_output.magi_state.positive_count(), 3);
+        assert!(!one_output.passed());
+        assert_eq!(one_output.magi_state.agents[0].decision, Some(MAGIDecision::NEGATIVE));
     }
 }
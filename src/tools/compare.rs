@@ -0,0 +1,165 @@
+use serde::Serialize;
+
+use super::code_review::{CodeReviewOutput, MAGIDecision};
+use super::report::ReviewReport;
+
+/// Which of two versions came out ahead
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preferred {
+    Old,
+    New,
+    /// Same outcome, votes and confidence
+    Tie,
+}
+
+/// How one version fared with the panel
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionScore {
+    pub path: String,
+    pub passed: bool,
+    /// Counted POSITIVE votes
+    pub positive: usize,
+    /// POSITIVE votes above (or, when negative, short of) the number needed
+    /// to pass
+    pub margin: i64,
+    /// Mean confidence of the agents that reported one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,
+}
+
+impl VersionScore {
+    pub fn new(path: &str, output: &CodeReviewOutput, required_positive: usize) -> Self {
+        let magi_state = output.magi_state();
+        let positive = magi_state.positive_count();
+
+        // A NEGATIVE vote's confidence counts against the version
+        let confidences: Vec<f32> = magi_state
+            .agents
+            .iter()
+            .filter_map(|agent| match (agent.decision, agent.confidence) {
+                (Some(MAGIDecision::POSITIVE), Some(confidence)) => Some(confidence),
+                (Some(MAGIDecision::NEGATIVE), Some(confidence)) => Some(1.0 - confidence),
+                _ => None,
+            })
+            .collect();
+        let confidence =
+            (!confidences.is_empty()).then(|| confidences.iter().sum::<f32>() / confidences.len() as f32);
+
+        Self {
+            path: path.to_string(),
+            passed: output.passed(),
+            positive,
+            margin: positive as i64 - required_positive as i64,
+            confidence,
+        }
+    }
+}
+
+/// Two versions of the same code reviewed side by side
+#[derive(Serialize)]
+pub struct Comparison {
+    pub preferred: Preferred,
+    /// Why `preferred` was picked, e.g. "only new passed"
+    pub reason: String,
+    pub old: VersionScore,
+    pub new: VersionScore,
+    pub old_review: ReviewReport,
+    pub new_review: ReviewReport,
+}
+
+impl Comparison {
+    /// Rank the versions: a pass beats a failure, then more POSITIVE votes
+    /// win, then the higher mean confidence. Anything else is a tie.
+    pub fn new(old: VersionScore, old_review: ReviewReport, new: VersionScore, new_review: ReviewReport) -> Self {
+        let (preferred, reason) = if old.passed != new.passed {
+            let preferred = if new.passed { Preferred::New } else { Preferred::Old };
+            (preferred, format!("only {} passed", name(preferred)))
+        } else if old.positive != new.positive {
+            let preferred = if new.positive > old.positive { Preferred::New } else { Preferred::Old };
+            (preferred, format!("both {}, {} had more approvals", outcome(new.passed), name(preferred)))
+        } else {
+            match (old.confidence, new.confidence) {
+                (Some(old_confidence), Some(new_confidence)) if old_confidence != new_confidence => {
+                    let preferred = if new_confidence > old_confidence { Preferred::New } else { Preferred::Old };
+                    (
+                        preferred,
+                        format!("both {} with the same votes, {} had higher confidence", outcome(new.passed), name(preferred)),
+                    )
+                }
+                _ => (Preferred::Tie, format!("both {} with the same votes", outcome(new.passed))),
+            }
+        };
+
+        Self {
+            preferred,
+            reason,
+            old,
+            new,
+            old_review,
+            new_review,
+        }
+    }
+}
+
+fn name(preferred: Preferred) -> &'static str {
+    match preferred {
+        Preferred::Old => "old",
+        Preferred::New => "new",
+        Preferred::Tie => "neither",
+    }
+}
+
+fn outcome(passed: bool) -> &'static str {
+    if passed {
+        "passed"
+    } else {
+        "failed"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, MockGateway};
+    use crate::tools::code_review::CodeReviewTool;
+
+    // Review both versions against a gateway that approves only code
+    // containing `approved`, and compare them
+    async fn compare(old: &str, new: &str, approved: &'static str) -> Comparison {
+        let gateway = MockGateway::start(move |_, frame| {
+            if !test_util::is_judgement(frame) {
+                return Vec::new();
+            }
+            let vote = if frame["request"].as_str().unwrap_or_default().contains(approved) { "POSITIVE" } else { "NEGATIVE" };
+            test_util::agent_ids(frame).iter().flat_map(|id| test_util::vote(frame, id, vote)).collect()
+        })
+        .await;
+        let tool = CodeReviewTool::with_config(gateway.config());
+        let required_positive = gateway.config().required_positive();
+
+        let old_review = tool.review_report(test_util::args(old)).await.unwrap();
+        let new_review = tool.review_report(test_util::args(new)).await.unwrap();
+        let old_score = VersionScore::new("old.rs", &old_review.output, required_positive);
+        let new_score = VersionScore::new("new.rs", &new_review.output, required_positive);
+        Comparison::new(old_score, old_review, new_score, new_review)
+    }
+
+    #[tokio::test]
+    async fn the_version_that_passed_is_preferred() {
+        let comparison = compare("fn add() {}", "fn add_checked() {}", "add_checked").await;
+
+        assert_eq!(comparison.preferred, Preferred::New);
+        assert_eq!(comparison.reason, "only new passed");
+        assert!(!comparison.old.passed && comparison.new.passed);
+        assert_eq!((comparison.old.margin, comparison.new.margin), (-2, 0));
+    }
+
+    #[tokio::test]
+    async fn versions_with_the_same_votes_tie() {
+        let comparison = compare("fn add() {}", "fn add() { }", "nothing").await;
+
+        assert_eq!(comparison.preferred, Preferred::Tie);
+        assert_eq!(comparison.reason, "both failed with the same votes");
+    }
+}
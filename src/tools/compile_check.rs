@@ -0,0 +1,165 @@
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+use tokio::process::Command;
+use uuid::Uuid;
+
+use super::code_review::CodeReviewError;
+
+// A compiler that hangs shouldn't hold up the review
+const CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A local syntax/compile check run before the code goes to the panel.
+///
+/// `command` is split on whitespace; `{file}` is replaced with the path of a
+/// temporary file holding the code and `{dir}` with its directory, e.g.
+/// `rustc --edition 2021 --emit=metadata --out-dir {dir} {file}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompileCheck {
+    /// Language name, matched case-insensitively against the review's language
+    pub language: String,
+    /// File extension for the temporary source file, without the dot
+    pub extension: String,
+    /// Command template
+    pub command: String,
+}
+
+/// Result of running a compile check
+#[derive(Debug)]
+pub enum CompileOutcome {
+    Passed,
+    /// The command exited nonzero; holds its combined output
+    Failed(String),
+}
+
+impl CompileCheck {
+    /// Built-in checks for Rust and Python
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            CompileCheck {
+                language: "rust".to_string(),
+                extension: "rs".to_string(),
+                command: "rustc --edition 2021 --crate-type lib --emit=metadata --out-dir {dir} {file}".to_string(),
+            },
+            CompileCheck {
+                language: "python".to_string(),
+                extension: "py".to_string(),
+                command: "python3 -m py_compile {file}".to_string(),
+            },
+        ]
+    }
+
+    /// Pick the check for a review: the explicit language if given, otherwise
+    /// the first configured language mentioned in the user's request.
+    pub fn find<'a>(checks: &'a [Self], language: Option<&str>, user_input: &str) -> Option<&'a Self> {
+        match language {
+            Some(language) => checks.iter().find(|check| check.language.eq_ignore_ascii_case(language.trim())),
+            None => {
+                let words: Vec<String> = user_input
+                    .split(|c: char| !c.is_alphanumeric() && c != '+' && c != '#')
+                    .map(str::to_lowercase)
+                    .collect();
+                checks
+                    .iter()
+                    .find(|check| words.iter().any(|word| *word == check.language.to_lowercase()))
+            }
+        }
+    }
+
+    /// Write the code to a temporary file and run the command on it
+    pub async fn run(&self, code: &str) -> Result<CompileOutcome, CodeReviewError> {
+        let dir = std::env::temp_dir().join(format!("rig-magi-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).map_err(|e| {
+            CodeReviewError::ConfigError(format!("Failed to create {}: {}", dir.display(), e))
+        })?;
+
+        let result = self.run_in(&dir, code).await;
+
+        // Best effort; a leftover temp dir is harmless
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+
+    async fn run_in(&self, dir: &Path, code: &str) -> Result<CompileOutcome, CodeReviewError> {
+        let file = dir.join(format!("snippet.{}", self.extension));
+        std::fs::write(&file, code).map_err(|e| {
+            CodeReviewError::ConfigError(format!("Failed to write {}: {}", file.display(), e))
+        })?;
+
+        let args: Vec<String> = self
+            .command
+            .split_whitespace()
+            .map(|arg| {
+                arg.replace("{file}", &file.display().to_string())
+                    .replace("{dir}", &dir.display().to_string())
+            })
+            .collect();
+        let (program, args) = args.split_first().ok_or_else(|| {
+            CodeReviewError::ConfigError(format!("Empty compile check command for {}", self.language))
+        })?;
+
+        let output = tokio::time::timeout(CHECK_TIMEOUT, Command::new(program).args(args).current_dir(dir).output())
+            .await
+            .map_err(|_| {
+                CodeReviewError::ConfigError(format!("Compile check for {} timed out", self.language))
+            })?
+            .map_err(|e| {
+                CodeReviewError::ConfigError(format!("Failed to run {}: {}", program, e))
+            })?;
+
+        if output.status.success() {
+            return Ok(CompileOutcome::Passed);
+        }
+
+        // Show the diagnostics against the name the model knows, not the temp path
+        let mut diagnostics = String::from_utf8_lossy(&output.stderr).into_owned();
+        diagnostics.push_str(&String::from_utf8_lossy(&output.stdout));
+        let diagnostics = diagnostics
+            .replace(&file.display().to_string(), &format!("snippet.{}", self.extension))
+            .trim()
+            .to_string();
+
+        Ok(CompileOutcome::Failed(diagnostics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A stand-in compiler that accepts code containing `fn`
+    fn stub() -> CompileCheck {
+        CompileCheck {
+            language: "rust".to_string(),
+            extension: "rs".to_string(),
+            command: "grep -q fn {file}".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn the_command_decides_the_outcome() {
+        assert!(matches!(stub().run("fn main() {}").await, Ok(CompileOutcome::Passed)));
+        assert!(matches!(stub().run("main() {}").await, Ok(CompileOutcome::Failed(_))));
+    }
+
+    #[tokio::test]
+    async fn a_missing_compiler_is_an_error() {
+        let check = CompileCheck {
+            command: "rig-magi-no-such-compiler {file}".to_string(),
+            ..stub()
+        };
+
+        assert!(matches!(check.run("fn main() {}").await, Err(CodeReviewError::ConfigError(_))));
+    }
+
+    #[test]
+    fn checks_are_found_by_language_or_request() {
+        let checks = CompileCheck::defaults();
+
+        assert_eq!(CompileCheck::find(&checks, Some(" Python "), "").map(|c| c.extension.as_str()), Some("py"));
+        assert_eq!(CompileCheck::find(&checks, None, "Write Rust code").map(|c| c.extension.as_str()), Some("rs"));
+        assert!(CompileCheck::find(&checks, None, "Write a rustacean poem").is_none());
+        assert!(CompileCheck::find(&checks, Some("go"), "in rust").is_none());
+    }
+}
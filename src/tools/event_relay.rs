@@ -0,0 +1,140 @@
+use futures_util::{stream::SplitSink, SinkExt, StreamExt};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+
+use super::events::ReviewEvent;
+use super::pool::WsStream;
+use super::redact;
+
+// Events held while the endpoint is unreachable; the oldest go first
+const RELAY_BACKLOG: usize = 256;
+
+// Wait between connection attempts after a failure
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+// A handshake slower than this counts as a failed attempt
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Forwards review events to a WebSocket endpoint of the caller's, e.g. a
+/// dashboard, as JSON text frames (the same lines `--events` prints).
+///
+/// Sending never waits on the endpoint: events are queued to a background
+/// task, which connects on the first event, reconnects after failures and
+/// holds up to `RELAY_BACKLOG` events while disconnected. The review is
+/// never affected by the relay.
+#[derive(Debug, Clone)]
+pub struct EventRelay {
+    sender: mpsc::UnboundedSender<ReviewEvent>,
+}
+
+impl EventRelay {
+    /// Start the relay task for `url`. It stops once every clone is dropped.
+    pub fn spawn(url: String) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(relay(url, receiver));
+        Self { sender }
+    }
+
+    pub fn send(&self, event: ReviewEvent) {
+        let _ = self.sender.send(event);
+    }
+}
+
+async fn relay(url: String, mut events: mpsc::UnboundedReceiver<ReviewEvent>) {
+    let shown_url = redact::url(&url);
+    let mut backlog: VecDeque<String> = VecDeque::new();
+    let mut connection: Option<SplitSink<WsStream, Message>> = None;
+    let mut retry_at = Instant::now();
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let Some(event) = event else {
+                    break;
+                };
+                let Ok(line) = serde_json::to_string(&event) else {
+                    continue;
+                };
+                if backlog.len() == RELAY_BACKLOG {
+                    backlog.pop_front();
+                    tracing::debug!(target: "rig-magi", "Event relay backlog full, dropped the oldest event");
+                }
+                backlog.push_back(line);
+            }
+            // Retry a held backlog without waiting for the next event
+            _ = tokio::time::sleep_until(retry_at), if connection.is_none() && !backlog.is_empty() => {}
+        }
+
+        if connection.is_none() && Instant::now() >= retry_at {
+            match tokio::time::timeout(CONNECT_TIMEOUT, connect_async(url.as_str())).await {
+                Ok(Ok((ws_stream, _))) => {
+                    tracing::info!(target: "rig-magi", "Relaying review events to {}", shown_url);
+                    let (write, mut read) = ws_stream.split();
+                    // Nothing is expected back; reading keeps pings answered
+                    tokio::spawn(async move { while let Some(Ok(_)) = read.next().await {} });
+                    connection = Some(write);
+                }
+                Ok(Err(e)) => {
+                    tracing::warn!(target: "rig-magi", "Failed to connect to event relay {}: {}", shown_url, redact::params(&e.to_string()));
+                    retry_at = Instant::now() + RECONNECT_DELAY;
+                }
+                Err(_) => {
+                    tracing::warn!(target: "rig-magi", "Timed out connecting to event relay {}", shown_url);
+                    retry_at = Instant::now() + RECONNECT_DELAY;
+                }
+            }
+        }
+
+        if let Some(write) = connection.as_mut() {
+            while let Some(line) = backlog.front() {
+                if let Err(e) = write.send(Message::Text(line.clone())).await {
+                    tracing::warn!(target: "rig-magi", "Event relay {} disconnected: {}", shown_url, e);
+                    connection = None;
+                    retry_at = Instant::now() + RECONNECT_DELAY;
+                    break;
+                }
+                backlog.pop_front();
+            }
+        }
+    }
+
+    if let Some(mut write) = connection {
+        let _ = write.close().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MockGateway;
+
+    fn chunk(content: &str) -> ReviewEvent {
+        ReviewEvent::Chunk {
+            request_id: "req-1".to_string(),
+            agent: "melchior".to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn events_are_forwarded_in_order() {
+        let sink = MockGateway::start(|_, _| Vec::new()).await;
+        let relay = EventRelay::spawn(sink.url());
+
+        relay.send(chunk("POSITIVE"));
+        relay.send(chunk(", well tested"));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while sink.received().len() < 2 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let received = sink.received();
+        let contents: Vec<&str> = received.iter().filter_map(|event| event["content"].as_str()).collect();
+        assert_eq!(contents, ["POSITIVE", ", well tested"]);
+        assert_eq!(received[0]["event"], "chunk");
+        assert_eq!(sink.uris().len(), 1);
+    }
+}
@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use super::code_review::MAGIDecision;
+
+/// Progress of a review, emitted as frames arrive from the gateway
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ReviewEvent {
+    /// A piece of an agent's streamed response
+    Chunk {
+        request_id: String,
+        agent: String,
+        content: String,
+    },
+    AgentCompleted {
+        request_id: String,
+        agent: String,
+        decision: Option<MAGIDecision>,
+    },
+    AgentErrored {
+        request_id: String,
+        agent: String,
+        message: String,
+    },
+    /// Emitted with each heartbeat while agents are pending. `eta_ms` is the
+    /// expected time left, from the agents' average latency in earlier
+    /// reviews; omitted until every pending agent has one.
+    Progress {
+        request_id: String,
+        pending: Vec<String>,
+        elapsed_ms: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        eta_ms: Option<u64>,
+    },
+    /// The panel's final result
+    Decided {
+        request_id: String,
+        result: String,
+        passed: bool,
+    },
+}
+
+impl ReviewEvent {
+    // Chunks are intermediate; everything else is needed to follow the outcome
+    fn is_chunk(&self) -> bool {
+        matches!(self, ReviewEvent::Chunk { .. })
+    }
+}
+
+/// What to do when the event buffer is full because the consumer is slow
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backpressure {
+    /// Drop chunk events, but wait for room for completion and decision events
+    #[default]
+    DropChunks,
+    /// Wait for room for every event, pausing the review until the consumer catches up
+    Block,
+}
+
+impl std::str::FromStr for Backpressure {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "drop_chunks" | "drop" => Ok(Backpressure::DropChunks),
+            "block" => Ok(Backpressure::Block),
+            other => Err(format!("Unknown event backpressure mode: {}", other)),
+        }
+    }
+}
+
+/// Sending half of a bounded review event channel
+#[derive(Debug, Clone)]
+pub struct EventSink {
+    sender: mpsc::Sender<ReviewEvent>,
+    backpressure: Backpressure,
+}
+
+/// Create a review event channel buffering at most `capacity` events
+pub fn channel(capacity: usize, backpressure: Backpressure) -> (EventSink, mpsc::Receiver<ReviewEvent>) {
+    let (sender, receiver) = mpsc::channel(capacity.max(1));
+    (EventSink { sender, backpressure }, receiver)
+}
+
+impl EventSink {
+    /// Send an event, applying the backpressure mode when the buffer is full.
+    /// A closed receiver is ignored; the review doesn't depend on its consumer.
+    pub async fn emit(&self, event: ReviewEvent) {
+        if self.backpressure == Backpressure::DropChunks && event.is_chunk() {
+            if let Err(TrySendError::Full(_)) = self.sender.try_send(event) {
+                tracing::debug!(target: "rig-magi", "Event buffer full, dropped a chunk");
+            }
+            return;
+        }
+
+        let _ = self.sender.send(event).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn chunk(content: &str) -> ReviewEvent {
+        ReviewEvent::Chunk {
+            request_id: "request".to_string(),
+            agent: "melchior".to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    fn completed() -> ReviewEvent {
+        ReviewEvent::AgentCompleted {
+            request_id: "request".to_string(),
+            agent: "melchior".to_string(),
+            decision: Some(MAGIDecision::POSITIVE),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_slow_consumer_loses_chunks_but_not_completions() {
+        let (sink, mut receiver) = channel(1, Backpressure::DropChunks);
+
+        sink.emit(chunk("first")).await;
+        sink.emit(chunk("second")).await;
+        let waiting = tokio::time::timeout(Duration::from_millis(50), sink.emit(completed())).await;
+        assert!(waiting.is_err());
+
+        assert!(matches!(receiver.recv().await, Some(ReviewEvent::Chunk { content, .. }) if content == "first"));
+        sink.emit(completed()).await;
+        assert!(matches!(receiver.recv().await, Some(ReviewEvent::AgentCompleted { .. })));
+    }
+
+    #[tokio::test]
+    async fn blocking_waits_for_room_for_every_event() {
+        let (sink, mut receiver) = channel(1, Backpressure::Block);
+
+        sink.emit(chunk("first")).await;
+        let waiting = tokio::time::timeout(Duration::from_millis(50), sink.emit(chunk("second"))).await;
+        assert!(waiting.is_err());
+
+        receiver.recv().await;
+        sink.emit(chunk("second")).await;
+        assert!(matches!(receiver.recv().await, Some(ReviewEvent::Chunk { content, .. }) if content == "second"));
+    }
+
+    #[tokio::test]
+    async fn a_closed_receiver_is_ignored() {
+        let (sink, receiver) = channel(1, Backpressure::Block);
+        drop(receiver);
+
+        sink.emit(completed()).await;
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Weight of the newest sample in the moving average
+const SMOOTHING: f64 = 0.3;
+
+/// How long each agent takes to complete a review, as an exponential moving
+/// average over the reviews so far. Shared by every review of a
+/// `CodeReviewTool`; it starts empty each run.
+#[derive(Debug, Default)]
+pub struct LatencyTracker {
+    averages: Mutex<HashMap<String, Duration>>,
+}
+
+impl LatencyTracker {
+    /// Fold in the time an agent took, from the request being sent to its
+    /// completion
+    pub fn record(&self, agent: &str, latency: Duration) {
+        let mut averages = self.averages.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let average = match averages.get(agent) {
+            Some(average) => average.mul_f64(1.0 - SMOOTHING) + latency.mul_f64(SMOOTHING),
+            None => latency,
+        };
+        averages.insert(agent.to_string(), average);
+    }
+
+    pub fn average(&self, agent: &str) -> Option<Duration> {
+        self.averages
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(agent)
+            .copied()
+    }
+
+    /// Expected time until the slowest of the pending agents finishes, given
+    /// how long the review has run. An agent past its average counts as about
+    /// to finish. `None` if any of them has no history to go on, or none are
+    /// pending.
+    pub fn eta<'a>(&self, pending: impl IntoIterator<Item = &'a str>, elapsed: Duration) -> Option<Duration> {
+        let mut eta: Option<Duration> = None;
+        for agent in pending {
+            let remaining = self.average(agent)?.saturating_sub(elapsed);
+            eta = Some(eta.map_or(remaining, |eta| eta.max(remaining)));
+        }
+        eta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn latency_is_a_moving_average_weighting_the_latest_sample() {
+        let latency = LatencyTracker::default();
+
+        latency.record("melchior", secs(10));
+        latency.record("melchior", secs(20));
+
+        assert_eq!(latency.average("melchior"), Some(secs(13)));
+        assert_eq!(latency.average("casper"), None);
+    }
+
+    #[test]
+    fn the_eta_is_the_longest_time_left_among_pending_agents() {
+        let latency = LatencyTracker::default();
+        latency.record("melchior", secs(4));
+        latency.record("balthasar", secs(12));
+
+        assert_eq!(latency.eta(["melchior", "balthasar"], secs(5)), Some(secs(7)));
+        assert_eq!(latency.eta(["melchior"], secs(5)), Some(Duration::ZERO));
+        assert_eq!(latency.eta(["melchior", "casper"], secs(1)), None);
+        assert_eq!(latency.eta(std::iter::empty(), secs(1)), None);
+    }
+}
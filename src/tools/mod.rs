@@ -1 +1,14 @@
+#[cfg(feature = "chaos")]
+pub mod chaos;
 pub mod code_review;
+pub mod compare;
+pub mod compile_check;
+pub mod event_relay;
+pub mod events;
+pub mod latency;
+pub mod multi_gateway;
+pub mod pool;
+pub mod redact;
+pub mod report;
+pub mod review_cache;
+pub mod verdict;
@@ -0,0 +1,257 @@
+use futures_util::future::join_all;
+use rig::{
+    completion::ToolDefinition,
+    tool::Tool,
+};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use super::code_review::{tool_definition, CodeReviewArgs, CodeReviewError, CodeReviewOutput, CodeReviewTool};
+use super::redact;
+use crate::config::CodeReviewConfig;
+
+/// How the verdicts of independent gateways are combined
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GatewayConsensus {
+    /// Every gateway must pass the code
+    All,
+    /// A single passing gateway is enough
+    Any,
+    /// More than half of the gateways must pass the code
+    Majority,
+}
+
+impl GatewayConsensus {
+    pub fn combine(&self, passed: usize, total: usize) -> bool {
+        match self {
+            GatewayConsensus::All => total > 0 && passed == total,
+            GatewayConsensus::Any => passed > 0,
+            GatewayConsensus::Majority => passed > total / 2,
+        }
+    }
+}
+
+impl FromStr for GatewayConsensus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "all" | "and" => Ok(GatewayConsensus::All),
+            "any" | "or" => Ok(GatewayConsensus::Any),
+            "majority" => Ok(GatewayConsensus::Majority),
+            other => Err(format!("Unknown gateway consensus: {}", other)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GatewayResult {
+    server_url: String,
+    passed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<CodeReviewOutput>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct MultiGatewayOutput {
+    gateways: Vec<GatewayResult>,
+    consensus: GatewayConsensus,
+    result: String,
+    passed: bool,
+    /// Rejected, and at least one gateway's rejection was transient
+    retryable: bool,
+    code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<String>,
+}
+
+/// Sends the same review to several MAGI gateways and combines their verdicts.
+///
+/// Exposes the same `code_review` tool interface as `CodeReviewTool`, so it can
+/// be registered on the agent in its place.
+pub struct MultiGatewayReviewer {
+    reviewers: Vec<(String, CodeReviewTool)>,
+    consensus: GatewayConsensus,
+}
+
+impl MultiGatewayReviewer {
+    pub fn new(config: CodeReviewConfig, server_urls: Vec<String>, consensus: GatewayConsensus) -> Self {
+        let reviewers = server_urls
+            .into_iter()
+            .map(|server_url| {
+                let tool = CodeReviewTool::with_config(CodeReviewConfig {
+                    server_url: server_url.clone(),
+                    ..config.clone()
+                });
+                (server_url, tool)
+            })
+            .collect();
+
+        Self { reviewers, consensus }
+    }
+
+    /// Build from `CODE_REVIEW_SERVER_URLS` (comma-separated) and
+    /// `CODE_REVIEW_GATEWAY_CONSENSUS` (`all`, `any` or `majority`, default `all`),
+    /// sharing everything but the URL with `config`.
+    /// Returns `None` when no gateway list is configured.
+    pub fn from_env(config: &CodeReviewConfig) -> Option<Self> {
+        let server_urls: Vec<String> = std::env::var("CODE_REVIEW_SERVER_URLS")
+            .ok()?
+            .split(',')
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty())
+            .collect();
+
+        if server_urls.is_empty() {
+            return None;
+        }
+
+        let consensus = match std::env::var("CODE_REVIEW_GATEWAY_CONSENSUS") {
+            Ok(value) => value.parse().unwrap_or_else(|e| {
+                tracing::warn!(target: "rig-magi", "{}, falling back to all", e);
+                GatewayConsensus::All
+            }),
+            Err(_) => GatewayConsensus::All,
+        };
+
+        Some(Self::new(config.clone(), server_urls, consensus))
+    }
+}
+
+impl Tool for MultiGatewayReviewer {
+    const NAME: &'static str = CodeReviewTool::NAME;
+    type Error = CodeReviewError;
+    type Args = CodeReviewArgs;
+    type Output = MultiGatewayOutput;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        tool_definition()
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        // Review against every gateway concurrently
+        let results = join_all(
+            self.reviewers
+                .iter()
+                .map(|(_, reviewer)| reviewer.call(args.clone())),
+        )
+        .await;
+
+        let mut gateways = Vec::new();
+        let mut errors = Vec::new();
+
+        for ((server_url, _), result) in self.reviewers.iter().zip(results) {
+            let server_url = redact::url(server_url);
+            match result {
+                Ok(output) => {
+                    tracing::info!(target: "rig-magi",
+                        "Gateway {} passed: {}",
+                        server_url,
+                        output.passed()
+                    );
+                    gateways.push(GatewayResult {
+                        server_url,
+                        passed: output.passed(),
+                        output: Some(output),
+                        error: None,
+                    });
+                }
+                Err(e) => {
+                    tracing::info!(target: "rig-magi",
+                        "Gateway {} failed: {}",
+                        server_url,
+                        e
+                    );
+                    errors.push(format!("{}: {}", server_url, e));
+                    gateways.push(GatewayResult {
+                        server_url,
+                        passed: false,
+                        output: None,
+                        error: Some(e.to_string()),
+                    });
+                }
+            }
+        }
+
+        // Nothing to combine if no gateway produced a verdict
+        if errors.len() == gateways.len() {
+            return Err(CodeReviewError::ConnectionError(format!(
+                "All review gateways failed: {}",
+                errors.join("; ")
+            )));
+        }
+
+        let passed_count = gateways.iter().filter(|gateway| gateway.passed).count();
+        let passed = self.consensus.combine(passed_count, gateways.len());
+        let retryable = !passed
+            && gateways
+                .iter()
+                .any(|gateway| gateway.output.as_ref().map_or(false, CodeReviewOutput::retryable));
+
+        Ok(MultiGatewayOutput {
+            gateways,
+            consensus: self.consensus,
+            result: if passed { "POSITIVE" } else { "NEGATIVE" }.to_string(),
+            passed,
+            retryable,
+            code: args.submission(),
+            correlation_id: args.correlation_id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, MockGateway};
+
+    // Review once against an approving and a rejecting gateway
+    async fn review_split(consensus: GatewayConsensus) -> MultiGatewayOutput {
+        let approving = MockGateway::voting(&["POSITIVE", "POSITIVE", "POSITIVE"]).await;
+        let rejecting = MockGateway::voting(&["NEGATIVE", "NEGATIVE", "NEGATIVE"]).await;
+        let reviewer = MultiGatewayReviewer::new(approving.config(), vec![approving.url(), rejecting.url()], consensus);
+
+        reviewer.call(test_util::args("fn add() {}")).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn differing_gateways_are_combined_by_consensus() {
+        let all = review_split(GatewayConsensus::All).await;
+        let passes: Vec<bool> = all.gateways.iter().map(|gateway| gateway.passed).collect();
+        assert_eq!(passes, [true, false]);
+        assert!(!all.passed);
+        assert_eq!(all.result, "NEGATIVE");
+
+        assert!(review_split(GatewayConsensus::Any).await.passed);
+        assert!(!review_split(GatewayConsensus::Majority).await.passed);
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_gateway_counts_as_a_rejection() {
+        let approving = MockGateway::voting(&["POSITIVE", "POSITIVE", "POSITIVE"]).await;
+        let unreachable = "ws://127.0.0.1:1/review".to_string();
+        let config = CodeReviewConfig {
+            reconnect_attempts: 0,
+            ..approving.config()
+        };
+        let reviewer = MultiGatewayReviewer::new(config, vec![approving.url(), unreachable], GatewayConsensus::Any);
+
+        let output = reviewer.call(test_util::args("fn add() {}")).await.unwrap();
+
+        assert!(output.passed);
+        assert!(output.gateways[1].error.is_some());
+    }
+
+    #[test]
+    fn consensus_parses_its_aliases() {
+        assert_eq!("and".parse(), Ok(GatewayConsensus::All));
+        assert_eq!(" OR ".parse(), Ok(GatewayConsensus::Any));
+        assert_eq!("majority".parse(), Ok(GatewayConsensus::Majority));
+        assert!("most".parse::<GatewayConsensus>().is_err());
+        assert!(!GatewayConsensus::All.combine(0, 0));
+        assert!(GatewayConsensus::Majority.combine(2, 3));
+    }
+}
@@ -0,0 +1,149 @@
+use futures_util::{FutureExt, SinkExt, StreamExt};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_tungstenite::{tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+
+use crate::config::CodeReviewConfig;
+use super::code_review::{connect, CodeReviewError};
+
+pub type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Idle connections older than this are replaced rather than reused, in case
+// the gateway times out idle sockets
+const MAX_IDLE: Duration = Duration::from_secs(240);
+
+/// Authenticated gateway connections opened ahead of time, so a review can
+/// skip the TCP, TLS and WebSocket handshakes. Each connection is used for
+/// one review; the pool opens a replacement in the background after each
+/// checkout.
+pub struct ConnectionPool {
+    config: CodeReviewConfig,
+    size: usize,
+    idle: Mutex<Vec<(WsStream, Instant)>>,
+    // Set while a refill task runs, so checkouts don't start competing ones
+    warming: AtomicBool,
+}
+
+impl ConnectionPool {
+    /// Create a pool of `size` connections and start opening them
+    pub fn new(config: CodeReviewConfig, size: usize) -> Arc<Self> {
+        let pool = Arc::new(Self {
+            config,
+            size,
+            idle: Mutex::new(Vec::new()),
+            warming: AtomicBool::new(false),
+        });
+        pool.warm();
+        pool
+    }
+
+    /// A ready connection if one is still usable, otherwise a new one
+    pub async fn get(self: &Arc<Self>) -> Result<WsStream, CodeReviewError> {
+        let ready = {
+            let mut idle = self.idle.lock().await;
+            let mut ready = None;
+            while let Some((stream, opened)) = idle.pop() {
+                if opened.elapsed() < MAX_IDLE {
+                    if let Some(stream) = usable(stream).await {
+                        ready = Some(stream);
+                        break;
+                    }
+                }
+                tracing::debug!(target: "rig-magi", "Discarding stale pooled connection");
+            }
+            ready
+        };
+
+        // Refill in the background, whether or not this checkout hit the pool
+        self.warm();
+
+        match ready {
+            Some(stream) => {
+                tracing::debug!(target: "rig-magi", "Reusing pooled connection");
+                Ok(stream)
+            }
+            None => connect(&self.config).await,
+        }
+    }
+
+    // Open connections until the pool is full again. Outside a runtime there
+    // is nothing to spawn on, and reviews just connect on demand.
+    fn warm(self: &Arc<Self>) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        if self.warming.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let pool = Arc::clone(self);
+        runtime.spawn(async move {
+            loop {
+                if pool.idle.lock().await.len() >= pool.size {
+                    break;
+                }
+                match connect(&pool.config).await {
+                    Ok(stream) => pool.idle.lock().await.push((stream, Instant::now())),
+                    Err(e) => {
+                        tracing::warn!(target: "rig-magi", "Failed to pre-warm connection: {}", e);
+                        break;
+                    }
+                }
+            }
+            pool.warming.store(false, Ordering::Release);
+        });
+    }
+}
+
+// Check that an idle connection is still open: anything the server has sent
+// while it sat in the pool must not be a close frame or an error, and a ping
+// must still go through
+async fn usable(mut stream: WsStream) -> Option<WsStream> {
+    while let Some(frame) = stream.next().now_or_never() {
+        match frame {
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return None,
+            Some(Ok(_)) => continue,
+        }
+    }
+
+    stream.send(Message::Ping(Vec::new())).await.ok()?;
+    Some(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockGateway, Reply};
+    use serde_json::json;
+
+    // The index of the gateway connection a stream is, as the gateway
+    // numbers them in the order they were opened
+    async fn connection_index(stream: &mut WsStream) -> u64 {
+        stream.send(Message::Text("{}".to_string())).await.unwrap();
+        loop {
+            if let Some(Ok(Message::Text(text))) = stream.next().await {
+                let reply: serde_json::Value = serde_json::from_str(&text).unwrap();
+                return reply["connection"].as_u64().unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn a_prewarmed_connection_is_handed_out_and_replaced() {
+        let gateway = MockGateway::start(|connection, _| vec![Reply::Frame(json!({ "connection": connection }))]).await;
+        let pool = ConnectionPool::new(gateway.config(), 1);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut first = pool.get().await.unwrap();
+        assert_eq!(connection_index(&mut first).await, 0);
+
+        // The checkout was refilled in the background
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(pool.idle.lock().await.len(), 1);
+        let mut second = pool.get().await.unwrap();
+        assert_eq!(connection_index(&mut second).await, 1);
+    }
+}
@@ -0,0 +1,91 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::code_review::CodeReviewOutput;
+use super::redact;
+
+/// How long an agent took, from the start of the review to its last frame
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentTiming {
+    pub agent: String,
+    /// `None` when the agent sent nothing, e.g. it was still pending or the
+    /// review was decided locally
+    pub duration_ms: Option<u64>,
+}
+
+/// A review's result bundled with its timing and metadata; the form to hand
+/// to consumers of a review
+#[derive(Serialize, Deserialize)]
+pub struct ReviewReport {
+    pub output: CodeReviewOutput,
+    pub started_at: DateTime<Utc>,
+    /// Wall time of the whole review, including any re-reviews
+    pub duration_ms: u64,
+    /// One entry per panel agent, in panel order
+    pub agent_timings: Vec<AgentTiming>,
+    /// Generate-and-review rounds that led to this result; 1 for a
+    /// standalone review
+    pub iterations: usize,
+    /// rig-magi version that produced the report
+    pub version: String,
+    /// Gateway URL the review was sent to, with credentials masked
+    pub gateway: String,
+}
+
+impl ReviewReport {
+    pub(crate) fn new(output: CodeReviewOutput, started_at: DateTime<Utc>, gateway: &str) -> Self {
+        let elapsed_ms = |at: DateTime<Utc>| (at - started_at).num_milliseconds().max(0) as u64;
+
+        let agent_timings = output
+            .magi_state()
+            .agents
+            .iter()
+            .map(|agent| AgentTiming {
+                agent: agent.name.clone(),
+                duration_ms: agent.messages.last().map(|message| elapsed_ms(message.timestamp)),
+            })
+            .collect();
+
+        Self {
+            output,
+            started_at,
+            duration_ms: elapsed_ms(Utc::now()),
+            agent_timings,
+            iterations: 1,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            gateway: redact::url(gateway),
+        }
+    }
+
+    /// Record how many rounds of a multi-turn prompt led to this review,
+    /// e.g. `MultiTurnAgent::turn_rounds()`
+    pub fn with_iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, MockGateway};
+    use crate::tools::code_review::CodeReviewTool;
+
+    #[tokio::test]
+    async fn a_report_survives_a_json_round_trip() {
+        let gateway = MockGateway::voting(&["POSITIVE", "POSITIVE", "NEGATIVE: no tests"]).await;
+        let report = CodeReviewTool::with_config(gateway.config())
+            .review_report(test_util::args("fn add() {}"))
+            .await
+            .unwrap()
+            .with_iterations(2);
+
+        let json = serde_json::to_value(&report).unwrap();
+        let parsed: ReviewReport = serde_json::from_value(json.clone()).unwrap();
+
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), json);
+        assert_eq!(parsed.iterations, 2);
+        assert_eq!(parsed.agent_timings.len(), 3);
+        assert!(parsed.output.passed());
+    }
+}
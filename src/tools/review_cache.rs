@@ -0,0 +1,203 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::config::CodeReviewConfig;
+use crate::session::write_atomically;
+use super::code_review::{CodeReviewArgs, CodeReviewOutput};
+
+// Bumped when the key or the stored output changes shape, so old entries
+// are missed instead of misread
+const CACHE_FORMAT: &str = "2";
+
+/// Review outputs kept on disk across runs, one `<key>.json` file per
+/// submission. The key covers the request, the code, the panel and the
+/// review policy, so changing any of them misses the cache rather than
+/// reusing a verdict reached under different rules.
+///
+/// The cache never fails a review: an unreadable or stale entry is a miss,
+/// and a failed write is logged.
+#[derive(Debug, Clone)]
+pub struct ReviewCache {
+    dir: PathBuf,
+    ttl: Option<Duration>,
+    max_entries: usize,
+}
+
+impl ReviewCache {
+    /// The cache configured by `review_cache_dir`, if any
+    pub fn from_config(config: &CodeReviewConfig) -> Option<Self> {
+        let dir = config.review_cache_dir.clone()?;
+        Some(Self {
+            dir,
+            ttl: config.review_cache_ttl,
+            max_entries: config.review_cache_max_entries,
+        })
+    }
+
+    /// The content hash a submission is stored under
+    pub fn key(config: &CodeReviewConfig, args: &CodeReviewArgs) -> String {
+        let mut hasher = Sha256::new();
+        let mut part = |value: &dyn fmt::Debug| {
+            hasher.update(format!("{:?}", value).as_bytes());
+            hasher.update([0]);
+        };
+
+        part(&CACHE_FORMAT);
+        part(&args.user_input);
+        part(&args.submission());
+        part(&args.language);
+        part(&args.rubric.as_ref().or(config.rubric.as_ref()));
+        part(&args.tests.as_ref().or(config.tests.as_ref()));
+
+        // The panel
+        part(&config.server_url);
+        for agent in &config.agents {
+            part(&(&agent.name, &agent.id));
+        }
+
+        // Everything that turns the panel's answers into a verdict
+        part(&config.required_positive());
+        part(&config.strict_verdict);
+        part(&config.min_approval_content);
+        part(&config.weak_approval_mode);
+        part(&config.minimum_margin_mode);
+        part(&config.close_call_margin);
+        part(&config.required_approvers);
+        part(&config.weights.iter().collect::<BTreeMap<_, _>>());
+        part(&config.security_veto);
+        part(&config.verdict_markers);
+        part(&config.wait_for_all);
+        part(&config.rereview_on_split);
+        part(&config.compile_check);
+        part(&config.allowed_languages);
+        part(&config.verdict_overrides.iter().collect::<BTreeMap<_, _>>());
+
+        hex::encode(hasher.finalize())
+    }
+
+    /// The stored output for `key`, unless it is missing, unreadable or older
+    /// than the TTL
+    pub fn get(&self, key: &str) -> Option<CodeReviewOutput> {
+        let path = self.entry_path(key);
+        let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok()?;
+        if self.expired(modified) {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+
+        let contents = fs::read(&path).ok()?;
+        match serde_json::from_slice(&contents) {
+            Ok(output) => Some(output),
+            Err(e) => {
+                tracing::warn!(target: "rig-magi", "Ignoring unreadable review cache entry {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Store an output under `key`, then evict the oldest entries beyond
+    /// `max_entries`
+    pub fn put(&self, key: &str, output: &CodeReviewOutput) {
+        if let Err(e) = self.write(key, output) {
+            tracing::warn!(target: "rig-magi", "Failed to cache review in {}: {}", self.dir.display(), e);
+            return;
+        }
+        self.evict();
+    }
+
+    fn write(&self, key: &str, output: &CodeReviewOutput) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        let json = serde_json::to_vec(output).map_err(|e| e.to_string())?;
+        write_atomically(&self.entry_path(key), &json).map_err(|e| e.to_string())
+    }
+
+    // Drop expired entries, then the oldest until `max_entries` remain
+    fn evict(&self) {
+        let Ok(dir) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut entries: Vec<(SystemTime, PathBuf)> = dir
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+            .filter_map(|path| Some((fs::metadata(&path).and_then(|m| m.modified()).ok()?, path)))
+            .collect();
+
+        entries.retain(|(modified, path)| {
+            let expired = self.expired(*modified);
+            if expired {
+                let _ = fs::remove_file(path);
+            }
+            !expired
+        });
+
+        if self.max_entries == 0 || entries.len() <= self.max_entries {
+            return;
+        }
+        entries.sort();
+        let excess = entries.len() - self.max_entries;
+        for (_, path) in &entries[..excess] {
+            let _ = fs::remove_file(path);
+        }
+        tracing::debug!(target: "rig-magi", evicted = excess, "Evicted old review cache entries");
+    }
+
+    fn expired(&self, modified: SystemTime) -> bool {
+        self.ttl.is_some_and(|ttl| modified.elapsed().is_ok_and(|age| age > ttl))
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ReviewerAgent;
+    use crate::test_util;
+
+    #[test]
+    fn the_key_changes_with_the_panel_and_the_policy() {
+        let config = CodeReviewConfig::default();
+        let args = test_util::args("fn add() {}");
+        let mut renamed = config.clone();
+        renamed.agents[2] = ReviewerAgent {
+            name: "security".to_string(),
+            id: "security-id".to_string(),
+        };
+        let unanimous = CodeReviewConfig {
+            quorum: Some(3),
+            ..config.clone()
+        };
+        let vetoing = CodeReviewConfig {
+            security_veto: true,
+            ..config.clone()
+        };
+
+        let key = ReviewCache::key(&config, &args);
+        assert_eq!(key, ReviewCache::key(&config.clone(), &args.clone()));
+        assert_ne!(key, ReviewCache::key(&renamed, &args));
+        assert_ne!(key, ReviewCache::key(&unanimous, &args));
+        assert_ne!(key, ReviewCache::key(&vetoing, &args));
+        assert_ne!(key, ReviewCache::key(&config, &test_util::args("fn sub() {}")));
+    }
+
+    #[test]
+    fn a_missing_or_unreadable_entry_is_a_miss() {
+        let cache = ReviewCache::from_config(&CodeReviewConfig {
+            review_cache_dir: Some(test_util::temp_dir()),
+            ..CodeReviewConfig::default()
+        })
+        .unwrap();
+
+        fs::write(cache.entry_path("corrupt"), "{").unwrap();
+
+        assert!(cache.get("missing").is_none());
+        assert!(cache.get("corrupt").is_none());
+    }
+}
@@ -0,0 +1,400 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::code_review::MAGIDecision;
+
+// Phrases that usually introduce a reviewer's reasoning
+const REASON_MARKERS: [&str; 6] = ["because", "since", "reason", "due to", "as it", "strength"];
+
+// Longest evidence sentence kept before truncating
+const MAX_EVIDENCE_LEN: usize = 200;
+
+// Keys a reviewer may use for its verdict, in a JSON object or a `KEY: VALUE` line
+const VERDICT_KEYS: [&str; 2] = ["decision", "verdict"];
+
+// Words that turn a following marker around, e.g. "not a POSITIVE result"
+const NEGATIONS: [&str; 10] = ["not", "no", "never", "nor", "without", "isn't", "isnt", "wasn't", "cannot", "can't"];
+
+// Words before a marker searched for a negation
+const NEGATION_WINDOW: usize = 3;
+
+/// The tokens that mean approve and reject, e.g. `POSITIVE`/`NEGATIVE` (the
+/// default) or `APPROVE`/`REJECT`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VerdictMarkers {
+    pub positive: Vec<String>,
+    pub negative: Vec<String>,
+}
+
+impl Default for VerdictMarkers {
+    fn default() -> Self {
+        Self {
+            positive: vec!["POSITIVE".to_string()],
+            negative: vec!["NEGATIVE".to_string()],
+        }
+    }
+}
+
+impl VerdictMarkers {
+    /// Both sets must be non-empty and no token may appear in both
+    pub fn validate(&self) -> Result<(), String> {
+        if self.positive.is_empty() || self.negative.is_empty() {
+            return Err("Positive and negative verdict markers must both be set".to_string());
+        }
+
+        if let Some(token) = self
+            .positive
+            .iter()
+            .find(|token| self.negative.iter().any(|other| other.eq_ignore_ascii_case(token)))
+        {
+            return Err(format!("Verdict marker {} is both positive and negative", token));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the content mentions a positive marker anywhere
+    pub fn mentions_positive(&self, content: &str) -> bool {
+        self.positive.iter().any(|token| content.contains(token.as_str()))
+    }
+
+    /// Match a whole verdict word against the markers, ignoring case
+    pub fn decision_for(&self, word: &str) -> Option<MAGIDecision> {
+        let word = word.trim();
+        if self.positive.iter().any(|token| token.eq_ignore_ascii_case(word)) {
+            Some(MAGIDecision::POSITIVE)
+        } else if self.negative.iter().any(|token| token.eq_ignore_ascii_case(word)) {
+            Some(MAGIDecision::NEGATIVE)
+        } else {
+            None
+        }
+    }
+}
+
+/// An issue a reviewer tagged with a category and a severity on its own
+/// line, e.g. `[SECURITY][CRITICAL] Query built from user input`. Both tags
+/// are uppercased.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaggedIssue {
+    pub category: String,
+    pub severity: String,
+    pub text: String,
+}
+
+/// Every tagged issue in the content, in order
+pub fn tagged_issues(content: &str) -> Vec<TaggedIssue> {
+    content.lines().filter_map(tagged_issue).collect()
+}
+
+// `[CATEGORY][SEVERITY] text`, optionally as a list item
+fn tagged_issue(line: &str) -> Option<TaggedIssue> {
+    let line = line.trim().trim_start_matches(|c: char| c == '-' || c == '*').trim();
+    let (category, rest) = line.strip_prefix('[')?.split_once(']')?;
+    let (severity, text) = rest.trim_start().strip_prefix('[')?.split_once(']')?;
+
+    Some(TaggedIssue {
+        category: category.trim().to_uppercase(),
+        severity: severity.trim().to_uppercase(),
+        text: text.trim().to_string(),
+    })
+}
+
+/// Decides an agent's vote from its content
+pub trait VerdictExtractor: Send + Sync {
+    /// The agent's decision and, if it gave one, its confidence in `0.0..=1.0`.
+    /// `None` when no decision can be extracted.
+    fn extract(&self, agent: &str, content: &str) -> Option<(MAGIDecision, Option<f32>)>;
+}
+
+/// The lenient default: the decision `parse_decision` finds, or NEGATIVE
+/// when it finds none
+#[derive(Debug, Clone, Default)]
+pub struct HeuristicExtractor {
+    pub markers: VerdictMarkers,
+}
+
+impl VerdictExtractor for HeuristicExtractor {
+    fn extract(&self, _agent: &str, content: &str) -> Option<(MAGIDecision, Option<f32>)> {
+        let decision = parse_decision(content, &self.markers).unwrap_or(MAGIDecision::NEGATIVE);
+        Some((decision, None))
+    }
+}
+
+/// An agent's decision from its content: the explicit verdict if there is
+/// one (see `explicit_verdict`), otherwise the markers it mentions in prose.
+///
+/// A prose marker only counts as a whole word, in any case, so `POSITIVELY`
+/// is no vote but `Positive` is, and not when one of the few words before it is a negation, as in "this
+/// is not a POSITIVE result". Any remaining NEGATIVE mention makes the
+/// decision NEGATIVE; a POSITIVE mention alone makes it POSITIVE. `None`
+/// when neither is found.
+pub fn parse_decision(content: &str, markers: &VerdictMarkers) -> Option<MAGIDecision> {
+    if let Some(decision) = explicit_verdict(content, markers) {
+        return Some(decision);
+    }
+
+    let words: Vec<&str> = content
+        .split(|c: char| !(c.is_alphanumeric() || c == '\'' || c == '_'))
+        .filter(|word| !word.is_empty())
+        .collect();
+    let mut positive = false;
+    for (index, word) in words.iter().enumerate() {
+        let Some(decision) = markers.decision_for(word) else {
+            continue;
+        };
+        let negated = words[index.saturating_sub(NEGATION_WINDOW)..index]
+            .iter()
+            .any(|before| NEGATIONS.iter().any(|negation| negation.eq_ignore_ascii_case(before)));
+        match (decision, negated) {
+            (MAGIDecision::NEGATIVE, false) => return Some(MAGIDecision::NEGATIVE),
+            (MAGIDecision::POSITIVE, false) => positive = true,
+            _ => {}
+        }
+    }
+    positive.then_some(MAGIDecision::POSITIVE)
+}
+
+/// Only explicit verdicts count (see `explicit_verdict`); content without one
+/// yields `None`. A `confidence` number in a JSON verdict is passed through.
+#[derive(Debug, Clone, Default)]
+pub struct JsonVerdictExtractor {
+    pub markers: VerdictMarkers,
+}
+
+impl VerdictExtractor for JsonVerdictExtractor {
+    fn extract(&self, _agent: &str, content: &str) -> Option<(MAGIDecision, Option<f32>)> {
+        let decision = explicit_verdict(content, &self.markers)?;
+        Some((decision, json_confidence(content)))
+    }
+}
+
+// `confidence` from a JSON verdict (the whole content or its last JSON line)
+fn json_confidence(content: &str) -> Option<f32> {
+    std::iter::once(content.trim())
+        .chain(content.lines().rev().map(str::trim))
+        .filter(|text| text.starts_with('{'))
+        .find_map(|text| {
+            let value: Value = serde_json::from_str(text).ok()?;
+            value.get("confidence").and_then(Value::as_f64)
+        })
+        .map(|confidence| confidence.clamp(0.0, 1.0) as f32)
+}
+
+/// Find a machine-readable verdict in an agent's content.
+///
+/// Accepts either a JSON object with a `decision`/`verdict` field (the whole
+/// content, or its last non-empty line) or a `DECISION: POSITIVE` /
+/// `VERDICT: NEGATIVE` line, where the value is one of the markers. The last
+/// verdict wins. Returns `None` when the content has no explicit verdict;
+/// incidental mentions of a marker in prose are not verdicts.
+pub fn explicit_verdict(content: &str, markers: &VerdictMarkers) -> Option<MAGIDecision> {
+    // Whole content as a JSON verdict
+    if let Some(decision) = json_verdict(content.trim(), markers) {
+        return Some(decision);
+    }
+
+    // Otherwise scan lines from the end for a JSON or `KEY: VALUE` verdict
+    content
+        .lines()
+        .rev()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .find_map(|line| json_verdict(line, markers).or_else(|| line_verdict(line, markers)))
+}
+
+fn json_verdict(text: &str, markers: &VerdictMarkers) -> Option<MAGIDecision> {
+    if !text.starts_with('{') {
+        return None;
+    }
+
+    let value: Value = serde_json::from_str(text).ok()?;
+    VERDICT_KEYS
+        .iter()
+        .find_map(|key| value.get(*key).and_then(Value::as_str))
+        .and_then(|word| markers.decision_for(word))
+}
+
+fn line_verdict(line: &str, markers: &VerdictMarkers) -> Option<MAGIDecision> {
+    // Tolerate markdown emphasis around the line, e.g. `**DECISION:** POSITIVE`
+    let line = line.trim_matches(|c: char| c == '*' || c == '_' || c == '`');
+    let (key, value) = line.split_once(':')?;
+    let key = key.trim_matches(|c: char| c == '*' || c == '_' || c.is_whitespace());
+
+    if !VERDICT_KEYS.iter().any(|k| key.eq_ignore_ascii_case(k)) {
+        return None;
+    }
+
+    markers.decision_for(value.trim_matches(|c: char| c == '*' || c == '_' || c == '`' || c == '.' || c.is_whitespace()))
+}
+
+/// Pick the sentences of an approval that state the verdict or the reasons
+/// for it, in order and at most `limit` of them.
+///
+/// A sentence counts as a verdict if it is an explicit verdict (see
+/// `explicit_verdict`) or mentions a positive marker or approval, and as a
+/// reason if it contains a phrase like "because" or "since".
+pub fn approval_evidence(content: &str, markers: &VerdictMarkers, limit: usize) -> Vec<String> {
+    let mut evidence: Vec<String> = Vec::new();
+
+    for sentence in sentences(content) {
+        if evidence.len() >= limit {
+            break;
+        }
+
+        let lower = sentence.to_lowercase();
+        let is_verdict = json_verdict(sentence, markers).is_some()
+            || line_verdict(sentence, markers).is_some()
+            || markers.mentions_positive(sentence)
+            || lower.contains("approve");
+        let is_reason = REASON_MARKERS.iter().any(|marker| lower.contains(marker));
+
+        if (is_verdict || is_reason) && !evidence.iter().any(|kept| kept == sentence) {
+            evidence.push(truncate(sentence));
+        }
+    }
+
+    evidence
+}
+
+// Split content into trimmed, non-empty sentences. Lines are split at
+// sentence-ending punctuation followed by whitespace.
+fn sentences(content: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+
+    for line in content.lines() {
+        let line = line.trim().trim_start_matches(|c: char| c == '-' || c == '*' || c == '#').trim();
+        let mut start = 0;
+        let mut chars = line.char_indices().peekable();
+
+        while let Some((index, c)) = chars.next() {
+            let at_boundary = matches!(c, '.' | '!' | '?')
+                && chars.peek().map_or(true, |(_, next)| next.is_whitespace());
+            if at_boundary {
+                let end = index + c.len_utf8();
+                sentences.push(line[start..end].trim());
+                start = end;
+            }
+        }
+        sentences.push(line[start..].trim());
+    }
+
+    sentences.retain(|sentence| !sentence.is_empty());
+    sentences
+}
+
+fn truncate(sentence: &str) -> String {
+    if sentence.chars().count() <= MAX_EVIDENCE_LEN {
+        return sentence.to_string();
+    }
+
+    let mut truncated: String = sentence.chars().take(MAX_EVIDENCE_LEN).collect();
+    truncated.push('…');
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approve_reject() -> VerdictMarkers {
+        VerdictMarkers {
+            positive: vec!["APPROVE".to_string()],
+            negative: vec!["REJECT".to_string()],
+        }
+    }
+
+    #[test]
+    fn strict_extraction_needs_an_explicit_verdict() {
+        let extractor = JsonVerdictExtractor::default();
+
+        assert_eq!(extractor.extract("melchior", "Looks POSITIVE to me"), None);
+        assert_eq!(
+            extractor.extract("melchior", "Fine.\nDECISION: POSITIVE"),
+            Some((MAGIDecision::POSITIVE, None))
+        );
+        assert_eq!(
+            extractor.extract("melchior", r#"{"decision": "negative", "confidence": 0.8}"#),
+            Some((MAGIDecision::NEGATIVE, Some(0.8)))
+        );
+    }
+
+    #[test]
+    fn approval_evidence_picks_verdict_and_reason_sentences_across_paragraphs() {
+        let content = "The function is short. It reads well.\n\n\
+                       I approve because the edge cases are handled! Naming is fine.\n\n\
+                       - Overall POSITIVE since tests cover it.\n\nDECISION: POSITIVE";
+        let markers = VerdictMarkers::default();
+
+        assert_eq!(
+            approval_evidence(content, &markers, 5),
+            [
+                "I approve because the edge cases are handled!",
+                "Overall POSITIVE since tests cover it.",
+                "DECISION: POSITIVE",
+            ]
+        );
+        assert_eq!(approval_evidence(content, &markers, 1), ["I approve because the edge cases are handled!"]);
+        assert!(approval_evidence(content, &markers, 0).is_empty());
+    }
+
+    #[test]
+    fn long_evidence_is_truncated() {
+        let sentence = format!("POSITIVE because {}", "x".repeat(300));
+
+        let evidence = approval_evidence(&sentence, &VerdictMarkers::default(), 3);
+
+        assert_eq!(evidence[0].chars().count(), MAX_EVIDENCE_LEN + 1);
+        assert!(evidence[0].ends_with('…'));
+    }
+
+    #[test]
+    fn custom_markers_replace_the_default_ones() {
+        let markers = approve_reject();
+
+        assert_eq!(parse_decision("I APPROVE of this", &markers), Some(MAGIDecision::POSITIVE));
+        assert_eq!(parse_decision("APPROVE, but REJECT the test", &markers), Some(MAGIDecision::NEGATIVE));
+        assert_eq!(parse_decision("Looks fine.\nDECISION: approve", &markers), Some(MAGIDecision::POSITIVE));
+        assert_eq!(parse_decision("POSITIVE", &markers), None);
+        assert!(markers.mentions_positive("APPROVE"));
+        assert!(!markers.mentions_positive("POSITIVE"));
+    }
+
+    #[test]
+    fn prose_markers_are_whole_words_in_any_case_and_not_negated() {
+        let markers = VerdictMarkers::default();
+
+        assert_eq!(parse_decision("This is NOT a POSITIVE result", &markers), None);
+        assert_eq!(parse_decision("POSITIVELY not ready", &markers), None);
+        assert_eq!(parse_decision("Looks Positive overall", &markers), Some(MAGIDecision::POSITIVE));
+        assert_eq!(parse_decision("positive start, negative on errors", &markers), Some(MAGIDecision::NEGATIVE));
+        assert_eq!(
+            parse_decision("POSITIVE on style.\nNEGATIVE on safety until fixed.\nDECISION: POSITIVE", &markers),
+            Some(MAGIDecision::POSITIVE)
+        );
+        assert_eq!(parse_decision("Not NEGATIVE, just POSITIVE\ndecision: negative", &markers), Some(MAGIDecision::NEGATIVE));
+    }
+
+    #[test]
+    fn marker_sets_must_be_set_and_disjoint() {
+        assert!(approve_reject().validate().is_ok());
+
+        let overlapping = VerdictMarkers {
+            positive: vec!["APPROVE".to_string(), "OK".to_string()],
+            negative: vec!["REJECT".to_string(), "ok".to_string()],
+        };
+        assert!(overlapping.validate().unwrap_err().contains("OK"));
+
+        let empty = VerdictMarkers { negative: Vec::new(), ..approve_reject() };
+        assert!(empty.validate().is_err());
+    }
+
+    #[test]
+    fn the_heuristic_extractor_reads_prose_and_defaults_to_negative() {
+        let extractor = HeuristicExtractor::default();
+
+        assert_eq!(extractor.extract("melchior", "Overall POSITIVE"), Some((MAGIDecision::POSITIVE, None)));
+        assert_eq!(extractor.extract("melchior", "POSITIVE style, NEGATIVE logic"), Some((MAGIDecision::NEGATIVE, None)));
+        assert_eq!(extractor.extract("melchior", "No verdict here"), Some((MAGIDecision::NEGATIVE, None)));
+    }
+}